@@ -0,0 +1,100 @@
+use std::path::PathBuf;
+
+use clap::{App, AppSettings, Arg, ArgMatches};
+
+use commons::types::Int;
+
+lazy_static! {
+    pub static ref ARGUMENTS: Arguments = Arguments::new();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Table,
+    Csv,
+    Json,
+}
+
+#[derive(Debug)]
+pub struct Arguments {
+    pub directory: PathBuf,
+    pub number_of_nodes: Option<Int>,
+    pub format: Format,
+}
+
+impl Arguments {
+    fn new() -> Arguments {
+        let matches = get_matches();
+
+        Arguments {
+            directory: directory_from_matches(&matches),
+            number_of_nodes: number_of_nodes_from_matches(&matches),
+            format: format_from_matches(&matches),
+        }
+    }
+}
+
+fn get_matches() -> ArgMatches<'static> {
+    App::new("Distributed-SWMR-register: Aggregator")
+        .setting(AppSettings::DisableVersion)
+        .setting(AppSettings::VersionlessSubcommands)
+        .about("Combines the result files of all the nodes of a run, checks that they are sound and prints the throughput, messages per operation and latencies of the cluster.")
+        .arg(directory_argument())
+        .arg(number_of_nodes_argument())
+        .arg(format_argument())
+        .get_matches()
+}
+
+fn directory_argument() -> Arg<'static, 'static> {
+    Arg::with_name("directory")
+        .default_value(".")
+        .help("The directory with the result files of the nodes, named like node000001.eval.")
+}
+
+fn directory_from_matches(matches: &ArgMatches<'static>) -> PathBuf {
+    PathBuf::from(
+        matches
+            .value_of("directory")
+            .expect("Directory arg not existing."),
+    )
+}
+
+fn number_of_nodes_argument() -> Arg<'static, 'static> {
+    Arg::with_name("number-of-nodes")
+        .short("n")
+        .long("number-of-nodes")
+        .takes_value(true)
+        .help("The number of nodes of the run. If not given, the highest node id of the result files is used, so a missing result file of the last node is not noticed.")
+}
+
+fn number_of_nodes_from_matches(
+    matches: &ArgMatches<'static>,
+) -> Option<Int> {
+    matches.value_of("number-of-nodes").map(|number_of_nodes| {
+        number_of_nodes
+            .parse()
+            .expect("Could not parse the number of nodes.")
+    })
+}
+
+fn format_argument() -> Arg<'static, 'static> {
+    Arg::with_name("format")
+        .short("f")
+        .long("format")
+        .takes_value(true)
+        .possible_values(&["table", "csv", "json"])
+        .default_value("table")
+        .help("How to print the aggregate. csv has a row per node and a last row for the whole cluster.")
+}
+
+fn format_from_matches(matches: &ArgMatches<'static>) -> Format {
+    match matches
+        .value_of("format")
+        .expect("Format arg not existing.")
+    {
+        "table" => Format::Table,
+        "csv" => Format::Csv,
+        "json" => Format::Json,
+        _ => panic!("Unknown format."),
+    }
+}
@@ -0,0 +1,38 @@
+#[macro_use]
+extern crate lazy_static;
+
+mod arguments;
+
+use std::process;
+
+use commons::aggregation;
+
+use crate::arguments::{Format, ARGUMENTS};
+
+fn main() {
+    let run_results =
+        aggregation::run_results_from_directory(&ARGUMENTS.directory);
+    if run_results.is_empty() {
+        eprintln!(
+            "There are no result files in {}",
+            ARGUMENTS.directory.display()
+        );
+        process::exit(1);
+    }
+
+    let number_of_nodes = match ARGUMENTS.number_of_nodes {
+        Some(number_of_nodes) => number_of_nodes,
+        None => *run_results.keys().max().unwrap(),
+    };
+    let aggregate = aggregation::aggregate(&run_results, number_of_nodes);
+
+    match ARGUMENTS.format {
+        Format::Table => print!("{}", aggregate.to_table()),
+        Format::Csv => print!("{}", aggregate.to_csv()),
+        Format::Json => println!("{}", aggregate.to_json()),
+    }
+
+    if !aggregate.is_sound() {
+        process::exit(1);
+    }
+}
@@ -1,26 +1,38 @@
+use std::collections::HashMap;
 use std::fmt::Debug;
-use std::str;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, Weak};
 use std::time::Duration;
 
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
-use commons::types::{NodeId, Timestamp};
+use commons::admin_protocol::QuorumStates;
+use commons::history;
+use commons::histogram::LatencyHistogram;
+use commons::run_result::{
+    Latencies, MessageTypeResult, QuorumAccessResult, RunResult,
+};
+use commons::types::{Int, NodeId, Timestamp};
 
+use crate::failure_detector::PeerHealth;
+use crate::incarnations::{Incarnations, ANY_INCARNATION};
 use crate::mediator::Med;
 use crate::messages::{
-    self, Message, Read1AckMessage, Read1Message, Read2AckMessage,
-    Read2Message, TimestampValueMessage, WriteAckMessage, WriteMessage,
+    self, AbdMessage, Envelope, HeartbeatAckMessage, HeartbeatMessage,
+    Read1AckMessage, Read1Message, Read2AckMessage, Read2Message,
+    Rejection, TimestampValueMessage, WriteAckMessage, WriteMessage,
+    HEARTBEAT_PROTOCOL_VERSION, MIN_SUPPORTED_PROTOCOL_VERSION,
+    PROTOCOL_VERSION,
 };
+use crate::protocol_versions::ProtocolVersions;
 use crate::quorum::Quorum;
+use crate::retransmission::RoundTripTimes;
 //use crate::terminal_output::printlnu;
 
 #[cfg(test)]
 pub mod tests;
 
-const QUORUM_ACCESS_TIMEOUT: Duration = Duration::from_millis(100);
-
 pub struct AbdNode<M, V> {
     mediator: Weak<M>,
 
@@ -32,6 +44,26 @@ pub struct AbdNode<M, V> {
     write_quorum: Quorum,
     read1_quorum: Quorum,
     read2_quorum: Quorum,
+
+    protocol_versions: ProtocolVersions,
+    incarnations: Incarnations,
+    round_trip_times: RoundTripTimes,
+    aborted: AtomicBool,
+}
+
+// An operation that was aborted before a majority had acked it, for
+// example because the node is shutting down. The operation may still
+// have taken effect on some nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Aborted;
+
+// The kind of a quorum access, which says which quorum it uses and
+// under which counters it is counted in the run result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum QuorumKind {
+    Write,
+    Read1,
+    Read2,
 }
 
 pub trait Value:
@@ -53,6 +85,12 @@ impl<V: Value, M: Med> AbdNode<M, V> {
             .upgrade()
             .expect("Error upgrading mediator in AbdNode constructor");
         let number_of_nodes = mediator_upgraded.number_of_nodes();
+        // Seeded with the node id, so that simulations are
+        // deterministic.
+        let round_trip_times = RoundTripTimes::new(
+            mediator_upgraded.retransmission_policy().clone(),
+            mediator_upgraded.node_id() as u64,
+        );
 
         AbdNode {
             mediator: mediator,
@@ -66,6 +104,14 @@ impl<V: Value, M: Med> AbdNode<M, V> {
             write_quorum: Quorum::new(number_of_nodes),
             read1_quorum: Quorum::new(number_of_nodes),
             read2_quorum: Quorum::new(number_of_nodes),
+
+            protocol_versions: ProtocolVersions::new(
+                MIN_SUPPORTED_PROTOCOL_VERSION,
+                PROTOCOL_VERSION,
+            ),
+            incarnations: Incarnations::new(history::now_in_micros()),
+            round_trip_times: round_trip_times,
+            aborted: AtomicBool::new(false),
         }
     }
 
@@ -75,29 +121,83 @@ impl<V: Value, M: Med> AbdNode<M, V> {
             .expect("Error upgrading mediator in AbdNode")
     }
 
+    //
+    // Introspection, for the admin endpoint
+    //
+
+    pub fn incarnation(&self) -> u64 {
+        self.incarnations.own_incarnation()
+    }
+
+    pub fn timestamp_and_value(&self) -> (Timestamp, V) {
+        let timestamp = self.timestamp.lock().unwrap();
+        let value = self.value.lock().unwrap();
+        (*timestamp, value.clone())
+    }
+
+    pub fn quorum_states(&self) -> QuorumStates {
+        QuorumStates {
+            write: self.write_quorum.state(),
+            read1: self.read1_quorum.state(),
+            read2: self.read2_quorum.state(),
+        }
+    }
+
+
+    //
+    // Latencies
+    //
+
+    // None if latencies aren't recorded, so that the time isn't even
+    // read then.
+    fn latency_start(&self) -> Option<Duration> {
+        let mediator = self.mediator();
+        if mediator.record_latencies() {
+            Some(mediator.now())
+        } else {
+            None
+        }
+    }
+
+    fn record_latency(
+        &self,
+        start: Option<Duration>,
+        histogram: impl FnOnce(&mut Latencies) -> &mut LatencyHistogram,
+    ) {
+        if let Some(start) = start {
+            let mediator = self.mediator();
+            let latency = mediator.now().saturating_sub(start);
+            let mut run_result = mediator.run_result();
+            histogram(&mut run_result.latencies).record(latency);
+        }
+    }
+
 
     //
     // Write client-side
     //
 
-    pub fn write(&self, value: V) {
+    pub fn write(&self, value: V) -> Result<(), Aborted> {
         if cfg!(debug_assertions) {
             assert!(self.write_quorum.is_idle());
         }
 
-        self.write_inner(value);
+        let start = self.latency_start();
+        self.write_inner(value)?;
 
         if cfg!(debug_assertions) {
             assert!(self.write_quorum.is_idle());
         }
 
         self.mediator().run_result().write_ops += 1;
+        self.record_latency(start, |latencies| &mut latencies.write);
+        Ok(())
     }
 
-    fn write_inner(&self, new_value: V) {
+    fn write_inner(&self, new_value: V) -> Result<(), Aborted> {
         self.update_local_timestamp_and_value(new_value);
         let write_message = self.construct_write_message();
-        self.quorum_access(&write_message, &self.write_quorum);
+        self.quorum_access(write_message.into(), QuorumKind::Write)
     }
 
     fn update_local_timestamp_and_value(&self, new_value: V) {
@@ -119,22 +219,196 @@ impl<V: Value, M: Med> AbdNode<M, V> {
         }
     }
 
-    fn quorum_access<Msg: Message>(&self, message: &Msg, quorum: &Quorum) {
-        let mut accessing = quorum.accessing().lock().unwrap();
-        *accessing = true;
+    // The message is sent to all nodes, and then sent again only to
+    // the nodes that haven't acked it when their retransmission
+    // timeouts expire. With fastest_quorum_first, the message is
+    // first sent only to the fastest majority, and the other nodes are
+    // scheduled to get it at the first retransmission timeout. The
+    // rounds and retransmissions are counted as they happen, so that
+    // an access that waits long for a majority shows in the metrics.
+    // The acks are counted in ack_received().
+    fn quorum_access(
+        &self,
+        message: AbdMessage<V>,
+        kind: QuorumKind,
+    ) -> Result<(), Aborted> {
+        let quorum = self.quorum(kind);
+        *quorum.accessing().lock().unwrap() = true;
+        // Checked after accessing is set, so that an abort either
+        // sees the access, or the access sees the abort.
+        if self.aborted.load(Ordering::SeqCst) {
+            quorum.abort();
+            quorum.take_aborted();
+            return Err(Aborted);
+        }
+        quorum.transmissions().clear();
+        let start = self.mediator().now();
+
+        let node_ids = self.node_ids_fastest_first();
+        let number_of_first_receivers =
+            if self.mediator().fastest_quorum_first() {
+                quorum.number_of_nodes_in_a_majority() as usize
+            } else {
+                node_ids.len()
+            };
+        let (first_receivers, later_receivers) =
+            node_ids.split_at(number_of_first_receivers);
+
+        let mut encoded_messages = HashMap::new();
+        self.send_message_to_nodes(
+            &message,
+            first_receivers,
+            quorum,
+            &mut encoded_messages,
+        );
+        self.count_quorum_access(kind, |accesses_of_kind, result| {
+            *accesses_of_kind += 1;
+            result.accesses += 1;
+            result.rounds += 1;
+        });
+        if let Some(deadline) = quorum.transmissions().next_deadline() {
+            for &node_id in later_receivers {
+                quorum.transmissions().scheduled(node_id, deadline);
+            }
+        }
+
+        while quorum.is_accessing() {
+            let mediator = self.mediator();
+            let timeout = match quorum.transmissions().next_deadline() {
+                Some(deadline) => deadline
+                    .checked_sub(mediator.now())
+                    .unwrap_or(Duration::from_secs(0)),
+                None => self.round_trip_times.policy().max_timeout,
+            };
+
+            let timed_out = mediator.wait_for_majority(quorum, timeout);
+            if timed_out {
+                let due_node_ids =
+                    quorum.transmissions().due(mediator.now());
+                if due_node_ids.is_empty() {
+                    continue;
+                }
+                let retransmissions = self.send_message_to_nodes(
+                    &message,
+                    &due_node_ids,
+                    quorum,
+                    &mut encoded_messages,
+                );
+                self.count_quorum_access(kind, |_, result| {
+                    result.rounds += 1;
+                    result.retransmissions += retransmissions;
+                });
+            }
+        }
+
+        if quorum.take_aborted() {
+            self.count_quorum_access(kind, |_, result| {
+                result.aborted += 1
+            });
+            Err(Aborted)
+        } else {
+            let time_to_majority =
+                self.mediator().now().saturating_sub(start);
+            self.count_quorum_access(kind, |_, result| {
+                result.time_to_majority.record(time_to_majority)
+            });
+            Ok(())
+        }
+    }
+
+    fn quorum(&self, kind: QuorumKind) -> &Quorum {
+        match kind {
+            QuorumKind::Write => &self.write_quorum,
+            QuorumKind::Read1 => &self.read1_quorum,
+            QuorumKind::Read2 => &self.read2_quorum,
+        }
+    }
 
-        let json = self.jsonify_message(message);
-        self.broadcast_json(&json);
+    // Counts in the run result of the kind, with one lock of it. The
+    // first counter is write_quorum_accesses for the write quorum, and
+    // read_quorum_accesses for both read quorums.
+    fn count_quorum_access(
+        &self,
+        kind: QuorumKind,
+        count: impl FnOnce(&mut Int, &mut QuorumAccessResult),
+    ) {
+        let mediator = self.mediator();
+        let mut run_result = mediator.run_result();
+        let run_result = &mut *run_result;
+        let quorum_accesses = &mut run_result.quorum_accesses;
+        match kind {
+            QuorumKind::Write => count(
+                &mut run_result.write_quorum_accesses,
+                &mut quorum_accesses.write,
+            ),
+            QuorumKind::Read1 => count(
+                &mut run_result.read_quorum_accesses,
+                &mut quorum_accesses.read1,
+            ),
+            QuorumKind::Read2 => count(
+                &mut run_result.read_quorum_accesses,
+                &mut quorum_accesses.read2,
+            ),
+        }
+    }
 
-        while *accessing {
-            let result = quorum
-                .majority_reached()
-                .wait_timeout(accessing, QUORUM_ACCESS_TIMEOUT)
-                .unwrap();
-            accessing = result.0;
-            if result.1.timed_out() {
-                self.broadcast_json(&json);
+    // Aborts the ongoing quorum accesses, and all later ones. Used
+    // when the node shuts down.
+    pub fn abort(&self) {
+        self.aborted.store(true, Ordering::SeqCst);
+        self.write_quorum.abort();
+        self.read1_quorum.abort();
+        self.read2_quorum.abort();
+    }
+
+    // Sorted by id among nodes with the same health, so that
+    // simulations are deterministic.
+    fn node_ids_fastest_first(&self) -> Vec<NodeId> {
+        let mediator = self.mediator();
+        let mut node_ids: Vec<(PeerHealth, NodeId)> = mediator
+            .node_ids()
+            .iter()
+            .map(|&node_id| (mediator.peer_health(node_id), node_id))
+            .collect();
+        node_ids.sort_by(
+            |(health, node_id), (other_health, other_node_id)| {
+                health
+                    .cmp_fastest_first(other_health)
+                    .then(node_id.cmp(other_node_id))
+            },
+        );
+        node_ids.into_iter().map(|(_, node_id)| node_id).collect()
+    }
+
+    // Acks of the latest quorum access give round trip times, also
+    // when they arrive after a majority has acked. Accessing is
+    // whether the access still waited for a majority when the ack
+    // arrived.
+    fn ack_received(
+        &self,
+        kind: QuorumKind,
+        sender: NodeId,
+        accessing: bool,
+    ) {
+        let quorum = self.quorum(kind);
+        if let Some(transmission) = quorum.transmissions().acked(sender) {
+            let mediator = self.mediator();
+            if transmission.attempts == 1 {
+                let round_trip_time =
+                    mediator.now() - transmission.first_sent;
+                self.round_trip_times.sample(sender, round_trip_time);
+            } else {
+                self.round_trip_times
+                    .backed_off(sender, transmission.attempts);
             }
+
+            self.count_quorum_access(kind, |_, result| {
+                if accessing {
+                    result.acks_before_majority += 1;
+                } else {
+                    result.acks_after_majority += 1;
+                }
+            });
         }
     }
 
@@ -147,7 +421,10 @@ impl<V: Value, M: Med> AbdNode<M, V> {
         self.update_local_timestamp_and_value_from_message(write_message);
         let write_ack_message =
             self.construct_write_ack_message(write_message.timestamp);
-        self.send_message_to(&write_ack_message, write_message.sender);
+        self.send_message_to(
+            &write_ack_message.into(),
+            write_message.sender,
+        );
     }
 
     fn update_local_timestamp_and_value_from_message<
@@ -182,6 +459,13 @@ impl<V: Value, M: Med> AbdNode<M, V> {
         let timestamp = self.timestamp.lock().unwrap();
         let accessing = self.write_quorum.accessing().lock().unwrap().clone();
 
+        if write_ack_message.timestamp == *timestamp {
+            self.ack_received(
+                QuorumKind::Write,
+                write_ack_message.sender,
+                accessing,
+            );
+        }
         if write_ack_message.timestamp == *timestamp && accessing {
             self.write_quorum
                 .insert_node_to_acking_nodes(write_ack_message.sender);
@@ -194,20 +478,27 @@ impl<V: Value, M: Med> AbdNode<M, V> {
     // Read client-side
     //
 
-    pub fn read(&self) -> V {
+    // Also returns the timestamp of the value, which is its version.
+    pub fn read_versioned(&self) -> Result<(Timestamp, V), Aborted> {
         if cfg!(debug_assertions) {
             assert!(self.read1_quorum.is_idle());
             assert!(self.read2_quorum.is_idle());
         }
         
-        self.read_phase1();
+        let start = self.latency_start();
+        self.read_phase1()?;
+        self.record_latency(start, |latencies| &mut latencies.read_phase1);
 
         if cfg!(debug_assertions) {
             assert!(self.read1_quorum.is_idle());
             assert!(self.read2_quorum.is_idle());
         }
         
-        self.read_phase2();
+        let phase2_start = self.latency_start();
+        self.read_phase2()?;
+        self.record_latency(phase2_start, |latencies| {
+            &mut latencies.read_phase2
+        });
 
         if cfg!(debug_assertions) {
             assert!(self.read1_quorum.is_idle());
@@ -215,13 +506,16 @@ impl<V: Value, M: Med> AbdNode<M, V> {
         }
 
         self.mediator().run_result().read_ops += 1;
+        self.record_latency(start, |latencies| &mut latencies.read);
 
-        self.value.lock().unwrap().clone()
+        let timestamp = self.timestamp.lock().unwrap();
+        let value = self.value.lock().unwrap();
+        Ok((*timestamp, value.clone()))
     }
 
-    fn read_phase1(&self) {
+    fn read_phase1(&self) -> Result<(), Aborted> {
         let read1_message = self.construct_read1_message();
-        self.quorum_access(&read1_message, &self.read1_quorum);
+        self.quorum_access(read1_message.into(), QuorumKind::Read1)
     }
 
     fn construct_read1_message(&self) -> Read1Message {
@@ -235,9 +529,9 @@ impl<V: Value, M: Med> AbdNode<M, V> {
         }
     }
 
-    fn read_phase2(&self) {
+    fn read_phase2(&self) -> Result<(), Aborted> {
         let read2_message = self.construct_read2_message();
-        self.quorum_access(&read2_message, &self.read2_quorum);
+        self.quorum_access(read2_message.into(), QuorumKind::Read2)
     }
 
     fn construct_read2_message(&self) -> Read2Message<V> {
@@ -263,7 +557,10 @@ impl<V: Value, M: Med> AbdNode<M, V> {
     fn receive_read1_message(&self, read1_message: &Read1Message) {
         let read1_ack_message =
             self.construct_read1_ack_message(read1_message.sequence_number);
-        self.send_message_to(&read1_ack_message, read1_message.sender);
+        self.send_message_to(
+            &read1_ack_message.into(),
+            read1_message.sender,
+        );
     }
 
     fn construct_read1_ack_message(
@@ -288,6 +585,13 @@ impl<V: Value, M: Med> AbdNode<M, V> {
         let sequence_number = self.read1_sequence_number.lock().unwrap();
         let accessing = self.read1_quorum.accessing().lock().unwrap().clone();
 
+        if read1_ack_message.sequence_number == *sequence_number {
+            self.ack_received(
+                QuorumKind::Read1,
+                read1_ack_message.sender,
+                accessing,
+            );
+        }
         if read1_ack_message.sequence_number == *sequence_number && accessing {
             self.update_local_timestamp_and_value_from_message(
                 read1_ack_message,
@@ -302,7 +606,10 @@ impl<V: Value, M: Med> AbdNode<M, V> {
         self.update_local_timestamp_and_value_from_message(read2_message);
         let read2_ack_message =
             self.construct_read2_ack_message(read2_message.sequence_number);
-        self.send_message_to(&read2_ack_message, read2_message.sender);
+        self.send_message_to(
+            &read2_ack_message.into(),
+            read2_message.sender,
+        );
     }
 
     fn construct_read2_ack_message(
@@ -322,6 +629,13 @@ impl<V: Value, M: Med> AbdNode<M, V> {
         let sequence_number = self.read2_sequence_number.lock().unwrap();
         let accessing = self.read2_quorum.accessing().lock().unwrap().clone();
 
+        if read2_ack_message.sequence_number == *sequence_number {
+            self.ack_received(
+                QuorumKind::Read2,
+                read2_ack_message.sender,
+                accessing,
+            );
+        }
         if read2_ack_message.sequence_number == *sequence_number && accessing {
             self.read2_quorum
                 .insert_node_to_acking_nodes(read2_ack_message.sender);
@@ -331,151 +645,312 @@ impl<V: Value, M: Med> AbdNode<M, V> {
 
 
     //
-    // Message sending
+    // Heartbeats
     //
 
-    fn jsonify_message<Msg: Message>(&self, message: &Msg) -> String {
-        serde_json::to_string(message)
-            .expect("Could not serialize a message")
-    }
-
-    fn broadcast_json(&self, json: &str) {
-        for &node_id in self.mediator().node_ids() {
-            self.send_json_to(json, node_id);
+    // The heartbeats are sent to all incarnations of all nodes, like
+    // the requests of quorum accesses. Also this node gets one, so
+    // that it has a round trip time to itself, and isn't suspected.
+    //
+    // Nodes of an older protocol version get no heartbeats, since they
+    // can't decode them. A node that hasn't been heard from yet gets
+    // them in the own version of this node: a node of an older version
+    // rejects them as incompatible, and a node of a newer version
+    // answers, so that the nodes learn each other's versions.
+    pub fn send_heartbeats(&self) {
+        let mediator = self.mediator();
+        let heartbeat_message: AbdMessage<V> = HeartbeatMessage {
+            sender: mediator.node_id(),
+            sent_at: mediator.now().as_micros() as u64,
+        }
+        .into();
+
+        for &node_id in mediator.node_ids() {
+            let negotiated_version =
+                self.protocol_versions.negotiated_version(node_id);
+            let protocol_version = match negotiated_version {
+                Some(version) if version < HEARTBEAT_PROTOCOL_VERSION => {
+                    continue
+                }
+                Some(version) => version,
+                None => self.protocol_versions.own_version(),
+            };
+            let bytes = self.encode_message(
+                &heartbeat_message,
+                protocol_version,
+                ANY_INCARNATION,
+            );
+            self.send_bytes_to(&heartbeat_message, &bytes, node_id);
         }
     }
 
-    fn send_message_to<Msg: Message>(
+    fn receive_heartbeat_message(
         &self,
-        message: &Msg,
-        receiver_id: NodeId,
+        heartbeat_message: &HeartbeatMessage,
     ) {
-        let json = self.jsonify_message(message);
-        self.send_json_to(&json, receiver_id);
+        let heartbeat_ack_message = HeartbeatAckMessage {
+            sender: self.mediator().node_id(),
+            sent_at: heartbeat_message.sent_at,
+        };
+        self.send_message_to(
+            &heartbeat_ack_message.into(),
+            heartbeat_message.sender,
+        );
     }
 
-    fn send_json_to(&self, json: &str, receiver_id: NodeId) {
-        self.mediator().send_json_to(&json, receiver_id);
-
-        if messages::json_is_write_message(&json) {
-            self.mediator().run_result().write_message.sent += 1;
-        } else if messages::json_is_write_ack_message(&json) {
-            self.mediator().run_result().write_ack_message.sent += 1;
-        } else if messages::json_is_read1_message(json) {
-            self.mediator().run_result().read1_message.sent += 1;
-        } else if messages::json_is_read1_ack_message(json) {
-            self.mediator().run_result().read1_ack_message.sent += 1;
-        } else if messages::json_is_read2_message(json) {
-            self.mediator().run_result().read2_message.sent += 1;
-        } else if messages::json_is_read2_ack_message(json) {
-            self.mediator().run_result().read2_ack_message.sent += 1;
-        }
+    fn receive_heartbeat_ack_message(
+        &self,
+        heartbeat_ack_message: &HeartbeatAckMessage,
+    ) {
+        let mediator = self.mediator();
+        let sent_at = Duration::from_micros(heartbeat_ack_message.sent_at);
+        let round_trip_time = mediator.now().checked_sub(sent_at);
+        mediator.heard_from(heartbeat_ack_message.sender, round_trip_time);
     }
 
 
     //
-    // json message reception
+    // Message sending
     //
 
-    pub fn json_received(&self, json: &str) {
-        self.try_receive_write_message_json(json);
-        self.try_receive_write_ack_message_json(json);
-        self.try_receive_read1_message_json(json);
-        self.try_receive_read1_ack_message_json(json);
-        self.try_receive_read2_message_json(json);
-        self.try_receive_read2_ack_message_json(json);
+    // Only version 1 exists so far. When the messages change in a new
+    // version, the older layout is encoded here for older versions.
+    fn encode_message(
+        &self,
+        message: &AbdMessage<V>,
+        protocol_version: u32,
+        receiver_incarnation: u64,
+    ) -> Vec<u8> {
+        let mediator = self.mediator();
+        let envelope = Envelope {
+            protocol_version: protocol_version,
+            max_protocol_version: self.protocol_versions.own_version(),
+            cluster_id: mediator.cluster_id().to_string(),
+            sender: message.sender(),
+            incarnation: self.incarnations.own_incarnation(),
+            receiver_incarnation: receiver_incarnation,
+            message: message,
+        };
+        let mut bytes = mediator.wire_format().encode(&envelope);
+        if let Some(keys) = mediator.keys() {
+            keys.append_tag(message.sender(), &mut bytes);
+        }
+        bytes
     }
 
-    fn try_receive_write_message_json(&self, json: &str) {
-        if messages::json_is_write_message(json) {
-            if let Ok(write_message) = serde_json::from_str(&json) {
-                self.receive_write_message(&write_message);
-
-                self.mediator().run_result().write_message.received += 1;
-                self.mediator()
-                    .run_result()
-                    .write_message
-                    .nodes_received_from
-                    .insert(write_message.sender);
+    // The message is encoded once per protocol version, and the
+    // encoded messages are kept for the retransmissions. Each send is
+    // recorded before the bytes are sent, so that an ack can't arrive
+    // before it. Returns the number of nodes the message was sent to
+    // again.
+    fn send_message_to_nodes(
+        &self,
+        message: &AbdMessage<V>,
+        node_ids: &[NodeId],
+        quorum: &Quorum,
+        encoded_messages: &mut HashMap<u32, Vec<u8>>,
+    ) -> Int {
+        let now = self.mediator().now();
+        let mut retransmissions = 0;
+        for &node_id in node_ids {
+            let attempts = quorum.transmissions().attempts(node_id) + 1;
+            if attempts > 1 {
+                retransmissions += 1;
             }
+            quorum.transmissions().sent(
+                node_id,
+                now,
+                self.round_trip_times.timeout(node_id, attempts),
+            );
+
+            let protocol_version =
+                self.protocol_versions.version_for(node_id);
+            let bytes = encoded_messages
+                .entry(protocol_version)
+                .or_insert_with(|| {
+                    self.encode_message(
+                        message,
+                        protocol_version,
+                        ANY_INCARNATION,
+                    )
+                });
+            self.send_bytes_to(message, bytes, node_id);
         }
+        retransmissions
     }
 
-    fn try_receive_write_ack_message_json(&self, json: &str) {
-        if messages::json_is_write_ack_message(json) {
-            if let Ok(write_ack_message) = serde_json::from_str(&json) {
-                self.receive_write_ack_message(&write_ack_message);
+    fn send_message_to(
+        &self,
+        message: &AbdMessage<V>,
+        receiver_id: NodeId,
+    ) {
+        let protocol_version =
+            self.protocol_versions.version_for(receiver_id);
+        let bytes = self.encode_message(
+            message,
+            protocol_version,
+            self.incarnations.incarnation_of(receiver_id),
+        );
+        self.send_bytes_to(message, &bytes, receiver_id);
+    }
+
+    // The message is passed along with its bytes, so that the
+    // statistics can be counted without decoding the bytes again.
+    fn send_bytes_to(
+        &self,
+        message: &AbdMessage<V>,
+        bytes: &[u8],
+        receiver_id: NodeId,
+    ) {
+        self.mediator().send_bytes_to(bytes, receiver_id);
 
-                self.mediator().run_result().write_ack_message.received +=
-                    1;
-                self.mediator()
-                    .run_result()
-                    .write_ack_message
-                    .nodes_received_from
-                    .insert(write_ack_message.sender);
-            }
-        }
+        let mediator = self.mediator();
+        let mut run_result = mediator.run_result();
+        message_type_result(&mut run_result, message).sent += 1;
     }
 
-    fn try_receive_read1_message_json(&self, json: &str) {
-        if messages::json_is_read1_message(json) {
-            if let Ok(read1_message) = serde_json::from_str(&json) {
-                self.receive_read1_message(&read1_message);
 
-                self.mediator().run_result().read1_message.received += 1;
-                self.mediator()
+    //
+    // Message reception
+    //
+
+    // Messages that are rejected are counted. Messages with an
+    // incompatible protocol version are also logged, once per sender
+    // and version.
+    pub fn bytes_received(&self, bytes: &[u8]) {
+        let mediator = self.mediator();
+        let opened = messages::open_envelope(
+            bytes,
+            mediator.cluster_id(),
+            mediator.node_ids(),
+            &self.protocol_versions,
+            mediator.keys(),
+            &self.incarnations,
+        );
+
+        match opened {
+            Ok((header, message)) => {
+                self.protocol_versions.peer_version_received(
+                    header.sender,
+                    header.max_protocol_version,
+                );
+                mediator
                     .run_result()
-                    .read1_message
-                    .nodes_received_from
-                    .insert(read1_message.sender);
+                    .peer_protocol_versions
+                    .insert(header.sender, header.max_protocol_version);
+                mediator.heard_from(header.sender, None);
+                self.message_received(&message);
+            }
+            Err(rejection) => {
+                if let Rejection::IncompatibleProtocolVersion {
+                    sender,
+                    protocol_version,
+                } = rejection
+                {
+                    self.log_incompatible_message(
+                        sender,
+                        protocol_version,
+                    );
+                }
+                let mut run_result = mediator.run_result();
+                *rejection_count(&mut run_result, rejection) += 1;
             }
         }
     }
 
-    fn try_receive_read1_ack_message_json(&self, json: &str) {
-        if messages::json_is_read1_ack_message(json) {
-            if let Ok(read1_ack_message) = serde_json::from_str(&json) {
-                self.receive_read1_ack_message(&read1_ack_message);
-
-                self.mediator().run_result().read1_ack_message.received +=
-                    1;
-                self.mediator()
-                    .run_result()
-                    .read1_ack_message
-                    .nodes_received_from
-                    .insert(read1_ack_message.sender);
-            }
+    fn log_incompatible_message(
+        &self,
+        sender: NodeId,
+        protocol_version: u32,
+    ) {
+        if self
+            .protocol_versions
+            .first_incompatible_message(sender, protocol_version)
+        {
+            self.mediator().log(format!(
+                "Rejected a message from node {} with protocol version {}. This node supports versions {} to {}.",
+                sender,
+                protocol_version,
+                MIN_SUPPORTED_PROTOCOL_VERSION,
+                PROTOCOL_VERSION
+            ));
         }
     }
 
-    fn try_receive_read2_message_json(&self, json: &str) {
-        if messages::json_is_read2_message(json) {
-            if let Ok(read2_message) = serde_json::from_str(&json) {
-                self.receive_read2_message(&read2_message);
-
-                self.mediator().run_result().read2_message.received += 1;
-                self.mediator()
-                    .run_result()
-                    .read2_message
-                    .nodes_received_from
-                    .insert(read2_message.sender);
+    fn message_received(&self, message: &AbdMessage<V>) {
+        match message {
+            AbdMessage::Write(write_message) => {
+                self.receive_write_message(write_message)
+            }
+            AbdMessage::WriteAck(write_ack_message) => {
+                self.receive_write_ack_message(write_ack_message)
+            }
+            AbdMessage::Read1(read1_message) => {
+                self.receive_read1_message(read1_message)
+            }
+            AbdMessage::Read1Ack(read1_ack_message) => {
+                self.receive_read1_ack_message(read1_ack_message)
+            }
+            AbdMessage::Read2(read2_message) => {
+                self.receive_read2_message(read2_message)
+            }
+            AbdMessage::Read2Ack(read2_ack_message) => {
+                self.receive_read2_ack_message(read2_ack_message)
+            }
+            AbdMessage::Heartbeat(heartbeat_message) => {
+                self.receive_heartbeat_message(heartbeat_message)
+            }
+            AbdMessage::HeartbeatAck(heartbeat_ack_message) => {
+                self.receive_heartbeat_ack_message(heartbeat_ack_message)
             }
         }
+
+        let mediator = self.mediator();
+        let mut run_result = mediator.run_result();
+        let message_type_result =
+            message_type_result(&mut run_result, message);
+        message_type_result.received += 1;
+        message_type_result
+            .nodes_received_from
+            .insert(message.sender());
     }
+}
 
-    fn try_receive_read2_ack_message_json(&self, json: &str) {
-        if messages::json_is_read2_ack_message(json) {
-            if let Ok(read2_ack_message) = serde_json::from_str(&json) {
-                self.receive_read2_ack_message(&read2_ack_message);
+fn message_type_result<'a, V>(
+    run_result: &'a mut RunResult,
+    message: &AbdMessage<V>,
+) -> &'a mut MessageTypeResult {
+    match message {
+        AbdMessage::Write(_) => &mut run_result.write_message,
+        AbdMessage::WriteAck(_) => &mut run_result.write_ack_message,
+        AbdMessage::Read1(_) => &mut run_result.read1_message,
+        AbdMessage::Read1Ack(_) => &mut run_result.read1_ack_message,
+        AbdMessage::Read2(_) => &mut run_result.read2_message,
+        AbdMessage::Read2Ack(_) => &mut run_result.read2_ack_message,
+        AbdMessage::Heartbeat(_) => &mut run_result.heartbeat_message,
+        AbdMessage::HeartbeatAck(_) => {
+            &mut run_result.heartbeat_ack_message
+        }
+    }
+}
 
-                self.mediator().run_result().read2_ack_message.received +=
-                    1;
-                self.mediator()
-                    .run_result()
-                    .read2_ack_message
-                    .nodes_received_from
-                    .insert(read2_ack_message.sender);
-            }
+fn rejection_count(
+    run_result: &mut RunResult,
+    rejection: Rejection,
+) -> &mut Int {
+    let rejected_messages = &mut run_result.rejected_messages;
+    match rejection {
+        Rejection::Malformed => &mut rejected_messages.malformed,
+        Rejection::IncompatibleProtocolVersion { .. } => {
+            &mut rejected_messages.incompatible_protocol_version
+        }
+        Rejection::OtherCluster => &mut rejected_messages.other_cluster,
+        Rejection::UnknownSender => &mut rejected_messages.unknown_sender,
+        Rejection::Unauthenticated => {
+            &mut rejected_messages.unauthenticated
+        }
+        Rejection::PreviousIncarnation => {
+            &mut rejected_messages.previous_incarnation
         }
     }
 }
@@ -1,23 +1,43 @@
 use std::collections::HashSet;
-use std::str;
+use std::sync::mpsc::{self, Sender};
 use std::sync::{Arc, Mutex, MutexGuard};
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use commons::run_result::RunResult;
-use commons::types::{Int, NodeId};
+use commons::types::{Int, NodeId, Timestamp};
 
-use crate::abd_node::AbdNode;
-use crate::mediator::Mediator;
-use crate::messages::{self, WriteAckMessage, WriteMessage};
+use crate::abd_node::{AbdNode, Aborted};
+use crate::authentication::{self, Keys};
+use crate::failure_detector::{FailureDetector, PeerHealth};
+use crate::incarnations::ANY_INCARNATION;
+use crate::mediator::{Mediator, WriteError};
+use crate::messages::{
+    AbdMessage, Envelope, EnvelopeHeader, HeartbeatAckMessage,
+    WriteAckMessage, WriteMessage, PROTOCOL_VERSION,
+};
+use crate::quorum::Quorum;
 use crate::responsible_cell::ResponsibleCell;
+use crate::retransmission::RetransmissionPolicy;
+use crate::transport::TransportError;
+use crate::wire_format::{self, WireFormat};
 
+mod heartbeats;
+mod message_reception;
+mod read;
 mod start_values;
 mod write;
 
 
-struct MockMediator {
+pub(crate) struct MockMediator {
     node_id: NodeId,
     node_ids: HashSet<NodeId>,
+    keys: Option<Keys>,
+    retransmission_policy: RetransmissionPolicy,
+    start_instant: Instant,
+    fastest_quorum_first: Mutex<bool>,
+    record_latencies: Mutex<bool>,
+    failure_detector: FailureDetector,
 
     run_result: Mutex<RunResult>,
     abd_node: ResponsibleCell<Option<AbdNode<MockMediator, String>>>,
@@ -26,22 +46,51 @@ struct MockMediator {
     write_message_receivers: Mutex<HashSet<NodeId>>,
     sent_write_ack_messages: Mutex<Vec<WriteAckMessage>>,
     write_ack_message_receivers: Mutex<HashSet<NodeId>>,
+    sent_heartbeat_ack_messages: Mutex<Vec<HeartbeatAckMessage>>,
+    sent_protocol_versions: Mutex<Vec<u32>>,
+    sent_receiver_incarnations: Mutex<Vec<u64>>,
+    log: Mutex<Vec<String>>,
+    loopback: Mutex<Option<Sender<Vec<u8>>>>,
 }
 
 impl MockMediator {
-    pub fn new(
+    pub(crate) fn new(
         node_id: NodeId,
         node_ids: HashSet<NodeId>,
     ) -> Arc<MockMediator> {
+        MockMediator::new_with_keys(node_id, node_ids, None)
+    }
+
+    pub fn new_with_keys(
+        node_id: NodeId,
+        node_ids: HashSet<NodeId>,
+        keys: Option<Keys>,
+    ) -> Arc<MockMediator> {
+        let failure_detector = FailureDetector::new(
+            &node_ids,
+            Duration::from_secs(1),
+            Duration::from_secs(0),
+        );
         let mediator = MockMediator {
             node_id: node_id,
             node_ids: node_ids,
+            keys: keys,
+            retransmission_policy: RetransmissionPolicy::new(),
+            start_instant: Instant::now(),
+            fastest_quorum_first: Mutex::new(false),
+            record_latencies: Mutex::new(false),
+            failure_detector: failure_detector,
             run_result: Mutex::new(RunResult::new()),
             abd_node: ResponsibleCell::new(None),
             sent_write_messages: Mutex::new(Vec::new()),
             write_message_receivers: Mutex::new(HashSet::new()),
             sent_write_ack_messages: Mutex::new(Vec::new()),
             write_ack_message_receivers: Mutex::new(HashSet::new()),
+            sent_heartbeat_ack_messages: Mutex::new(Vec::new()),
+            sent_protocol_versions: Mutex::new(Vec::new()),
+            sent_receiver_incarnations: Mutex::new(Vec::new()),
+            log: Mutex::new(Vec::new()),
+            loopback: Mutex::new(None),
         };
         let mediator = Arc::new(mediator);
         let abd_node: AbdNode<MockMediator, String> =
@@ -51,42 +100,90 @@ impl MockMediator {
         mediator
     }
 
+    // A cluster of only this node, whose messages to itself are
+    // received on another thread, so that its operations complete.
+    pub(crate) fn new_with_loopback(node_id: NodeId) -> Arc<MockMediator> {
+        let node_ids = vec![node_id].into_iter().collect();
+        let mediator = MockMediator::new(node_id, node_ids);
+
+        let (loopback_tx, loopback_rx) = mpsc::channel::<Vec<u8>>();
+        let loopback_mediator = Arc::downgrade(&mediator);
+        thread::spawn(move || {
+            for bytes in loopback_rx {
+                match loopback_mediator.upgrade() {
+                    Some(mediator) => mediator.bytes_received(&bytes),
+                    None => return,
+                }
+            }
+        });
+        *mediator.loopback.lock().unwrap() = Some(loopback_tx);
+
+        mediator
+    }
+
     pub fn abd_node(&self) -> &AbdNode<MockMediator, String> {
         self.abd_node.get().as_ref().unwrap()
     }
 }
 
 impl Mediator for MockMediator {
-    fn send_json_to(&self, json: &str, receiver: NodeId) {
-        if messages::json_is_write_message(json) {
-            self.sent_write_messages
-                .lock()
-                .unwrap()
-                .push(
-                    serde_json::from_str(json)
-                        .expect("Could not derserialize a write message."),
-                );
-            self.write_message_receivers
-                .lock()
-                .unwrap()
-                .insert(receiver);
-        } else if messages::json_is_write_ack_message(json) {
-            println!("Hej");
-            self.sent_write_ack_messages
-                .lock()
-                .unwrap()
-                .push(serde_json::from_str(json).expect(
-                    "Could not derserialize a write ack message.",
-                ));
-            self.write_ack_message_receivers
-                .lock()
-                .unwrap()
-                .insert(receiver);
+    fn send_bytes_to(&self, bytes: &[u8], receiver: NodeId) {
+        if receiver == self.node_id {
+            if let Some(loopback_tx) = &*self.loopback.lock().unwrap() {
+                let _ = loopback_tx.send(bytes.to_vec());
+            }
+        }
+        let bytes = match self.keys {
+            Some(_) => authentication::split_tag(bytes).unwrap().0,
+            None => bytes,
+        };
+        let envelope: Envelope<AbdMessage<String>> =
+            wire_format::decode(bytes).expect("Could not decode a message.");
+        self.sent_protocol_versions
+            .lock()
+            .unwrap()
+            .push(envelope.protocol_version);
+        self.sent_receiver_incarnations
+            .lock()
+            .unwrap()
+            .push(envelope.receiver_incarnation);
+        match envelope.message {
+            AbdMessage::Write(write_message) => {
+                self.sent_write_messages
+                    .lock()
+                    .unwrap()
+                    .push(write_message);
+                self.write_message_receivers
+                    .lock()
+                    .unwrap()
+                    .insert(receiver);
+            }
+            AbdMessage::WriteAck(write_ack_message) => {
+                self.sent_write_ack_messages
+                    .lock()
+                    .unwrap()
+                    .push(write_ack_message);
+                self.write_ack_message_receivers
+                    .lock()
+                    .unwrap()
+                    .insert(receiver);
+            }
+            AbdMessage::HeartbeatAck(heartbeat_ack_message) => {
+                self.sent_heartbeat_ack_messages
+                    .lock()
+                    .unwrap()
+                    .push(heartbeat_ack_message);
+            }
+            _ => {}
         }
     }
 
-    fn json_received(&self, json: &str) {
-        self.abd_node().json_received(json);
+    fn bytes_received(&self, bytes: &[u8]) {
+        self.abd_node().bytes_received(bytes);
+    }
+
+    fn transport_error(&self, _error: TransportError) {
+        panic!("Unused");
     }
 
     fn node_id(&self) -> NodeId {
@@ -101,16 +198,65 @@ impl Mediator for MockMediator {
         self.node_ids.len() as Int
     }
 
+    fn cluster_id(&self) -> &str {
+        "test"
+    }
+
+    fn wire_format(&self) -> WireFormat {
+        WireFormat::Json
+    }
+
+    fn keys(&self) -> Option<&Keys> {
+        self.keys.as_ref()
+    }
+
+    fn retransmission_policy(&self) -> &RetransmissionPolicy {
+        &self.retransmission_policy
+    }
+
+    fn fastest_quorum_first(&self) -> bool {
+        *self.fastest_quorum_first.lock().unwrap()
+    }
+
+    fn record_latencies(&self) -> bool {
+        *self.record_latencies.lock().unwrap()
+    }
+
+    fn heard_from(&self, node_id: NodeId, round_trip_time: Option<Duration>) {
+        self.failure_detector
+            .heard_from(node_id, round_trip_time, self.now());
+    }
+
+    fn peer_health(&self, node_id: NodeId) -> PeerHealth {
+        self.failure_detector.health(node_id)
+    }
+
     fn run_result(&self) -> MutexGuard<RunResult> {
         self.run_result.lock().unwrap()
     }
 
-    fn write(&self, message: String) {
-        self.abd_node().write(message);
+    fn wait_for_majority(&self, quorum: &Quorum, timeout: Duration) -> bool {
+        quorum.wait_for_majority(timeout)
     }
 
-    fn read(&self) -> String {
-        panic!("Unused");
+    fn now(&self) -> Duration {
+        self.start_instant.elapsed()
+    }
+
+    fn log(&self, string: String) {
+        self.log.lock().unwrap().push(string);
+    }
+
+    fn write(&self, message: String) -> Result<(), WriteError> {
+        self.abd_node().write(message).map_err(WriteError::from)
+    }
+
+    fn read(&self) -> Result<String, Aborted> {
+        self.read_versioned().map(|(_, value)| value)
+    }
+
+    fn read_versioned(&self) -> Result<(Timestamp, String), Aborted> {
+        self.abd_node().read_versioned()
     }
 }
 
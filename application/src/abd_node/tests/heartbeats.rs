@@ -0,0 +1,84 @@
+
+use super::*;
+
+use crate::failure_detector::PeerStatus;
+use crate::messages::{HeartbeatMessage, HEARTBEAT_PROTOCOL_VERSION};
+
+#[test]
+fn test_that_heartbeats_are_sent_to_all_nodes() {
+    let mediator = create_mediator();
+
+    mediator.abd_node().send_heartbeats();
+
+    assert_eq!(mediator.run_result().heartbeat_message.sent, 4);
+}
+
+#[test]
+fn test_that_heartbeats_are_only_sent_to_nodes_that_can_decode_them() {
+    let mediator = create_mediator();
+    let protocol_versions = &mediator.abd_node().protocol_versions;
+    protocol_versions.peer_version_received(2, HEARTBEAT_PROTOCOL_VERSION);
+    protocol_versions
+        .peer_version_received(3, HEARTBEAT_PROTOCOL_VERSION - 1);
+
+    mediator.abd_node().send_heartbeats();
+
+    assert_eq!(mediator.run_result().heartbeat_message.sent, 3);
+    let mut sent_protocol_versions =
+        mediator.sent_protocol_versions.lock().unwrap().clone();
+    sent_protocol_versions.sort();
+    assert_eq!(
+        sent_protocol_versions,
+        vec![
+            HEARTBEAT_PROTOCOL_VERSION,
+            PROTOCOL_VERSION,
+            PROTOCOL_VERSION
+        ]
+    );
+}
+
+#[test]
+fn test_that_a_heartbeat_is_acked_with_its_send_time() {
+    let mediator = create_mediator();
+
+    let heartbeat_message = HeartbeatMessage {
+        sender: 3,
+        sent_at: 1234,
+    };
+    let bytes = mediator.abd_node().encode_message(
+        &heartbeat_message.into(),
+        PROTOCOL_VERSION,
+        ANY_INCARNATION,
+    );
+    mediator.bytes_received(&bytes);
+
+    let expected_heartbeat_ack_message = HeartbeatAckMessage {
+        sender: mediator.node_id(),
+        sent_at: 1234,
+    };
+    assert_eq!(
+        *mediator.sent_heartbeat_ack_messages.lock().unwrap(),
+        vec![expected_heartbeat_ack_message]
+    );
+}
+
+#[test]
+fn test_that_a_heartbeat_ack_gives_a_round_trip_time() {
+    let mediator = create_mediator();
+
+    let heartbeat_ack_message = HeartbeatAckMessage {
+        sender: 3,
+        sent_at: 0,
+    };
+    let bytes = mediator.abd_node().encode_message(
+        &heartbeat_ack_message.into(),
+        PROTOCOL_VERSION,
+        ANY_INCARNATION,
+    );
+    mediator.bytes_received(&bytes);
+
+    let health = mediator.peer_health(3);
+    assert_eq!(health.status, PeerStatus::Alive);
+    assert!(health.round_trip_time.is_some());
+    assert_eq!(mediator.peer_health(2).round_trip_time, None);
+}
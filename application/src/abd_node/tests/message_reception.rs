@@ -0,0 +1,538 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::*;
+
+fn write_message_from(sender: NodeId) -> AbdMessage<String> {
+    AbdMessage::Write(WriteMessage {
+        sender: sender,
+        timestamp: 7,
+        value: "Haskell".to_string(),
+    })
+}
+
+fn header(
+    protocol_version: u32,
+    cluster_id: &str,
+    sender: NodeId,
+) -> EnvelopeHeader {
+    EnvelopeHeader {
+        protocol_version: protocol_version,
+        max_protocol_version: protocol_version,
+        cluster_id: cluster_id.to_string(),
+        sender: sender,
+        incarnation: 1,
+        receiver_incarnation: ANY_INCARNATION,
+    }
+}
+
+fn bytes_from(
+    header: EnvelopeHeader,
+    message: AbdMessage<String>,
+) -> Vec<u8> {
+    let envelope = Envelope {
+        protocol_version: header.protocol_version,
+        max_protocol_version: header.max_protocol_version,
+        cluster_id: header.cluster_id,
+        sender: header.sender,
+        incarnation: header.incarnation,
+        receiver_incarnation: header.receiver_incarnation,
+        message: message,
+    };
+    WireFormat::Binary.encode(&envelope)
+}
+
+fn envelope_bytes(
+    protocol_version: u32,
+    cluster_id: &str,
+    sender: NodeId,
+    message: AbdMessage<String>,
+) -> Vec<u8> {
+    bytes_from(header(protocol_version, cluster_id, sender), message)
+}
+
+fn assert_that_the_write_message_was_not_received(
+    mediator: &Arc<MockMediator>,
+) {
+    assert_eq!(*mediator.abd_node().timestamp.lock().unwrap(), 0);
+    assert!(mediator.sent_write_ack_messages.lock().unwrap().is_empty());
+    assert_eq!(mediator.run_result().write_message.received, 0);
+}
+
+#[test]
+fn test_that_a_message_in_a_correct_envelope_is_received() {
+    let mediator = create_mediator();
+
+    let bytes =
+        envelope_bytes(PROTOCOL_VERSION, "test", 3, write_message_from(3));
+    mediator.bytes_received(&bytes);
+
+    assert_eq!(*mediator.abd_node().timestamp.lock().unwrap(), 7);
+    assert_eq!(mediator.run_result().write_message.received, 1);
+}
+
+#[test]
+fn test_that_a_message_from_another_cluster_is_rejected() {
+    let mediator = create_mediator();
+
+    let bytes =
+        envelope_bytes(PROTOCOL_VERSION, "other", 3, write_message_from(3));
+    mediator.bytes_received(&bytes);
+
+    assert_that_the_write_message_was_not_received(&mediator);
+    assert_eq!(mediator.run_result().rejected_messages.other_cluster, 1);
+}
+
+#[test]
+fn test_that_a_message_with_another_protocol_version_is_rejected() {
+    let mediator = create_mediator();
+
+    let bytes = envelope_bytes(
+        PROTOCOL_VERSION + 1,
+        "test",
+        3,
+        write_message_from(3),
+    );
+    mediator.bytes_received(&bytes);
+
+    assert_that_the_write_message_was_not_received(&mediator);
+    assert_eq!(
+        mediator
+            .run_result()
+            .rejected_messages
+            .incompatible_protocol_version,
+        1
+    );
+}
+
+#[test]
+fn test_that_the_version_is_read_even_if_the_message_is_unknown() {
+    let mediator = create_mediator();
+
+    let json = format!(
+        "{{\"protocol_version\":{0},\"max_protocol_version\":{0},\"cluster_id\":\"test\",\"sender\":3,\"incarnation\":1,\"receiver_incarnation\":0,\"message\":{{\"Gossip\":{{}}}}}}",
+        PROTOCOL_VERSION + 1
+    );
+    mediator.bytes_received(json.as_bytes());
+
+    assert_eq!(
+        mediator
+            .run_result()
+            .rejected_messages
+            .incompatible_protocol_version,
+        1
+    );
+    assert_eq!(mediator.run_result().rejected_messages.malformed, 0);
+}
+
+#[test]
+fn test_that_a_message_from_an_unknown_node_is_rejected() {
+    let mediator = create_mediator();
+
+    let bytes =
+        envelope_bytes(PROTOCOL_VERSION, "test", 9, write_message_from(9));
+    mediator.bytes_received(&bytes);
+
+    assert_that_the_write_message_was_not_received(&mediator);
+    assert_eq!(mediator.run_result().rejected_messages.unknown_sender, 1);
+}
+
+#[test]
+fn test_that_a_message_from_another_sender_than_the_envelope_is_rejected(
+) {
+    let mediator = create_mediator();
+
+    let bytes =
+        envelope_bytes(PROTOCOL_VERSION, "test", 2, write_message_from(3));
+    mediator.bytes_received(&bytes);
+
+    assert_that_the_write_message_was_not_received(&mediator);
+    assert_eq!(mediator.run_result().rejected_messages.malformed, 1);
+}
+
+#[test]
+fn test_that_bytes_that_are_not_a_message_are_rejected() {
+    let mediator = create_mediator();
+
+    mediator.bytes_received(b"Not a message");
+    mediator.bytes_received(&[]);
+
+    assert_that_the_write_message_was_not_received(&mediator);
+    assert_eq!(mediator.run_result().rejected_messages.malformed, 2);
+}
+
+#[test]
+fn test_that_a_message_from_a_newer_node_in_a_known_version_is_received()
+{
+    let mediator = create_mediator();
+
+    let mut header = header(PROTOCOL_VERSION, "test", 3);
+    header.max_protocol_version = PROTOCOL_VERSION + 1;
+    let bytes = bytes_from(header, write_message_from(3));
+    mediator.bytes_received(&bytes);
+
+    assert_eq!(*mediator.abd_node().timestamp.lock().unwrap(), 7);
+    assert_eq!(
+        mediator.run_result().peer_protocol_versions.get(&3),
+        Some(&(PROTOCOL_VERSION + 1))
+    );
+    assert_eq!(
+        *mediator.sent_protocol_versions.lock().unwrap(),
+        vec![PROTOCOL_VERSION]
+    );
+}
+
+#[test]
+fn test_that_an_incompatible_node_is_logged_once() {
+    let mediator = create_mediator();
+
+    for _ in 0..3 {
+        let bytes = envelope_bytes(
+            PROTOCOL_VERSION + 1,
+            "test",
+            3,
+            write_message_from(3),
+        );
+        mediator.bytes_received(&bytes);
+    }
+
+    assert_eq!(mediator.log.lock().unwrap().len(), 1);
+    assert!(mediator.log.lock().unwrap()[0].contains("node 3"));
+    assert_eq!(
+        mediator
+            .run_result()
+            .rejected_messages
+            .incompatible_protocol_version,
+        3
+    );
+    assert!(mediator.run_result().peer_protocol_versions.is_empty());
+}
+
+#[test]
+fn test_that_an_incompatible_version_from_an_unknown_node_is_not_logged()
+{
+    let mediator = create_mediator();
+
+    let bytes = envelope_bytes(
+        PROTOCOL_VERSION + 1,
+        "test",
+        9,
+        write_message_from(9),
+    );
+    mediator.bytes_received(&bytes);
+
+    assert!(mediator.log.lock().unwrap().is_empty());
+    assert_eq!(mediator.run_result().rejected_messages.unknown_sender, 1);
+    assert_eq!(
+        mediator
+            .run_result()
+            .rejected_messages
+            .incompatible_protocol_version,
+        0
+    );
+}
+
+fn keys_for_tests() -> Keys {
+    Keys::from_string(
+        "1,000102030405060708090a0b0c0d0e0f\n3,303132333435363738393a3b3c3d3e3f\n",
+    )
+}
+
+fn create_mediator_with_keys() -> Arc<MockMediator> {
+    MockMediator::new_with_keys(
+        1,
+        node_ids_for_tests(),
+        Some(keys_for_tests()),
+    )
+}
+
+#[test]
+fn test_that_an_authenticated_message_is_received() {
+    let mediator = create_mediator_with_keys();
+
+    let mut bytes =
+        envelope_bytes(PROTOCOL_VERSION, "test", 3, write_message_from(3));
+    keys_for_tests().append_tag(3, &mut bytes);
+    mediator.bytes_received(&bytes);
+
+    assert_eq!(*mediator.abd_node().timestamp.lock().unwrap(), 7);
+    assert_eq!(mediator.sent_write_ack_messages.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn test_that_a_message_without_a_tag_is_rejected() {
+    let mediator = create_mediator_with_keys();
+
+    let bytes =
+        envelope_bytes(PROTOCOL_VERSION, "test", 3, write_message_from(3));
+    mediator.bytes_received(&bytes);
+    mediator.bytes_received(&bytes[..10]);
+
+    assert_that_the_write_message_was_not_received(&mediator);
+    assert_eq!(mediator.run_result().rejected_messages.unauthenticated, 2);
+}
+
+#[test]
+fn test_that_a_message_forged_with_the_key_of_another_node_is_rejected() {
+    let mediator = create_mediator_with_keys();
+
+    let mut bytes =
+        envelope_bytes(PROTOCOL_VERSION, "test", 3, write_message_from(3));
+    keys_for_tests().append_tag(1, &mut bytes);
+    mediator.bytes_received(&bytes);
+
+    assert_that_the_write_message_was_not_received(&mediator);
+    assert_eq!(mediator.run_result().rejected_messages.unauthenticated, 1);
+}
+
+#[test]
+fn test_that_a_message_from_a_node_without_a_key_is_rejected() {
+    let mediator = create_mediator_with_keys();
+
+    let mut bytes =
+        envelope_bytes(PROTOCOL_VERSION, "test", 2, write_message_from(2));
+    bytes.extend_from_slice(&[0; authentication::TAG_SIZE]);
+    mediator.bytes_received(&bytes);
+
+    assert_that_the_write_message_was_not_received(&mediator);
+    assert_eq!(mediator.run_result().rejected_messages.unauthenticated, 1);
+}
+
+#[test]
+fn test_that_an_incompatible_version_without_a_valid_tag_is_not_logged() {
+    let mediator = create_mediator_with_keys();
+
+    let mut bytes = envelope_bytes(
+        PROTOCOL_VERSION + 1,
+        "test",
+        3,
+        write_message_from(3),
+    );
+    keys_for_tests().append_tag(1, &mut bytes);
+    mediator.bytes_received(&bytes);
+
+    assert!(mediator.log.lock().unwrap().is_empty());
+    assert_eq!(mediator.run_result().rejected_messages.unauthenticated, 1);
+    assert_eq!(
+        mediator
+            .run_result()
+            .rejected_messages
+            .incompatible_protocol_version,
+        0
+    );
+}
+
+fn write_ack_message_from(sender: NodeId) -> AbdMessage<String> {
+    AbdMessage::WriteAck(WriteAckMessage {
+        sender: sender,
+        timestamp: 0,
+    })
+}
+
+#[test]
+fn test_that_a_message_from_a_previous_incarnation_is_discarded() {
+    let mediator = create_mediator();
+
+    let mut new_header = header(PROTOCOL_VERSION, "test", 3);
+    new_header.incarnation = 20;
+    mediator.bytes_received(&bytes_from(
+        new_header,
+        write_ack_message_from(3),
+    ));
+    let mut old_header = header(PROTOCOL_VERSION, "test", 3);
+    old_header.incarnation = 10;
+    mediator.bytes_received(&bytes_from(old_header, write_message_from(3)));
+
+    assert_that_the_write_message_was_not_received(&mediator);
+    assert_eq!(
+        mediator.run_result().rejected_messages.previous_incarnation,
+        1
+    );
+}
+
+#[test]
+fn test_that_a_message_from_a_restarted_node_is_received() {
+    let mediator = create_mediator();
+
+    let mut old_header = header(PROTOCOL_VERSION, "test", 3);
+    old_header.incarnation = 10;
+    mediator.bytes_received(&bytes_from(
+        old_header,
+        write_ack_message_from(3),
+    ));
+    let mut new_header = header(PROTOCOL_VERSION, "test", 3);
+    new_header.incarnation = 20;
+    mediator.bytes_received(&bytes_from(new_header, write_message_from(3)));
+
+    assert_eq!(*mediator.abd_node().timestamp.lock().unwrap(), 7);
+    assert_eq!(
+        mediator.run_result().rejected_messages.previous_incarnation,
+        0
+    );
+}
+
+#[test]
+fn test_that_a_reply_to_a_previous_incarnation_is_discarded() {
+    let mediator = create_mediator();
+    let own_incarnation =
+        mediator.abd_node().incarnations.own_incarnation();
+
+    let mut header = header(PROTOCOL_VERSION, "test", 3);
+    header.receiver_incarnation = own_incarnation - 1;
+    mediator.bytes_received(&bytes_from(header, write_ack_message_from(3)));
+
+    assert_eq!(mediator.run_result().write_ack_message.received, 0);
+    assert_eq!(
+        mediator.run_result().rejected_messages.previous_incarnation,
+        1
+    );
+}
+
+#[test]
+fn test_that_a_reply_is_sent_to_the_incarnation_of_the_requester() {
+    let mediator = create_mediator();
+
+    let mut header = header(PROTOCOL_VERSION, "test", 3);
+    header.incarnation = 20;
+    mediator.bytes_received(&bytes_from(header, write_message_from(3)));
+
+    assert_eq!(
+        *mediator.sent_receiver_incarnations.lock().unwrap(),
+        vec![20]
+    );
+}
+
+
+//
+// Fuzzing
+//
+
+// The seeds are fixed, so that a failure can be reproduced.
+const FUZZING_SEED: u64 = 42;
+const FUZZING_ROUNDS: usize = 2000;
+
+fn number_of_counted_messages(mediator: &Arc<MockMediator>) -> Int {
+    let run_result = mediator.run_result();
+    let rejected = &run_result.rejected_messages;
+    let received = [
+        &run_result.write_message,
+        &run_result.write_ack_message,
+        &run_result.read1_message,
+        &run_result.read1_ack_message,
+        &run_result.read2_message,
+        &run_result.read2_ack_message,
+        &run_result.heartbeat_message,
+        &run_result.heartbeat_ack_message,
+    ]
+    .iter()
+    .map(|message_type_result| message_type_result.received)
+    .sum::<Int>();
+
+    received
+        + rejected.malformed
+        + rejected.incompatible_protocol_version
+        + rejected.other_cluster
+        + rejected.unknown_sender
+        + rejected.unauthenticated
+        + rejected.undecryptable
+        + rejected.previous_incarnation
+}
+
+fn random_bytes(rng: &mut StdRng) -> Vec<u8> {
+    let mut bytes = vec![0; rng.gen_range(0, 300)];
+    rng.fill(&mut bytes[..]);
+    bytes
+}
+
+// Flips, removes or appends a few random bytes.
+fn mutated(bytes: &[u8], rng: &mut StdRng) -> Vec<u8> {
+    let mut bytes = bytes.to_vec();
+    for _ in 0..rng.gen_range(1, 4) {
+        match rng.gen_range(0, 3) {
+            0 if !bytes.is_empty() => {
+                let index = rng.gen_range(0, bytes.len());
+                bytes[index] ^= 1 << rng.gen_range(0, 8);
+            }
+            1 if !bytes.is_empty() => {
+                bytes.truncate(rng.gen_range(0, bytes.len()));
+            }
+            _ => bytes.push(rng.gen()),
+        }
+    }
+    bytes
+}
+
+fn valid_bytes() -> Vec<Vec<u8>> {
+    let header = header(PROTOCOL_VERSION, "test", 3);
+    let envelope = Envelope {
+        protocol_version: header.protocol_version,
+        max_protocol_version: header.max_protocol_version,
+        cluster_id: header.cluster_id,
+        sender: header.sender,
+        incarnation: header.incarnation,
+        receiver_incarnation: header.receiver_incarnation,
+        message: write_message_from(3),
+    };
+    vec![
+        WireFormat::Binary.encode(&envelope),
+        WireFormat::Json.encode(&envelope),
+        envelope_bytes(PROTOCOL_VERSION, "test", 3, write_ack_message_from(3)),
+    ]
+}
+
+#[test]
+fn test_that_random_bytes_are_counted_as_rejected() {
+    let mediator = create_mediator();
+    let mut rng = StdRng::seed_from_u64(FUZZING_SEED);
+
+    for _ in 0..FUZZING_ROUNDS {
+        mediator.bytes_received(&random_bytes(&mut rng));
+    }
+
+    assert_that_the_write_message_was_not_received(&mediator);
+    assert_eq!(
+        number_of_counted_messages(&mediator),
+        FUZZING_ROUNDS as Int
+    );
+}
+
+#[test]
+fn test_that_mutated_messages_are_counted() {
+    let mediator = create_mediator();
+    let mut rng = StdRng::seed_from_u64(FUZZING_SEED);
+    let valid_bytes = valid_bytes();
+
+    for round in 0..FUZZING_ROUNDS {
+        let bytes = &valid_bytes[round % valid_bytes.len()];
+        mediator.bytes_received(&mutated(bytes, &mut rng));
+    }
+
+    assert_eq!(
+        number_of_counted_messages(&mediator),
+        FUZZING_ROUNDS as Int
+    );
+}
+
+#[test]
+fn test_that_mutated_authenticated_messages_are_rejected() {
+    let mediator = create_mediator_with_keys();
+    let mut rng = StdRng::seed_from_u64(FUZZING_SEED);
+    let valid_bytes: Vec<Vec<u8>> = valid_bytes()
+        .into_iter()
+        .map(|mut bytes| {
+            keys_for_tests().append_tag(3, &mut bytes);
+            bytes
+        })
+        .collect();
+
+    for round in 0..FUZZING_ROUNDS {
+        let bytes = &valid_bytes[round % valid_bytes.len()];
+        let mutated_bytes = mutated(bytes, &mut rng);
+        if &mutated_bytes != bytes {
+            mediator.bytes_received(&mutated_bytes);
+        }
+    }
+
+    assert_that_the_write_message_was_not_received(&mediator);
+    assert_eq!(mediator.run_result().write_ack_message.received, 0);
+}
@@ -0,0 +1,17 @@
+use super::*;
+
+#[test]
+fn test_that_both_read_phases_are_counted_as_read_quorum_accesses() {
+    let mediator = MockMediator::new_with_loopback(1);
+
+    mediator.abd_node().read_versioned().unwrap();
+
+    let run_result = mediator.run_result();
+    let quorum_accesses = &run_result.quorum_accesses;
+    assert_eq!(run_result.read_ops, 1);
+    assert_eq!(run_result.read_quorum_accesses, 2);
+    assert_eq!(run_result.write_quorum_accesses, 0);
+    assert_eq!(quorum_accesses.read1.accesses, 1);
+    assert_eq!(quorum_accesses.read2.accesses, 1);
+    assert_eq!(quorum_accesses.write.accesses, 0);
+}
@@ -11,16 +11,16 @@ fn create_mediator_perform_write_and_ack_from_node_ids(node_ids: HashSet<NodeId>
         perform_single_write_on_background_thread(&mediator);
     wait_until_local_timestamp_is_updated(&mediator);
     send_write_ack_message_from_node_ids(&mediator, node_ids);
-    write_thread_handle.join().unwrap();
+    write_thread_handle.join().unwrap().unwrap();
     mediator
 }
 
 fn perform_single_write_on_background_thread(
     mediator: &Arc<MockMediator>,
-) -> JoinHandle<()> {
+) -> JoinHandle<Result<(), WriteError>> {
     let mediator_for_write_thread = Arc::clone(&mediator);
     thread::spawn(move || {
-        mediator_for_write_thread.write("Haskell".to_string());
+        mediator_for_write_thread.write("Haskell".to_string())
     })
 }
 
@@ -47,7 +47,11 @@ fn send_write_ack_message_from_node_ids(
             sender: node_id,
             timestamp: timestamp,
         };
-        let json = mediator.abd_node().jsonify_message(&write_ack_message);
-        mediator.json_received(&json);
+        let bytes = mediator.abd_node().encode_message(
+            &write_ack_message.into(),
+            PROTOCOL_VERSION,
+            ANY_INCARNATION,
+        );
+        mediator.bytes_received(&bytes);
     }
 }
@@ -39,8 +39,12 @@ fn test_that_a_write_message_reception_sends_correct_write_ack_message() {
         timestamp: 7,
         value: "Haskell".to_string(),
     };
-    let json = mediator.abd_node().jsonify_message(&write_message);
-    mediator.json_received(&json);
+    let bytes = mediator.abd_node().encode_message(
+        &write_message.into(),
+        PROTOCOL_VERSION,
+        ANY_INCARNATION,
+    );
+    mediator.bytes_received(&bytes);
 
     let expected_write_ack_message = WriteAckMessage {
         sender: mediator.node_id(),
@@ -49,4 +53,59 @@ fn test_that_a_write_message_reception_sends_correct_write_ack_message() {
 
     let sent_write_ack_messages = mediator.sent_write_ack_messages.lock().unwrap().clone();
     assert_eq!(sent_write_ack_messages, vec![expected_write_ack_message]);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_that_write_messages_are_only_sent_again_to_nodes_that_have_not_acked() {
+    let mediator = create_mediator();
+    perform_single_write_on_background_thread(&mediator);
+    wait_until_local_timestamp_is_updated(&mediator);
+    while mediator.sent_write_messages.lock().unwrap().len() < 4 {}
+
+    let mut node_ids = HashSet::new();
+    node_ids.insert(2);
+    send_write_ack_message_from_node_ids(&mediator, node_ids);
+    mediator.write_message_receivers.lock().unwrap().clear();
+
+    // Two rounds of retransmissions to the three other nodes.
+    while mediator.sent_write_messages.lock().unwrap().len() < 4 + 2 * 3 {}
+
+    let mut expected_receivers = HashSet::new();
+    expected_receivers.insert(1);
+    expected_receivers.insert(3);
+    expected_receivers.insert(4);
+    assert_eq!(
+        *mediator.write_message_receivers.lock().unwrap(),
+        expected_receivers
+    );
+    let quorum_accesses = mediator.run_result().quorum_accesses.clone();
+    assert!(quorum_accesses.write.retransmissions >= 2 * 3);
+    assert!(quorum_accesses.write.rounds >= 3);
+    assert_eq!(quorum_accesses.write.acks_before_majority, 1);
+    assert_eq!(quorum_accesses.read1.retransmissions, 0);
+}
+
+#[test]
+fn test_that_the_fastest_quorum_gets_the_write_message_first() {
+    let mediator = create_mediator();
+    *mediator.fastest_quorum_first.lock().unwrap() = true;
+    mediator.heard_from(4, Some(Duration::from_millis(1)));
+    mediator.heard_from(3, Some(Duration::from_millis(2)));
+    mediator.heard_from(1, Some(Duration::from_millis(3)));
+
+    perform_single_write_on_background_thread(&mediator);
+    wait_until_local_timestamp_is_updated(&mediator);
+    while mediator.sent_write_messages.lock().unwrap().len() < 3 {}
+
+    let mut expected_receivers = HashSet::new();
+    expected_receivers.insert(1);
+    expected_receivers.insert(3);
+    expected_receivers.insert(4);
+    assert_eq!(
+        *mediator.write_message_receivers.lock().unwrap(),
+        expected_receivers
+    );
+
+    // Node 2 gets the message at the first retransmission timeout.
+    while !mediator.write_message_receivers.lock().unwrap().contains(&2) {}
+}
@@ -40,6 +40,83 @@ fn test_that_write_does_not_terminate_without_acks_from_majority() {
     check_that_write_fails(&mediator);
 }
 
+#[test]
+fn test_that_an_aborted_write_terminates_without_acks() {
+    let mediator = create_mediator();
+    let write_thread_handle =
+        perform_single_write_on_background_thread(&mediator);
+    wait_until_local_timestamp_is_updated(&mediator);
+
+    mediator.abd_node().abort();
+
+    assert_eq!(write_thread_handle.join().unwrap(), Err(WriteError::Aborted));
+    assert!(mediator.abd_node().write_quorum.is_idle());
+    assert_eq!(mediator.run_result().write_ops, 0);
+    let write_result = mediator.run_result().quorum_accesses.write.clone();
+    assert_eq!(write_result.accesses, 1);
+    assert_eq!(write_result.aborted, 1);
+    assert_eq!(write_result.time_to_majority.count(), 0);
+}
+
+#[test]
+fn test_that_writes_after_an_abort_are_aborted() {
+    let mediator = create_mediator();
+    mediator.abd_node().abort();
+
+    assert_eq!(mediator.write("Haskell".to_string()), Err(WriteError::Aborted));
+    assert!(mediator.sent_write_messages.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_that_the_write_latency_is_recorded_when_enabled() {
+    let mediator = create_mediator();
+    *mediator.record_latencies.lock().unwrap() = true;
+    let write_thread_handle =
+        perform_single_write_on_background_thread(&mediator);
+    wait_until_local_timestamp_is_updated(&mediator);
+    thread::sleep(Duration::from_millis(20));
+    send_write_ack_message_from_node_ids(&mediator, node_ids_for_tests());
+    write_thread_handle.join().unwrap().unwrap();
+
+    let write_latencies = mediator.run_result().latencies.write.clone();
+    assert_eq!(write_latencies.count(), 1);
+    assert!(write_latencies.max() >= Duration::from_millis(20));
+    assert_eq!(mediator.run_result().latencies.read.count(), 0);
+}
+
+#[test]
+fn test_that_the_write_latency_is_not_recorded_when_disabled() {
+    let mediator = create_mediator_perform_write_and_ack_from_node_ids(
+        node_ids_for_tests(),
+    );
+
+    assert_eq!(mediator.run_result().write_ops, 1);
+    assert_eq!(mediator.run_result().latencies.write.count(), 0);
+}
+
+#[test]
+fn test_that_acks_are_counted_before_and_after_the_majority() {
+    let mediator = create_mediator();
+    let write_thread_handle =
+        perform_single_write_on_background_thread(&mediator);
+    wait_until_local_timestamp_is_updated(&mediator);
+    while mediator.sent_write_messages.lock().unwrap().len() < 4 {}
+    send_write_ack_message_from_node_ids(&mediator, node_ids_for_tests());
+    write_thread_handle.join().unwrap().unwrap();
+
+    let run_result = mediator.run_result();
+    let write_result = &run_result.quorum_accesses.write;
+    assert_eq!(run_result.write_quorum_accesses, 1);
+    assert_eq!(run_result.read_quorum_accesses, 0);
+    assert_eq!(write_result.accesses, 1);
+    assert_eq!(write_result.rounds, 1);
+    assert_eq!(write_result.retransmissions, 0);
+    // Three of the four nodes are a majority.
+    assert_eq!(write_result.acks_before_majority, 3);
+    assert_eq!(write_result.acks_after_majority, 1);
+    assert_eq!(write_result.time_to_majority.count(), 1);
+}
+
 fn check_that_write_fails(mediator: &Arc<MockMediator>) {
     // If this test terminates, it means that retransmissions had
     // to be done, because no write acks received.
@@ -24,8 +24,12 @@ fn test_that_a_write_message_updates_local_timestamp_and_value_if_greater() {
         timestamp: 30,
         value: "Rust".to_string(),
     };
-    let json = mediator.abd_node().jsonify_message(&write_message);
-    mediator.json_received(&json);
+    let bytes = mediator.abd_node().encode_message(
+        &write_message.into(),
+        PROTOCOL_VERSION,
+        ANY_INCARNATION,
+    );
+    mediator.bytes_received(&bytes);
 
     assert_eq!(*mediator.abd_node().timestamp.lock().unwrap(), 30);
     assert_eq!(*mediator.abd_node().value.lock().unwrap(), "Rust".to_string());
@@ -41,8 +45,12 @@ fn test_that_a_write_message_does_not_update_local_timestamp_and_value_if_equal(
         timestamp: 20,
         value: "Rust".to_string(),
     };
-    let json = mediator.abd_node().jsonify_message(&write_message);
-    mediator.json_received(&json);
+    let bytes = mediator.abd_node().encode_message(
+        &write_message.into(),
+        PROTOCOL_VERSION,
+        ANY_INCARNATION,
+    );
+    mediator.bytes_received(&bytes);
 
     assert_eq!(*mediator.abd_node().timestamp.lock().unwrap(), 20);
     assert_eq!(*mediator.abd_node().value.lock().unwrap(), "".to_string());
@@ -58,8 +66,12 @@ fn test_that_a_write_message_does_not_update_local_timestamp_and_value_if_smalle
         timestamp: 3,
         value: "Rust".to_string(),
     };
-    let json = mediator.abd_node().jsonify_message(&write_message);
-    mediator.json_received(&json);
+    let bytes = mediator.abd_node().encode_message(
+        &write_message.into(),
+        PROTOCOL_VERSION,
+        ANY_INCARNATION,
+    );
+    mediator.bytes_received(&bytes);
 
     assert_eq!(*mediator.abd_node().timestamp.lock().unwrap(), 20);
     assert_eq!(*mediator.abd_node().value.lock().unwrap(), "".to_string());
@@ -73,8 +85,12 @@ fn test_that_a_write_message_does_not_make_the_write_quorum_non_idle() {
         timestamp: 3,
         value: "Rust".to_string(),
     };
-    let json = mediator.abd_node().jsonify_message(&write_message);
-    mediator.json_received(&json);
+    let bytes = mediator.abd_node().encode_message(
+        &write_message.into(),
+        PROTOCOL_VERSION,
+        ANY_INCARNATION,
+    );
+    mediator.bytes_received(&bytes);
 
     assert!(mediator.abd_node().write_quorum.is_idle());
 }
@@ -86,8 +102,12 @@ fn test_that_a_write_ack_message_does_not_change_local_timestamp() {
         sender: 2,
         timestamp: 3,
     };
-    let json = mediator.abd_node().jsonify_message(&write_ack_message);
-    mediator.json_received(&json);
+    let bytes = mediator.abd_node().encode_message(
+        &write_ack_message.into(),
+        PROTOCOL_VERSION,
+        ANY_INCARNATION,
+    );
+    mediator.bytes_received(&bytes);
 
     assert_eq!(*mediator.abd_node().timestamp.lock().unwrap(), 0);
 }
@@ -99,8 +119,12 @@ fn test_that_a_write_ack_message_does_not_cause_idle_quorum_to_become_non_idle()
         sender: 2,
         timestamp: 3,
     };
-    let json = mediator.abd_node().jsonify_message(&write_ack_message);
-    mediator.json_received(&json);
+    let bytes = mediator.abd_node().encode_message(
+        &write_ack_message.into(),
+        PROTOCOL_VERSION,
+        ANY_INCARNATION,
+    );
+    mediator.bytes_received(&bytes);
 
     assert!(mediator.abd_node().write_quorum.is_idle());
 }
@@ -115,8 +139,12 @@ fn test_that_a_write_ack_with_wrong_timestamp_does_not_count_as_an_ack() {
         sender: 2,
         timestamp: 3003,
     };
-    let json = mediator.abd_node().jsonify_message(&write_ack_message);
-    mediator.json_received(&json);
+    let bytes = mediator.abd_node().encode_message(
+        &write_ack_message.into(),
+        PROTOCOL_VERSION,
+        ANY_INCARNATION,
+    );
+    mediator.bytes_received(&bytes);
 
     assert!(mediator.abd_node().write_quorum.acking_nodes().lock().unwrap().is_empty());
 }
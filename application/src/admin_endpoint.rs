@@ -0,0 +1,338 @@
+use std::io::{self, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use commons::admin_protocol::{AdminRequest, AdminResponse, NodeState};
+
+use crate::client_endpoint;
+use crate::client_switches::ClientSwitches;
+
+// Far longer than any admin request.
+const MAX_REQUEST_LENGTH: usize = 256;
+// Since the connections are served one at a time, a connection that
+// sends no request, or doesn't read its responses, for this long is
+// closed, so that it doesn't lock out the other connections.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/*
+A TCP endpoint on 127.0.0.1 that shows what the node is doing while it
+runs, and that can switch the client reading and writing on and off,
+with the line-based protocol in commons::admin_protocol. It is meant
+for an operator on the same computer, so it isn't authenticated, and
+it serves one connection at a time, on the accept thread. The state
+of the node comes from the node_state function, so that the endpoint
+can be tested without a running node.
+*/
+pub struct AdminEndpoint {
+    socket_addr: SocketAddr,
+    shutting_down: Arc<AtomicBool>,
+    accept_thread: JoinHandle<()>,
+    current_connection: Arc<Mutex<Option<TcpStream>>>,
+}
+
+impl AdminEndpoint {
+    pub fn start(
+        socket_addr: SocketAddr,
+        node_state: impl Fn(&ClientSwitches) -> NodeState + Send + 'static,
+        client_switches: Arc<ClientSwitches>,
+    ) -> AdminEndpoint {
+        let listener = TcpListener::bind(socket_addr)
+            .expect("Could not create the admin endpoint listener.");
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let current_connection = Arc::new(Mutex::new(None));
+
+        let accept_thread_shutting_down = Arc::clone(&shutting_down);
+        let accept_thread_current_connection =
+            Arc::clone(&current_connection);
+        let accept_thread = thread::spawn(move || {
+            accept_loop(
+                listener,
+                accept_thread_shutting_down,
+                accept_thread_current_connection,
+                node_state,
+                client_switches,
+            );
+        });
+
+        AdminEndpoint {
+            socket_addr: socket_addr,
+            shutting_down: shutting_down,
+            accept_thread: accept_thread,
+            current_connection: current_connection,
+        }
+    }
+
+    pub fn shutdown(self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        if let Some(stream) =
+            self.current_connection.lock().unwrap().take()
+        {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        let _ = TcpStream::connect(self.socket_addr);
+        self.accept_thread
+            .join()
+            .expect("The admin endpoint accept thread panicked.");
+    }
+}
+
+fn accept_loop(
+    listener: TcpListener,
+    shutting_down: Arc<AtomicBool>,
+    current_connection: Arc<Mutex<Option<TcpStream>>>,
+    node_state: impl Fn(&ClientSwitches) -> NodeState,
+    client_switches: Arc<ClientSwitches>,
+) {
+    loop {
+        let stream = listener.accept();
+        if shutting_down.load(Ordering::SeqCst) {
+            return;
+        }
+        let stream = match stream {
+            Ok((stream, _)) => stream,
+            Err(_) => continue,
+        };
+        if stream.set_read_timeout(Some(IDLE_TIMEOUT)).is_err()
+            || stream.set_write_timeout(Some(IDLE_TIMEOUT)).is_err()
+        {
+            continue;
+        }
+        let writer = match stream.try_clone() {
+            Ok(writer) => writer,
+            Err(_) => continue,
+        };
+        if let Ok(stream_clone) = stream.try_clone() {
+            *current_connection.lock().unwrap() = Some(stream_clone);
+        }
+        // The endpoint may have started shutting down before the
+        // connection was registered, in which case nobody closes it.
+        if shutting_down.load(Ordering::SeqCst) {
+            return;
+        }
+
+        serve_connection(stream, writer, &node_state, &client_switches);
+        current_connection.lock().unwrap().take();
+    }
+}
+
+// A line that is too long or not utf-8 gets an error and closes the
+// connection, like in the client endpoint, and so does a connection
+// that is idle for the IDLE_TIMEOUT.
+fn serve_connection(
+    stream: TcpStream,
+    mut writer: TcpStream,
+    node_state: &impl Fn(&ClientSwitches) -> NodeState,
+    client_switches: &ClientSwitches,
+) {
+    let mut reader = BufReader::new(stream);
+
+    loop {
+        let (response, close) = match client_endpoint::read_request_line(
+            &mut reader,
+            MAX_REQUEST_LENGTH,
+        ) {
+            Ok(Some(line)) => {
+                match AdminRequest::from_line(line.trim_end()) {
+                    Ok(request) => (
+                        response_to(request, node_state, client_switches),
+                        false,
+                    ),
+                    Err(reason) => (AdminResponse::Error(reason), false),
+                }
+            }
+            Ok(None) => return,
+            Err(error)
+                if error.kind() == io::ErrorKind::WouldBlock
+                    || error.kind() == io::ErrorKind::TimedOut =>
+            {
+                let reason = format!(
+                    "No request for {} seconds.",
+                    IDLE_TIMEOUT.as_secs()
+                );
+                (AdminResponse::Error(reason), true)
+            }
+            Err(error) => (AdminResponse::Error(error.to_string()), true),
+        };
+
+        if writeln!(writer, "{}", response.to_line()).is_err() || close {
+            return;
+        }
+    }
+}
+
+fn response_to(
+    request: AdminRequest,
+    node_state: &impl Fn(&ClientSwitches) -> NodeState,
+    client_switches: &ClientSwitches,
+) -> AdminResponse {
+    let result = match request {
+        AdminRequest::State => {
+            let node_state = node_state(client_switches);
+            return AdminResponse::State(Box::new(node_state));
+        }
+        AdminRequest::SetReading(reading) => {
+            client_switches.set_reading(reading)
+        }
+        AdminRequest::SetWriting(writing) => {
+            client_switches.set_writing(writing)
+        }
+    };
+
+    match result {
+        Ok(()) => AdminResponse::Ok,
+        Err(reason) => AdminResponse::Error(reason),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io::BufRead;
+
+    use commons::admin_protocol::{
+        ClientOperationsState, Configuration, QuorumState, QuorumStates,
+    };
+    use commons::run_result::RunResult;
+
+    use super::*;
+
+    fn free_socket_addr() -> SocketAddr {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn quorum_state() -> QuorumState {
+        QuorumState {
+            accessing: false,
+            acking_nodes: Vec::new(),
+        }
+    }
+
+    // The state of node 3 of a cluster of three, with the switches.
+    fn node_state(client_switches: &ClientSwitches) -> NodeState {
+        NodeState {
+            node_id: 3,
+            incarnation: 1,
+            uptime_millis: 0,
+            timestamp: 7,
+            value: "Haskell".to_string(),
+            quorums: QuorumStates {
+                write: quorum_state(),
+                read1: quorum_state(),
+                read2: quorum_state(),
+            },
+            peers: BTreeMap::new(),
+            client_operations: ClientOperationsState {
+                reading: client_switches.reading(),
+                writing: client_switches.writing(),
+                in_progress: 0,
+            },
+            configuration: Configuration {
+                node_ids: vec![1, 2, 3],
+                cluster_id: "default".to_string(),
+                is_writer: client_switches.is_writer(),
+                wire_format: "Binary".to_string(),
+                authenticated: false,
+                encrypted: false,
+                max_value_size: 10,
+                run_length_secs: 0,
+                adaptive_retransmission: false,
+                initial_retransmission_timeout_millis: 100,
+                fastest_quorum_first: false,
+                heartbeat_interval_millis: 100,
+                suspicion_timeout_millis: 1000,
+            },
+            run_result: RunResult::new(),
+        }
+    }
+
+    struct Connection {
+        writer: TcpStream,
+        reader: BufReader<TcpStream>,
+    }
+
+    impl Connection {
+        fn open(socket_addr: SocketAddr) -> Connection {
+            let writer = TcpStream::connect(socket_addr).unwrap();
+            let reader = BufReader::new(writer.try_clone().unwrap());
+            Connection {
+                writer: writer,
+                reader: reader,
+            }
+        }
+
+        fn request(&mut self, request: AdminRequest) -> AdminResponse {
+            writeln!(self.writer, "{}", request.to_line()).unwrap();
+            let mut response_line = String::new();
+            self.reader.read_line(&mut response_line).unwrap();
+            AdminResponse::from_line(response_line.trim_end()).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_that_the_state_of_the_node_is_served() {
+        let client_switches = Arc::new(ClientSwitches::new(false, true));
+        let socket_addr = free_socket_addr();
+        let endpoint = AdminEndpoint::start(
+            socket_addr,
+            node_state,
+            Arc::clone(&client_switches),
+        );
+        let mut connection = Connection::open(socket_addr);
+
+        match connection.request(AdminRequest::State) {
+            AdminResponse::State(node_state) => {
+                assert_eq!(node_state.node_id, 3);
+                assert_eq!(node_state.timestamp, 7);
+                assert_eq!(node_state.value, "Haskell");
+                assert!(node_state.client_operations.reading);
+            }
+            response => panic!("Unexpected response {:?}", response),
+        }
+
+        drop(connection);
+        endpoint.shutdown();
+    }
+
+    #[test]
+    fn test_that_reading_and_writing_are_switched() {
+        let client_switches = Arc::new(ClientSwitches::new(false, true));
+        let socket_addr = free_socket_addr();
+        let endpoint = AdminEndpoint::start(
+            socket_addr,
+            node_state,
+            Arc::clone(&client_switches),
+        );
+        let mut connection = Connection::open(socket_addr);
+
+        match connection.request(AdminRequest::SetReading(false)) {
+            AdminResponse::Ok => {}
+            response => panic!("Unexpected response {:?}", response),
+        }
+        assert!(!client_switches.reading());
+        match connection.request(AdminRequest::State) {
+            AdminResponse::State(node_state) => {
+                assert!(!node_state.client_operations.reading)
+            }
+            response => panic!("Unexpected response {:?}", response),
+        }
+
+        match connection.request(AdminRequest::SetWriting(true)) {
+            AdminResponse::Error(reason) => {
+                assert!(reason.contains("not the writer"))
+            }
+            response => panic!("Unexpected response {:?}", response),
+        }
+        assert!(!client_switches.writing());
+
+        drop(connection);
+        endpoint.shutdown();
+    }
+}
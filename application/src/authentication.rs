@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::fs;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use commons::types::NodeId;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const TAG_SIZE: usize = 32;
+const MIN_KEY_SIZE: usize = 16;
+
+/*
+The keys that messages are authenticated with, read from a key file
+that is kept next to the hosts file. Each line of the file is a node
+id and the key of that node, as hex:
+
+1,6b2f0e4a9c1d3e5f7a8b9c0d1e2f3a4b
+2,0f1e2d3c4b5a69788796a5b4c3d2e1f0
+
+Empty lines and lines starting with # are ignored. Every message is
+followed by an HMAC-SHA256 tag of the encoded envelope, computed with
+the key of the sender. So a host that doesn't have the key of a node
+can't forge messages from that node. To use a single key for the
+whole cluster, give all nodes the same key.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keys {
+    keys: HashMap<NodeId, Vec<u8>>,
+}
+
+impl Keys {
+    pub fn from_file(file_path: &str) -> Keys {
+        let string = fs::read_to_string(file_path)
+            .expect("Unable to read the key file.");
+        Keys::from_string(&string)
+    }
+
+    pub fn from_string(string: &str) -> Keys {
+        let mut keys = HashMap::new();
+
+        for line in string.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let components: Vec<&str> = line.split(',').collect();
+            if components.len() != 2 {
+                panic!(
+                    "The key file line \"{}\" does not have two components.",
+                    line
+                );
+            }
+            let node_id: NodeId = components[0]
+                .trim()
+                .parse()
+                .expect("Could not parse a node id in the key file.");
+            let key = hex::decode(components[1].trim())
+                .expect("Could not parse a key in the key file as hex.");
+            if key.len() < MIN_KEY_SIZE {
+                panic!(
+                    "The key of node {} is {} bytes, but must be at least {} bytes.",
+                    node_id,
+                    key.len(),
+                    MIN_KEY_SIZE
+                );
+            }
+
+            keys.insert(node_id, key);
+        }
+
+        Keys { keys: keys }
+    }
+
+    // Appends the tag of the bytes, computed with the key of the
+    // sender.
+    pub fn append_tag(&self, sender: NodeId, bytes: &mut Vec<u8>) {
+        let tag = self
+            .mac(sender)
+            .expect("Could not find own key in the key file.")
+            .chain_update(&bytes[..])
+            .finalize()
+            .into_bytes();
+        bytes.extend_from_slice(&tag);
+    }
+
+    // Whether the tag of the bytes was computed with the key of the
+    // sender. The comparison is in constant time.
+    pub fn verify(
+        &self,
+        sender: NodeId,
+        bytes: &[u8],
+        tag: &[u8],
+    ) -> bool {
+        match self.mac(sender) {
+            Some(mac) => mac.chain_update(bytes).verify_slice(tag).is_ok(),
+            None => false,
+        }
+    }
+
+    fn mac(&self, node_id: NodeId) -> Option<HmacSha256> {
+        self.keys.get(&node_id).map(|key| {
+            HmacSha256::new_from_slice(key)
+                .expect("HMAC accepts keys of any size.")
+        })
+    }
+}
+
+// Splits authenticated bytes into the message and its tag.
+pub fn split_tag(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    if bytes.len() < TAG_SIZE {
+        None
+    } else {
+        Some(bytes.split_at(bytes.len() - TAG_SIZE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> Keys {
+        Keys::from_string(
+            "# Test keys\n1,000102030405060708090a0b0c0d0e0f\n\n2,101112131415161718191a1b1c1d1e1f\n",
+        )
+    }
+
+    #[test]
+    fn test_that_a_tagged_message_is_verified() {
+        let keys = keys();
+        let mut bytes = b"message".to_vec();
+        keys.append_tag(1, &mut bytes);
+
+        assert_eq!(bytes.len(), b"message".len() + TAG_SIZE);
+        let (message, tag) = split_tag(&bytes).unwrap();
+        assert_eq!(message, b"message");
+        assert!(keys.verify(1, message, tag));
+    }
+
+    #[test]
+    fn test_that_a_modified_message_is_not_verified() {
+        let keys = keys();
+        let mut bytes = b"message".to_vec();
+        keys.append_tag(1, &mut bytes);
+        bytes[0] = b'M';
+
+        let (message, tag) = split_tag(&bytes).unwrap();
+        assert!(!keys.verify(1, message, tag));
+    }
+
+    #[test]
+    fn test_that_a_tag_with_the_key_of_another_node_is_not_verified() {
+        let keys = keys();
+        let mut bytes = b"message".to_vec();
+        keys.append_tag(2, &mut bytes);
+
+        let (message, tag) = split_tag(&bytes).unwrap();
+        assert!(!keys.verify(1, message, tag));
+        assert!(!keys.verify(3, message, tag));
+    }
+
+    #[test]
+    fn test_that_bytes_shorter_than_a_tag_have_no_tag() {
+        assert_eq!(split_tag(&[0; TAG_SIZE - 1]), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_that_short_keys_are_rejected() {
+        Keys::from_string("1,0001020304\n");
+    }
+}
@@ -0,0 +1,465 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{
+    IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream,
+};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use commons::client_protocol::{Request, Response};
+
+use crate::abd_node::Aborted;
+use crate::mediator::{Med, WriteError};
+
+// Besides the value, a request line only has the command.
+const MAX_REQUEST_OVERHEAD: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct ClientEndpointSettings {
+    pub timeout: Duration,
+    pub max_connections: usize,
+    pub max_value_size: usize,
+    pub accepts_writes: bool,
+}
+
+type Connections = Arc<Mutex<Vec<(TcpStream, JoinHandle<()>)>>>;
+
+/*
+A TCP endpoint that external processes can read and write the
+register through, with the line-based protocol in
+commons::client_protocol. Each connection has its own thread, so the
+requests of different connections are done concurrently, while the
+requests of one connection are done in order. The AbdNode still does
+one read and one write at a time.
+
+Each operation is done on a thread of its own, so that a request that
+takes longer than the timeout can get an error. The operation is not
+stopped, since a quorum access can't be undone, and a write that timed
+out may still take effect. An operation that timed out still counts
+until it ends, and at most max_connections operations are in progress
+at once, so that a node that can't reach a majority doesn't pile up
+threads. Only the writer accepts writes, since the register has a
+single writer.
+
+The endpoint only listens on the loopback interface, since it isn't
+authenticated.
+*/
+pub struct ClientEndpoint {
+    socket_addr: SocketAddr,
+    shutting_down: Arc<AtomicBool>,
+    accept_thread: JoinHandle<()>,
+    connections: Connections,
+}
+
+impl ClientEndpoint {
+    pub fn start<M: Med>(
+        socket_addr: SocketAddr,
+        settings: ClientEndpointSettings,
+        mediator: Arc<M>,
+    ) -> ClientEndpoint {
+        let listener = TcpListener::bind(socket_addr)
+            .expect("Could not create the client endpoint listener.");
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let connections = Arc::new(Mutex::new(Vec::new()));
+
+        let accept_thread_shutting_down = Arc::clone(&shutting_down);
+        let accept_thread_connections = Arc::clone(&connections);
+        let accept_thread = thread::spawn(move || {
+            accept_loop(
+                listener,
+                settings,
+                accept_thread_shutting_down,
+                accept_thread_connections,
+                mediator,
+            );
+        });
+
+        ClientEndpoint {
+            socket_addr: socket_addr,
+            shutting_down: shutting_down,
+            accept_thread: accept_thread,
+            connections: connections,
+        }
+    }
+
+    // Stops accepting connections, closes the open ones and joins
+    // their threads. Operations that are still in progress are left
+    // to the mediator, which aborts them when it shuts down.
+    pub fn shutdown(self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        let loopback_socket_addr = SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            self.socket_addr.port(),
+        );
+        let _ = TcpStream::connect(loopback_socket_addr);
+        self.accept_thread
+            .join()
+            .expect("The client endpoint accept thread panicked.");
+
+        let connections =
+            std::mem::take(&mut *self.connections.lock().unwrap());
+        for (stream, connection_thread) in connections {
+            let _ = stream.shutdown(Shutdown::Both);
+            connection_thread
+                .join()
+                .expect("A client connection thread panicked.");
+        }
+    }
+}
+
+fn accept_loop<M: Med>(
+    listener: TcpListener,
+    settings: ClientEndpointSettings,
+    shutting_down: Arc<AtomicBool>,
+    connections: Connections,
+    mediator: Arc<M>,
+) {
+    let number_of_connections = Arc::new(AtomicUsize::new(0));
+    let operations_in_progress = Arc::new(AtomicUsize::new(0));
+
+    loop {
+        let stream = listener.accept();
+        if shutting_down.load(Ordering::SeqCst) {
+            return;
+        }
+        let mut stream = match stream {
+            Ok((stream, _)) => stream,
+            Err(_) => continue,
+        };
+
+        if number_of_connections.load(Ordering::SeqCst)
+            >= settings.max_connections
+        {
+            let response =
+                Response::Error("Too many connections.".to_string());
+            let _ = writeln!(stream, "{}", response.to_line());
+            continue;
+        }
+        let stream_clone = match stream.try_clone() {
+            Ok(stream_clone) => stream_clone,
+            Err(_) => continue,
+        };
+
+        number_of_connections.fetch_add(1, Ordering::SeqCst);
+        let connection_settings = settings.clone();
+        let connection_mediator = Arc::clone(&mediator);
+        let connection_number_of_connections =
+            Arc::clone(&number_of_connections);
+        let connection_operations_in_progress =
+            Arc::clone(&operations_in_progress);
+        let connection_thread = thread::spawn(move || {
+            serve_connection(
+                stream,
+                &connection_settings,
+                &connection_operations_in_progress,
+                connection_mediator,
+            );
+            connection_number_of_connections
+                .fetch_sub(1, Ordering::SeqCst);
+        });
+
+        let mut connections = connections.lock().unwrap();
+        connections.retain(|(_, connection_thread)| {
+            !connection_thread.is_finished()
+        });
+        connections.push((stream_clone, connection_thread));
+    }
+}
+
+// Serves requests until the connection is closed. A line that is too
+// long or not utf-8 gets an error and closes the connection.
+fn serve_connection<M: Med>(
+    stream: TcpStream,
+    settings: &ClientEndpointSettings,
+    operations_in_progress: &Arc<AtomicUsize>,
+    mediator: Arc<M>,
+) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    let mut reader = BufReader::new(stream);
+    let max_line_length = settings.max_value_size + MAX_REQUEST_OVERHEAD;
+
+    loop {
+        let (response, close) =
+            match read_request_line(&mut reader, max_line_length) {
+                Ok(Some(line)) => match Request::from_line(&line) {
+                    Ok(request) => (
+                        response_to(
+                            request,
+                            settings,
+                            operations_in_progress,
+                            &mediator,
+                        ),
+                        false,
+                    ),
+                    Err(reason) => (Response::Error(reason), false),
+                },
+                Ok(None) => return,
+                Err(error) => (Response::Error(error.to_string()), true),
+            };
+
+        if writeln!(writer, "{}", response.to_line()).is_err() || close {
+            return;
+        }
+    }
+}
+
+// Returns None when the connection is closed, without the line break
+// at the end of the line.
+pub fn read_request_line<R: BufRead>(
+    reader: &mut R,
+    max_line_length: usize,
+) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    let length = reader
+        .by_ref()
+        .take(max_line_length as u64 + 1)
+        .read_line(&mut line)?;
+    if length == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') && length > max_line_length {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The request is too long.",
+        ));
+    }
+
+    let line_without_break = line.trim_end_matches(&['\r', '\n'][..]);
+    Ok(Some(line_without_break.to_string()))
+}
+
+fn response_to<M: Med>(
+    request: Request,
+    settings: &ClientEndpointSettings,
+    operations_in_progress: &Arc<AtomicUsize>,
+    mediator: &Arc<M>,
+) -> Response {
+    if let Request::Put(value) = &request {
+        if !settings.accepts_writes {
+            return Response::Error(
+                "This node is not the writer.".to_string(),
+            );
+        }
+        if value.len() > settings.max_value_size {
+            return Response::Error(format!(
+                "The value is larger than the max value size of {} bytes.",
+                settings.max_value_size
+            ));
+        }
+    }
+
+    let previous_operations_in_progress =
+        operations_in_progress.fetch_add(1, Ordering::SeqCst);
+    if previous_operations_in_progress >= settings.max_connections {
+        operations_in_progress.fetch_sub(1, Ordering::SeqCst);
+        return Response::Error(
+            "Too many operations in progress.".to_string(),
+        );
+    }
+
+    let (response_tx, response_rx) = mpsc::channel();
+    let operation_mediator = Arc::clone(mediator);
+    let operation_operations_in_progress =
+        Arc::clone(operations_in_progress);
+    thread::spawn(move || {
+        let response = match request {
+            Request::Get => operation_mediator.read().map(Response::Value),
+            Request::GetVersioned => operation_mediator
+                .read_versioned()
+                .map(|(timestamp, value)| {
+                    Response::Versioned(timestamp, value)
+                }),
+            Request::Put(value) => match operation_mediator.write(value) {
+                Ok(()) => Ok(Response::Ok),
+                Err(WriteError::Aborted) => Err(Aborted),
+                Err(error) => Ok(Response::Error(error.to_string())),
+            },
+        };
+        let response = response.unwrap_or_else(|Aborted| {
+            Response::Error("The node is shutting down.".to_string())
+        });
+        operation_operations_in_progress.fetch_sub(1, Ordering::SeqCst);
+        let _ = response_tx.send(response);
+    });
+
+    match response_rx.recv_timeout(settings.timeout) {
+        Ok(response) => response,
+        Err(_) => Response::Error(format!(
+            "Timed out after {} ms.",
+            settings.timeout.as_millis()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::io::Cursor;
+
+    use commons::types::NodeId;
+
+    use crate::abd_node::tests::MockMediator;
+    use crate::mediator::Mediator;
+
+    use super::*;
+
+    fn settings(timeout: Duration) -> ClientEndpointSettings {
+        ClientEndpointSettings {
+            timeout: timeout,
+            max_connections: 1,
+            max_value_size: 10,
+            accepts_writes: true,
+        }
+    }
+
+    fn free_socket_addr() -> SocketAddr {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    struct Connection {
+        writer: TcpStream,
+        reader: BufReader<TcpStream>,
+    }
+
+    impl Connection {
+        fn open(socket_addr: SocketAddr) -> Connection {
+            let writer = TcpStream::connect(socket_addr).unwrap();
+            let reader = BufReader::new(writer.try_clone().unwrap());
+            Connection {
+                writer: writer,
+                reader: reader,
+            }
+        }
+
+        fn request(&mut self, line: &str) -> Response {
+            writeln!(self.writer, "{}", line).unwrap();
+            let mut response_line = String::new();
+            self.reader.read_line(&mut response_line).unwrap();
+            Response::from_line(response_line.trim_end()).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_that_a_written_value_is_read() {
+        let mediator = MockMediator::new_with_loopback(1);
+        let socket_addr = free_socket_addr();
+        let endpoint = ClientEndpoint::start(
+            socket_addr,
+            settings(Duration::from_secs(5)),
+            Arc::clone(&mediator),
+        );
+        let mut connection = Connection::open(socket_addr);
+
+        assert_eq!(connection.request("PUT Haskell"), Response::Ok);
+        assert_eq!(
+            connection.request("GET"),
+            Response::Value("Haskell".to_string())
+        );
+        assert_eq!(
+            connection.request("GET_VERSIONED"),
+            Response::Versioned(1, "Haskell".to_string())
+        );
+
+        drop(connection);
+        endpoint.shutdown();
+    }
+
+    #[test]
+    fn test_that_a_value_larger_than_the_max_is_not_written() {
+        let mediator = MockMediator::new_with_loopback(1);
+        let socket_addr = free_socket_addr();
+        let endpoint = ClientEndpoint::start(
+            socket_addr,
+            settings(Duration::from_secs(5)),
+            Arc::clone(&mediator),
+        );
+        let mut connection = Connection::open(socket_addr);
+
+        match connection.request("PUT 12345678901") {
+            Response::Error(reason) => assert!(reason.contains("max")),
+            response => panic!("Unexpected response {:?}", response),
+        }
+        assert_eq!(mediator.run_result().write_ops, 0);
+        assert_eq!(connection.request("PUT 1234567890"), Response::Ok);
+
+        drop(connection);
+        endpoint.shutdown();
+    }
+
+    #[test]
+    fn test_that_an_operation_without_a_majority_times_out() {
+        let node_ids: HashSet<NodeId> =
+            vec![1, 2, 3].into_iter().collect();
+        let mediator = MockMediator::new(1, node_ids);
+        let socket_addr = free_socket_addr();
+        let endpoint = ClientEndpoint::start(
+            socket_addr,
+            settings(Duration::from_millis(100)),
+            Arc::clone(&mediator),
+        );
+        let mut connection = Connection::open(socket_addr);
+
+        match connection.request("PUT Haskell") {
+            Response::Error(reason) => {
+                assert!(reason.contains("Timed out"))
+            }
+            response => panic!("Unexpected response {:?}", response),
+        }
+        // The write that timed out is still in progress.
+        match connection.request("GET") {
+            Response::Error(reason) => {
+                assert!(reason.contains("Too many operations"))
+            }
+            response => panic!("Unexpected response {:?}", response),
+        }
+
+        mediator.abd_node().abort();
+        drop(connection);
+        endpoint.shutdown();
+    }
+
+    #[test]
+    fn test_that_request_lines_are_read_without_line_breaks() {
+        let mut reader = Cursor::new(b"GET\r\nPUT Val: 1\nGET".to_vec());
+
+        assert_eq!(
+            read_request_line(&mut reader, 100).unwrap(),
+            Some("GET".to_string())
+        );
+        assert_eq!(
+            read_request_line(&mut reader, 100).unwrap(),
+            Some("PUT Val: 1".to_string())
+        );
+        assert_eq!(
+            read_request_line(&mut reader, 100).unwrap(),
+            Some("GET".to_string())
+        );
+        assert_eq!(read_request_line(&mut reader, 100).unwrap(), None);
+    }
+
+    #[test]
+    fn test_that_a_too_long_request_line_is_an_error() {
+        let mut reader = Cursor::new(b"PUT 123456789\n".to_vec());
+        assert!(read_request_line(&mut reader, 10).is_err());
+
+        let mut reader = Cursor::new(b"PUT 12345\n".to_vec());
+        assert!(read_request_line(&mut reader, 10).is_ok());
+    }
+
+    #[test]
+    fn test_that_a_request_line_that_is_not_utf8_is_an_error() {
+        let mut reader =
+            Cursor::new(vec![b'P', b'U', b'T', b' ', 0xff, b'\n']);
+        assert!(read_request_line(&mut reader, 100).is_err());
+    }
+}
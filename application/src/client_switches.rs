@@ -0,0 +1,143 @@
+use std::sync::{Condvar, Mutex};
+
+struct Switches {
+    reading: bool,
+    writing: bool,
+    shutting_down: bool,
+}
+
+/*
+Whether the client threads in main.rs read and write, which can be
+changed through the admin endpoint while the node runs. A client
+thread that is switched off waits until it is switched on again, or
+until the node shuts down.
+
+Since the register has a single writer, only the node that was started
+with --write can be switched to writing, and it can't be switched to
+reading, since a node doesn't read and write at the same time.
+*/
+pub struct ClientSwitches {
+    is_writer: bool,
+    switches: Mutex<Switches>,
+    changed: Condvar,
+}
+
+impl ClientSwitches {
+    pub fn new(is_writer: bool, reading: bool) -> ClientSwitches {
+        ClientSwitches {
+            is_writer: is_writer,
+            switches: Mutex::new(Switches {
+                reading: reading && !is_writer,
+                writing: is_writer,
+                shutting_down: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    pub fn is_writer(&self) -> bool {
+        self.is_writer
+    }
+
+    pub fn reading(&self) -> bool {
+        self.switches.lock().unwrap().reading
+    }
+
+    pub fn writing(&self) -> bool {
+        self.switches.lock().unwrap().writing
+    }
+
+    pub fn set_reading(&self, reading: bool) -> Result<(), String> {
+        if reading && self.is_writer {
+            return Err("The writer can't read.".to_string());
+        }
+        self.switches.lock().unwrap().reading = reading;
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    pub fn set_writing(&self, writing: bool) -> Result<(), String> {
+        if writing && !self.is_writer {
+            return Err("This node is not the writer.".to_string());
+        }
+        self.switches.lock().unwrap().writing = writing;
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    // Blocks while reading is switched off. Returns false if the node
+    // is shutting down.
+    pub fn wait_until_reading(&self) -> bool {
+        self.wait_until(|switches| switches.reading)
+    }
+
+    // Blocks while writing is switched off. Returns false if the node
+    // is shutting down.
+    pub fn wait_until_writing(&self) -> bool {
+        self.wait_until(|switches| switches.writing)
+    }
+
+    pub fn shutdown(&self) {
+        self.switches.lock().unwrap().shutting_down = true;
+        self.changed.notify_all();
+    }
+
+    fn wait_until(&self, switched_on: impl Fn(&Switches) -> bool) -> bool {
+        let switches = self
+            .changed
+            .wait_while(self.switches.lock().unwrap(), |switches| {
+                !switched_on(switches) && !switches.shutting_down
+            })
+            .unwrap();
+        !switches.shutting_down
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_that_only_the_writer_can_write() {
+        let reader = ClientSwitches::new(false, true);
+        assert!(reader.set_writing(true).is_err());
+        assert!(reader.set_reading(false).is_ok());
+        assert!(!reader.reading());
+
+        let writer = ClientSwitches::new(true, false);
+        assert!(writer.writing());
+        assert!(writer.set_reading(true).is_err());
+        assert!(writer.set_writing(false).is_ok());
+        assert!(!writer.writing());
+    }
+
+    #[test]
+    fn test_that_a_switched_off_client_waits_until_switched_on() {
+        let switches = Arc::new(ClientSwitches::new(false, false));
+        let thread_switches = Arc::clone(&switches);
+        let handle =
+            thread::spawn(move || thread_switches.wait_until_reading());
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(!handle.is_finished());
+
+        switches.set_reading(true).unwrap();
+        assert!(handle.join().unwrap());
+    }
+
+    #[test]
+    fn test_that_a_switched_off_client_stops_at_shutdown() {
+        let switches = Arc::new(ClientSwitches::new(true, false));
+        switches.set_writing(false).unwrap();
+        let thread_switches = Arc::clone(&switches);
+        let handle =
+            thread::spawn(move || thread_switches.wait_until_writing());
+
+        switches.shutdown();
+        assert!(!handle.join().unwrap());
+    }
+}
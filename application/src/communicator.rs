@@ -1,75 +1,145 @@
-use std::collections::HashMap;
-use std::net::UdpSocket;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::str;
-use std::sync::{Arc, Weak};
-use std::thread;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
 
-use commons::types::NodeId;
+use commons::types::{Int, NodeId};
 
+use crate::encryption::Cipher;
 use crate::mediator::Med;
+use crate::partition_schedule::PartitionSchedule;
+use crate::transport::{Transport, TransportError};
 
+// Sends messages to other nodes with the transport of each node. The
+// transports deliver received messages to the mediator themselves,
+// which then decrypts them with the communicator, if the cluster is
+// encrypted. All transport errors are counted, but so that a node
+// that is down doesn't flood the log, a failing receiver is only
+// logged when sending to it starts to fail, and each kind of receive
+// error, and the evictions from each sender, only the first time.
 pub struct Communicator<M> {
-    socket: UdpSocket,
-    socket_addrs: HashMap<NodeId, SocketAddr>,
+    transports: HashMap<NodeId, Arc<dyn Transport>>,
+    cipher: Option<Cipher>,
+    partition_schedule: PartitionSchedule,
+    partition_start: Instant,
+    failing_receivers: Mutex<HashSet<NodeId>>,
+    logged_receive_errors: Mutex<HashSet<io::ErrorKind>>,
+    logged_evicting_senders: Mutex<HashSet<SocketAddr>>,
     mediator: Weak<M>,
 }
 
 impl<M: Med> Communicator<M> {
     pub fn new(
-        own_socket_addr: SocketAddr,
-        socket_addrs: HashMap<NodeId, SocketAddr>,
+        transports: HashMap<NodeId, Arc<dyn Transport>>,
+        cipher: Option<Cipher>,
+        partition_schedule: PartitionSchedule,
+        partition_start: Instant,
         mediator: Weak<M>,
     ) -> Arc<Communicator<M>> {
-        let own_socket_addr = SocketAddr::new(
-            IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
-            own_socket_addr.port(),
-        );
-        let socket = UdpSocket::bind(own_socket_addr)
-            .expect("Could not create socket.");
-
         let communicator = Communicator {
-            socket: socket,
-            socket_addrs: socket_addrs,
+            transports: transports,
+            cipher: cipher,
+            partition_schedule: partition_schedule,
+            partition_start: partition_start,
+            failing_receivers: Mutex::new(HashSet::new()),
+            logged_receive_errors: Mutex::new(HashSet::new()),
+            logged_evicting_senders: Mutex::new(HashSet::new()),
             mediator: mediator,
         };
-        let communicator = Arc::new(communicator);
-        let recv_thread_communicator = Arc::clone(&communicator);
-        thread::spawn(move || {
-            recv_thread_communicator.recv_loop();
-        });
-        communicator
+        Arc::new(communicator)
     }
 
-    pub fn recv_loop(&self) {
-        loop {
-            let mut buf = [0; 4096];
+    fn mediator(&self) -> Arc<M> {
+        self.mediator
+            .upgrade()
+            .expect("Error upgrading mediator in Communicator")
+    }
 
-            let amt = self
-                .socket
-                .recv(&mut buf)
-                .expect("Error receiving from socket");
-            let json_string = str::from_utf8(&buf[0..amt])
-                .expect("Error converting bytes to utf8");
+    pub fn send_bytes_to(&self, bytes: &[u8], receiver_id: NodeId) {
+        if self.is_partitioned_from(receiver_id) {
+            self.mediator().run_result().injected_faults.partitioned += 1;
+            return;
+        }
+
+        let result = match self.transports.get(&receiver_id) {
+            Some(transport) => match &self.cipher {
+                Some(cipher) => {
+                    transport.send_to(&cipher.encrypt(bytes), receiver_id)
+                }
+                None => transport.send_to(bytes, receiver_id),
+            },
+            None => Err(TransportError::UnknownReceiver(receiver_id)),
+        };
 
-            self.mediator().json_received(json_string);
+        match result {
+            Ok(()) => {
+                self.failing_receivers.lock().unwrap().remove(&receiver_id);
+            }
+            Err(error) => self.transport_error(error),
         }
     }
 
-    fn mediator(&self) -> Arc<M> {
-        self.mediator
-            .upgrade()
-            .expect("Error upgrading mediator in Communicator")
+    pub fn transport_error(&self, error: TransportError) {
+        let mediator = self.mediator();
+        let first_of_its_kind = match &error {
+            TransportError::UnknownReceiver(receiver_id) => {
+                mediator.run_result().network_errors.unknown_receiver += 1;
+                self.failing_receivers.lock().unwrap().insert(*receiver_id)
+            }
+            TransportError::Send(receiver_id, _) => {
+                mediator.run_result().network_errors.send_failed += 1;
+                self.failing_receivers.lock().unwrap().insert(*receiver_id)
+            }
+            TransportError::Receive(io_error) => {
+                mediator.run_result().network_errors.receive_failed += 1;
+                self.logged_receive_errors
+                    .lock()
+                    .unwrap()
+                    .insert(io_error.kind())
+            }
+            TransportError::Evicted(sender, number_of_messages) => {
+                mediator
+                    .run_result()
+                    .network_errors
+                    .evicted_partial_messages +=
+                    *number_of_messages as Int;
+                self.logged_evicting_senders
+                    .lock()
+                    .unwrap()
+                    .insert(*sender)
+            }
+        };
+
+        if first_of_its_kind {
+            mediator.log(error.to_string());
+        }
+    }
+
+    // Stops the transports from receiving. A transport that is shared
+    // by several nodes is shut down for each of them, which is fine,
+    // since shutting it down again does nothing.
+    pub fn shutdown(&self) {
+        for transport in self.transports.values() {
+            transport.shutdown();
+        }
+    }
+
+    // Returns None if the bytes could not be decrypted.
+    pub fn decrypt<'a>(&self, bytes: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+        match &self.cipher {
+            Some(cipher) => cipher.decrypt(bytes).map(Cow::Owned),
+            None => Some(Cow::Borrowed(bytes)),
+        }
     }
 
-    pub fn send_json_to(&self, json: &str, receiver_id: NodeId) {
-        let bytes = json.as_bytes();
-        let dst_socket_addr = self
-            .socket_addrs
-            .get(&receiver_id)
-            .expect("Could not find receiver among the socket addresses");
-        self.socket
-            .send_to(bytes, dst_socket_addr)
-            .expect("Could not send on the socket");
+    fn is_partitioned_from(&self, receiver_id: NodeId) -> bool {
+        !self.partition_schedule.is_empty()
+            && self.partition_schedule.blocks(
+                self.partition_start.elapsed(),
+                self.mediator().node_id(),
+                receiver_id,
+            )
     }
 }
@@ -2,19 +2,39 @@ use std::collections::HashSet;
 
 use commons::types::{Int, NodeId};
 
+use crate::authentication::Keys;
+use crate::retransmission::RetransmissionPolicy;
+use crate::settings::Settings;
+use crate::wire_format::WireFormat;
+
 pub struct ConfigurationManager {
     node_id: NodeId,
     node_ids: HashSet<NodeId>,
+    cluster_id: String,
+    wire_format: WireFormat,
+    keys: Option<Keys>,
+    retransmission_policy: RetransmissionPolicy,
+    fastest_quorum_first: bool,
+    record_latencies: bool,
 }
 
 impl ConfigurationManager {
     pub fn new(
         node_id: NodeId,
         node_ids: HashSet<NodeId>,
+        settings: &Settings,
     ) -> ConfigurationManager {
         ConfigurationManager {
             node_id: node_id,
             node_ids: node_ids,
+            cluster_id: settings.cluster_id().to_string(),
+            wire_format: settings.wire_format(),
+            keys: settings.keys().cloned(),
+            retransmission_policy: settings
+                .retransmission_policy()
+                .clone(),
+            fastest_quorum_first: settings.fastest_quorum_first(),
+            record_latencies: settings.record_latencies(),
         }
     }
 
@@ -29,4 +49,28 @@ impl ConfigurationManager {
     pub fn number_of_nodes(&self) -> Int {
         self.node_ids.len() as Int
     }
+
+    pub fn cluster_id(&self) -> &str {
+        &self.cluster_id
+    }
+
+    pub fn wire_format(&self) -> WireFormat {
+        self.wire_format
+    }
+
+    pub fn keys(&self) -> Option<&Keys> {
+        self.keys.as_ref()
+    }
+
+    pub fn retransmission_policy(&self) -> &RetransmissionPolicy {
+        &self.retransmission_policy
+    }
+
+    pub fn fastest_quorum_first(&self) -> bool {
+        self.fastest_quorum_first
+    }
+
+    pub fn record_latencies(&self) -> bool {
+        self.record_latencies
+    }
 }
@@ -0,0 +1,164 @@
+use std::fmt;
+use std::fs;
+
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::{Key, KeyInit, XChaCha20Poly1305, XNonce};
+
+pub const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 24;
+
+/*
+Encrypts and authenticates all bytes sent between the nodes of a
+cluster with XChaCha20-Poly1305 and a key that is shared by the whole
+cluster. The key is read from an encryption key file, which has a
+single line with the key as 64 hex characters. Empty lines and lines
+starting with # are ignored.
+
+Each encrypted message starts with a random 24 byte nonce, followed
+by the ciphertext and the 16 byte Poly1305 tag. Since the nonces are
+this large, random nonces don't repeat in practice, so the nodes
+don't have to coordinate nonces, and UDP messages can be lost and
+reordered freely.
+*/
+#[derive(Clone)]
+pub struct Cipher {
+    aead: XChaCha20Poly1305,
+}
+
+impl Cipher {
+    pub fn new(key: &[u8]) -> Cipher {
+        if key.len() != KEY_SIZE {
+            panic!(
+                "The encryption key is {} bytes, but must be {} bytes.",
+                key.len(),
+                KEY_SIZE
+            );
+        }
+
+        Cipher {
+            aead: XChaCha20Poly1305::new(Key::from_slice(key)),
+        }
+    }
+
+    pub fn from_file(file_path: &str) -> Cipher {
+        let string = fs::read_to_string(file_path)
+            .expect("Unable to read the encryption key file.");
+        Cipher::from_string(&string)
+    }
+
+    pub fn from_string(string: &str) -> Cipher {
+        let lines: Vec<&str> = string
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+        if lines.len() != 1 {
+            panic!("The encryption key file must have exactly one key.");
+        }
+
+        let key = hex::decode(lines[0]).expect(
+            "Could not parse the key in the encryption key file as hex.",
+        );
+        Cipher::new(&key)
+    }
+
+    pub fn encrypt(&self, bytes: &[u8]) -> Vec<u8> {
+        let nonce: [u8; NONCE_SIZE] = rand::random();
+        let ciphertext = self
+            .aead
+            .encrypt(XNonce::from_slice(&nonce), bytes)
+            .expect("Could not encrypt a message.");
+
+        let mut encrypted =
+            Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+        encrypted.extend_from_slice(&nonce);
+        encrypted.extend_from_slice(&ciphertext);
+        encrypted
+    }
+
+    // Returns None if the bytes were not encrypted with the same key,
+    // or have been modified.
+    pub fn decrypt(&self, bytes: &[u8]) -> Option<Vec<u8>> {
+        if bytes.len() < NONCE_SIZE {
+            return None;
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_SIZE);
+        self.aead
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .ok()
+    }
+}
+
+// The key is left out, so that it isn't printed.
+impl fmt::Debug for Cipher {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(formatter, "Cipher")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generated_key() -> [u8; KEY_SIZE] {
+        rand::random()
+    }
+
+    #[test]
+    fn test_that_encrypted_bytes_are_decrypted() {
+        let cipher = Cipher::new(&generated_key());
+
+        let encrypted = cipher.encrypt(b"Haskell");
+
+        assert_ne!(&encrypted[NONCE_SIZE..], b"Haskell");
+        assert_eq!(cipher.decrypt(&encrypted), Some(b"Haskell".to_vec()));
+    }
+
+    #[test]
+    fn test_that_the_same_bytes_are_encrypted_differently() {
+        let cipher = Cipher::new(&generated_key());
+
+        assert_ne!(cipher.encrypt(b"Haskell"), cipher.encrypt(b"Haskell"));
+    }
+
+    #[test]
+    fn test_that_bytes_encrypted_with_another_key_are_not_decrypted() {
+        let cipher = Cipher::new(&generated_key());
+        let other_cipher = Cipher::new(&generated_key());
+
+        let encrypted = other_cipher.encrypt(b"Haskell");
+
+        assert_eq!(cipher.decrypt(&encrypted), None);
+    }
+
+    #[test]
+    fn test_that_modified_bytes_are_not_decrypted() {
+        let cipher = Cipher::new(&generated_key());
+
+        let mut encrypted = cipher.encrypt(b"Haskell");
+        let last = encrypted.len() - 1;
+        encrypted[last] ^= 1;
+
+        assert_eq!(cipher.decrypt(&encrypted), None);
+        assert_eq!(cipher.decrypt(&encrypted[..NONCE_SIZE - 1]), None);
+    }
+
+    #[test]
+    fn test_that_the_key_is_read_from_a_key_file() {
+        let key = generated_key();
+        let cipher = Cipher::from_string(&format!(
+            "# Cluster key\n{}\n",
+            hex::encode(key)
+        ));
+
+        let encrypted = Cipher::new(&key).encrypt(b"Haskell");
+
+        assert_eq!(cipher.decrypt(&encrypted), Some(b"Haskell".to_vec()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_that_a_key_of_the_wrong_size_is_rejected() {
+        Cipher::from_string("000102030405060708090a0b0c0d0e0f\n");
+    }
+}
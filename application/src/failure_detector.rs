@@ -0,0 +1,232 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use commons::types::NodeId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerStatus {
+    Alive,
+    Suspected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeerHealth {
+    pub status: PeerStatus,
+    pub round_trip_time: Option<Duration>,
+}
+
+impl PeerHealth {
+    pub fn unknown() -> PeerHealth {
+        PeerHealth {
+            status: PeerStatus::Alive,
+            round_trip_time: None,
+        }
+    }
+
+    // Alive nodes come before suspected ones, and among them, the
+    // nodes with the shortest round trip times come first. Nodes
+    // without a round trip time come last.
+    pub fn cmp_fastest_first(&self, other: &PeerHealth) -> Ordering {
+        let suspected = self.status == PeerStatus::Suspected;
+        let other_suspected = other.status == PeerStatus::Suspected;
+        suspected.cmp(&other_suspected).then_with(|| {
+            match (self.round_trip_time, other.round_trip_time) {
+                (Some(time), Some(other_time)) => time.cmp(&other_time),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }
+        })
+    }
+}
+
+struct Peer {
+    last_heard_from: Duration,
+    round_trip_time: Option<Duration>,
+    status: PeerStatus,
+}
+
+/*
+Keeps track of which of the other nodes are alive. Every node sends
+heartbeats to the others, and a node is suspected to have failed if
+nothing has been received from it for the suspicion timeout. Any
+message counts, not only heartbeat acks. A suspected node becomes
+alive again as soon as a message from it is received, so a suspicion
+is only a hint, and is never used to decide whether an operation is
+done.
+
+The round trip times are measured with the heartbeats, and smoothed
+like in RetransmissionPolicy. The times are from Mediator::now().
+*/
+pub struct FailureDetector {
+    suspicion_timeout: Duration,
+    peers: Mutex<HashMap<NodeId, Peer>>,
+}
+
+impl FailureDetector {
+    // The nodes are regarded as heard from at the start, so that they
+    // get the suspicion timeout to start.
+    pub fn new(
+        node_ids: &HashSet<NodeId>,
+        suspicion_timeout: Duration,
+        now: Duration,
+    ) -> FailureDetector {
+        let peers = node_ids
+            .iter()
+            .map(|&node_id| {
+                let peer = Peer {
+                    last_heard_from: now,
+                    round_trip_time: None,
+                    status: PeerStatus::Alive,
+                };
+                (node_id, peer)
+            })
+            .collect();
+
+        FailureDetector {
+            suspicion_timeout: suspicion_timeout,
+            peers: Mutex::new(peers),
+        }
+    }
+
+    // Returns true if the node was suspected, and is now alive again.
+    pub fn heard_from(
+        &self,
+        node_id: NodeId,
+        round_trip_time: Option<Duration>,
+        now: Duration,
+    ) -> bool {
+        let mut peers = self.peers.lock().unwrap();
+        let peer = match peers.get_mut(&node_id) {
+            Some(peer) => peer,
+            None => return false,
+        };
+
+        peer.last_heard_from = now;
+        if let Some(round_trip_time) = round_trip_time {
+            peer.round_trip_time = Some(match peer.round_trip_time {
+                Some(smoothed) => smoothed * 7 / 8 + round_trip_time / 8,
+                None => round_trip_time,
+            });
+        }
+
+        let was_suspected = peer.status == PeerStatus::Suspected;
+        peer.status = PeerStatus::Alive;
+        was_suspected
+    }
+
+    // Suspects the nodes that haven't been heard from for the
+    // suspicion timeout, and returns the ones that weren't already
+    // suspected, sorted.
+    pub fn suspect_silent_nodes(&self, now: Duration) -> Vec<NodeId> {
+        let mut newly_suspected = Vec::new();
+        for (&node_id, peer) in self.peers.lock().unwrap().iter_mut() {
+            let silent = now
+                .checked_sub(peer.last_heard_from)
+                .is_some_and(|silence| silence >= self.suspicion_timeout);
+            if silent && peer.status == PeerStatus::Alive {
+                peer.status = PeerStatus::Suspected;
+                newly_suspected.push(node_id);
+            }
+        }
+        newly_suspected.sort();
+        newly_suspected
+    }
+
+    pub fn health(&self, node_id: NodeId) -> PeerHealth {
+        match self.peers.lock().unwrap().get(&node_id) {
+            Some(peer) => PeerHealth {
+                status: peer.status,
+                round_trip_time: peer.round_trip_time,
+            },
+            None => PeerHealth::unknown(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn failure_detector() -> FailureDetector {
+        let node_ids = vec![1, 2, 3].into_iter().collect();
+        FailureDetector::new(&node_ids, millis(1000), millis(0))
+    }
+
+    #[test]
+    fn test_that_nodes_are_alive_at_the_start() {
+        let failure_detector = failure_detector();
+
+        assert!(failure_detector.suspect_silent_nodes(millis(999)).is_empty());
+        assert_eq!(failure_detector.health(2), PeerHealth::unknown());
+    }
+
+    #[test]
+    fn test_that_silent_nodes_are_suspected_once() {
+        let failure_detector = failure_detector();
+        failure_detector.heard_from(2, None, millis(500));
+
+        assert_eq!(
+            failure_detector.suspect_silent_nodes(millis(1000)),
+            vec![1, 3]
+        );
+        assert!(failure_detector.suspect_silent_nodes(millis(1200)).is_empty());
+        assert_eq!(
+            failure_detector.suspect_silent_nodes(millis(1500)),
+            vec![2]
+        );
+        assert_eq!(failure_detector.health(1).status, PeerStatus::Suspected);
+    }
+
+    #[test]
+    fn test_that_a_suspected_node_is_alive_when_heard_from() {
+        let failure_detector = failure_detector();
+        failure_detector.suspect_silent_nodes(millis(1000));
+
+        assert!(failure_detector.heard_from(2, None, millis(1100)));
+        assert!(!failure_detector.heard_from(2, None, millis(1200)));
+        assert_eq!(failure_detector.health(2).status, PeerStatus::Alive);
+    }
+
+    #[test]
+    fn test_that_round_trip_times_are_smoothed() {
+        let failure_detector = failure_detector();
+
+        failure_detector.heard_from(2, Some(millis(80)), millis(10));
+        assert_eq!(failure_detector.health(2).round_trip_time, Some(millis(80)));
+
+        failure_detector.heard_from(2, Some(millis(160)), millis(20));
+        failure_detector.heard_from(2, None, millis(30));
+        assert_eq!(failure_detector.health(2).round_trip_time, Some(millis(90)));
+    }
+
+    #[test]
+    fn test_that_alive_and_fast_nodes_come_first() {
+        let fast = PeerHealth {
+            status: PeerStatus::Alive,
+            round_trip_time: Some(millis(1)),
+        };
+        let slow = PeerHealth {
+            status: PeerStatus::Alive,
+            round_trip_time: Some(millis(50)),
+        };
+        let suspected = PeerHealth {
+            status: PeerStatus::Suspected,
+            round_trip_time: Some(millis(1)),
+        };
+
+        let mut healths = vec![suspected, PeerHealth::unknown(), slow, fast];
+        healths.sort_by(|a, b| a.cmp_fastest_first(b));
+
+        assert_eq!(
+            healths,
+            vec![fast, slow, PeerHealth::unknown(), suspected]
+        );
+    }
+}
@@ -0,0 +1,603 @@
+use std::cmp::Ordering;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use commons::types::NodeId;
+
+use crate::communicator::Communicator;
+use crate::mediator::Med;
+
+// A reordered message is sent at the latest this long after it was
+// held back, if no other message is sent before that.
+const MAX_HOLD_BACK_TIME: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultSettings {
+    pub drop_probability: f64,
+    pub duplicate_probability: f64,
+    pub delay_probability: f64,
+    pub max_delay: Duration,
+    pub reorder_probability: f64,
+    pub seed: u64,
+}
+
+impl FaultSettings {
+    pub fn injects_faults(&self) -> bool {
+        self.drop_probability > 0.0
+            || self.duplicate_probability > 0.0
+            || self.delay_probability > 0.0
+            || self.reorder_probability > 0.0
+    }
+
+    fn fate(&self, rng: &mut StdRng) -> Fate {
+        if rng.gen_bool(self.drop_probability) {
+            return Fate::Drop;
+        }
+        if rng.gen_bool(self.reorder_probability) {
+            return Fate::Reorder;
+        }
+
+        let number_of_copies =
+            if rng.gen_bool(self.duplicate_probability) {
+                2
+            } else {
+                1
+            };
+
+        let mut delays = Vec::new();
+        for _ in 0..number_of_copies {
+            if rng.gen_bool(self.delay_probability) {
+                let max_delay = self.max_delay.as_micros() as u64;
+                delays.push(Some(Duration::from_micros(
+                    rng.gen_range(0, max_delay + 1),
+                )));
+            } else {
+                delays.push(None);
+            }
+        }
+
+        Fate::Transmit(delays)
+    }
+}
+
+// What happens to a single outgoing message. Transmit has one entry
+// per copy of the message, with the delay of that copy, if any.
+#[derive(Debug, Clone, PartialEq)]
+enum Fate {
+    Drop,
+    Reorder,
+    Transmit(Vec<Option<Duration>>),
+}
+
+struct DelayedMessage {
+    send_time: Instant,
+    sequence_number: u64,
+    // None for the held back message with the sequence number, which
+    // is sent then if it is still held back.
+    bytes: Option<Vec<u8>>,
+    receiver_id: NodeId,
+}
+
+impl PartialEq for DelayedMessage {
+    fn eq(&self, other: &DelayedMessage) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for DelayedMessage {}
+
+impl PartialOrd for DelayedMessage {
+    fn partial_cmp(&self, other: &DelayedMessage) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DelayedMessage {
+    fn cmp(&self, other: &DelayedMessage) -> Ordering {
+        (self.send_time, self.sequence_number)
+            .cmp(&(other.send_time, other.sequence_number))
+    }
+}
+
+struct HeldBackMessage {
+    sequence_number: u64,
+    bytes: Vec<u8>,
+    receiver_id: NodeId,
+}
+
+struct State {
+    rng: StdRng,
+    held_back_message: Option<HeldBackMessage>,
+    next_sequence_number: u64,
+}
+
+impl State {
+    fn take_sequence_number(&mut self) -> u64 {
+        let sequence_number = self.next_sequence_number;
+        self.next_sequence_number += 1;
+        sequence_number
+    }
+}
+
+/*
+Wraps the Communicator and injects faults into the outgoing messages,
+to test ABD under unreliable networks. A message can be dropped,
+duplicated, delayed or reordered. A reordered message is held back
+and sent right after the next message, so it arrives after a message
+that was sent later. If no message is sent within MAX_HOLD_BACK_TIME,
+it is sent anyway, so that it isn't lost when the node stops sending.
+Delayed messages, and held back messages that are due, are sent by a
+background thread.
+
+The faults are decided by a random number generator seeded with the
+seed plus the node id, so that different nodes inject different
+faults. Since the timing of the threads varies between runs, the
+same seed doesn't give the exact same run. Use the simulator for
+that.
+*/
+pub struct FaultInjector<M> {
+    settings: FaultSettings,
+    communicator: Arc<Communicator<M>>,
+    mediator: Weak<M>,
+    state: Arc<Mutex<State>>,
+    delayed_message_tx: Mutex<Option<Sender<DelayedMessage>>>,
+    delay_thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl<M: Med> FaultInjector<M> {
+    pub fn new(
+        settings: FaultSettings,
+        node_id: NodeId,
+        communicator: Arc<Communicator<M>>,
+        mediator: Weak<M>,
+    ) -> FaultInjector<M> {
+        let seed = settings.seed.wrapping_add(node_id as u64);
+        let state = Arc::new(Mutex::new(State {
+            rng: StdRng::seed_from_u64(seed),
+            held_back_message: None,
+            next_sequence_number: 0,
+        }));
+
+        let (delayed_message_tx, delayed_message_rx) = mpsc::channel();
+        let delay_thread_communicator = Arc::clone(&communicator);
+        let delay_thread_state = Arc::clone(&state);
+        let delay_thread = thread::spawn(move || {
+            delayed_send_loop(
+                delayed_message_rx,
+                delay_thread_communicator,
+                delay_thread_state,
+            );
+        });
+
+        FaultInjector {
+            settings: settings,
+            communicator: communicator,
+            mediator: mediator,
+            state: state,
+            delayed_message_tx: Mutex::new(Some(delayed_message_tx)),
+            delay_thread: Mutex::new(Some(delay_thread)),
+        }
+    }
+
+    // Stops the delay thread. The messages it still holds back are
+    // dropped, and so are messages that are delayed or reordered
+    // afterwards, unless another message is sent after them.
+    pub fn shutdown(&self) {
+        self.delayed_message_tx.lock().unwrap().take();
+        if let Some(delay_thread) = self.delay_thread.lock().unwrap().take()
+        {
+            delay_thread
+                .join()
+                .expect("The delay thread of the FaultInjector panicked.");
+        }
+    }
+
+    fn mediator(&self) -> Arc<M> {
+        self.mediator
+            .upgrade()
+            .expect("Error upgrading mediator in FaultInjector")
+    }
+
+    pub fn send_bytes_to(&self, bytes: &[u8], receiver_id: NodeId) {
+        // The state is unlocked before the messages are sent, so that a
+        // slow send doesn't block the other threads that send, and the
+        // delay thread.
+        let (fate, delayed_messages, previously_held_back_message) = {
+            let mut state = self.state.lock().unwrap();
+            let previously_held_back_message =
+                state.held_back_message.take();
+            let fate = self.settings.fate(&mut state.rng);
+            let delayed_messages =
+                hold_back_or_delay(&mut state, &fate, bytes, receiver_id);
+            (fate, delayed_messages, previously_held_back_message)
+        };
+
+        for delayed_message in delayed_messages.into_iter() {
+            self.send_to_delay_thread(delayed_message);
+        }
+
+        match fate {
+            Fate::Drop => {
+                self.mediator().run_result().injected_faults.dropped += 1;
+            }
+            Fate::Reorder => {
+                self.mediator().run_result().injected_faults.reordered +=
+                    1;
+            }
+            Fate::Transmit(delays) => {
+                if delays.len() > 1 {
+                    self.mediator().run_result().injected_faults.duplicated +=
+                        1;
+                }
+
+                for delay in delays.into_iter() {
+                    match delay {
+                        Some(_) => {
+                            self.mediator()
+                                .run_result()
+                                .injected_faults
+                                .delayed += 1;
+                        }
+                        None => {
+                            self.communicator.send_bytes_to(bytes, receiver_id)
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(held_back_message) = previously_held_back_message {
+            self.communicator.send_bytes_to(
+                &held_back_message.bytes,
+                held_back_message.receiver_id,
+            );
+        }
+    }
+
+    fn send_to_delay_thread(&self, delayed_message: DelayedMessage) {
+        if let Some(delayed_message_tx) =
+            self.delayed_message_tx.lock().unwrap().as_ref()
+        {
+            delayed_message_tx
+                .send(delayed_message)
+                .expect("The delay thread of the FaultInjector has stopped.");
+        }
+    }
+}
+
+// Holds back the message if it is reordered, and returns the messages
+// for the delay thread: the copies of the message that are delayed, or
+// the reminder to send the held back message.
+fn hold_back_or_delay(
+    state: &mut State,
+    fate: &Fate,
+    bytes: &[u8],
+    receiver_id: NodeId,
+) -> Vec<DelayedMessage> {
+    let now = Instant::now();
+    match fate {
+        Fate::Drop => Vec::new(),
+        Fate::Reorder => {
+            let sequence_number = state.take_sequence_number();
+            state.held_back_message = Some(HeldBackMessage {
+                sequence_number: sequence_number,
+                bytes: bytes.to_vec(),
+                receiver_id: receiver_id,
+            });
+            vec![DelayedMessage {
+                send_time: now + MAX_HOLD_BACK_TIME,
+                sequence_number: sequence_number,
+                bytes: None,
+                receiver_id: receiver_id,
+            }]
+        }
+        Fate::Transmit(delays) => delays
+            .iter()
+            .flatten()
+            .map(|delay| DelayedMessage {
+                send_time: now + *delay,
+                sequence_number: state.take_sequence_number(),
+                bytes: Some(bytes.to_vec()),
+                receiver_id: receiver_id,
+            })
+            .collect(),
+    }
+}
+
+fn delayed_send_loop<M: Med>(
+    delayed_message_rx: Receiver<DelayedMessage>,
+    communicator: Arc<Communicator<M>>,
+    state: Arc<Mutex<State>>,
+) {
+    let mut queue: BinaryHeap<Reverse<DelayedMessage>> = BinaryHeap::new();
+
+    loop {
+        let now = Instant::now();
+        while queue
+            .peek()
+            .is_some_and(|Reverse(message)| message.send_time <= now)
+        {
+            let Reverse(message) = queue.pop().unwrap();
+            let bytes = match message.bytes {
+                Some(bytes) => Some(bytes),
+                None => {
+                    take_held_back_message(&state, message.sequence_number)
+                }
+            };
+            if let Some(bytes) = bytes {
+                communicator.send_bytes_to(&bytes, message.receiver_id);
+            }
+        }
+
+        let received = match queue.peek() {
+            Some(Reverse(message)) => delayed_message_rx.recv_timeout(
+                message.send_time.saturating_duration_since(now),
+            ),
+            None => delayed_message_rx
+                .recv()
+                .map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match received {
+            Ok(message) => queue.push(Reverse(message)),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+// The bytes of the held back message with the sequence number, if it
+// hasn't already been sent after another message.
+fn take_held_back_message(
+    state: &Mutex<State>,
+    sequence_number: u64,
+) -> Option<Vec<u8>> {
+    let mut state = state.lock().unwrap();
+    let is_still_held_back = state
+        .held_back_message
+        .as_ref()
+        .is_some_and(|message| message.sequence_number == sequence_number);
+    if is_still_held_back {
+        state.held_back_message.take().map(|message| message.bytes)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use crate::abd_node::tests::MockMediator;
+    use crate::mediator::Mediator;
+    use crate::partition_schedule::PartitionSchedule;
+    use crate::transport::{Transport, TransportError};
+
+    use super::*;
+
+    // Records the messages that are sent through it.
+    struct RecordingTransport {
+        sent_messages: Mutex<Vec<Vec<u8>>>,
+    }
+
+    impl Transport for RecordingTransport {
+        fn send_to(
+            &self,
+            bytes: &[u8],
+            _receiver_id: NodeId,
+        ) -> Result<(), TransportError> {
+            self.sent_messages.lock().unwrap().push(bytes.to_vec());
+            Ok(())
+        }
+
+        fn shutdown(&self) {}
+    }
+
+    // Node 1 sends through the fault injector to node 2.
+    fn fault_injector(
+        settings: FaultSettings,
+    ) -> (
+        FaultInjector<MockMediator>,
+        Arc<MockMediator>,
+        Arc<RecordingTransport>,
+    ) {
+        let node_ids: HashSet<NodeId> = vec![1, 2].into_iter().collect();
+        let mediator = MockMediator::new(1, node_ids);
+        let transport = Arc::new(RecordingTransport {
+            sent_messages: Mutex::new(Vec::new()),
+        });
+        let mut transports: HashMap<NodeId, Arc<dyn Transport>> =
+            HashMap::new();
+        transports.insert(2, Arc::clone(&transport) as Arc<dyn Transport>);
+        let communicator = Communicator::new(
+            transports,
+            None,
+            PartitionSchedule::new(),
+            Instant::now(),
+            Arc::downgrade(&mediator),
+        );
+        let fault_injector = FaultInjector::new(
+            settings,
+            1,
+            communicator,
+            Arc::downgrade(&mediator),
+        );
+        (fault_injector, mediator, transport)
+    }
+
+    fn sent_messages(transport: &RecordingTransport) -> Vec<Vec<u8>> {
+        transport.sent_messages.lock().unwrap().clone()
+    }
+
+    fn wait_for_sent_messages(
+        transport: &RecordingTransport,
+        number_of_messages: usize,
+    ) {
+        let start = Instant::now();
+        while sent_messages(transport).len() < number_of_messages
+            && start.elapsed() < Duration::from_secs(5)
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn settings_without_faults() -> FaultSettings {
+        FaultSettings {
+            drop_probability: 0.0,
+            duplicate_probability: 0.0,
+            delay_probability: 0.0,
+            max_delay: Duration::from_millis(100),
+            reorder_probability: 0.0,
+            seed: 0,
+        }
+    }
+
+    fn fates(settings: &FaultSettings, seed: u64) -> Vec<Fate> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..100).map(|_| settings.fate(&mut rng)).collect()
+    }
+
+    #[test]
+    fn test_that_no_faults_are_injected_by_default() {
+        let settings = settings_without_faults();
+
+        assert!(!settings.injects_faults());
+        for fate in fates(&settings, 0) {
+            assert_eq!(fate, Fate::Transmit(vec![None]));
+        }
+    }
+
+    #[test]
+    fn test_that_all_messages_are_dropped_with_probability_1() {
+        let mut settings = settings_without_faults();
+        settings.drop_probability = 1.0;
+
+        assert!(settings.injects_faults());
+        for fate in fates(&settings, 0) {
+            assert_eq!(fate, Fate::Drop);
+        }
+    }
+
+    #[test]
+    fn test_that_all_messages_are_duplicated_with_probability_1() {
+        let mut settings = settings_without_faults();
+        settings.duplicate_probability = 1.0;
+
+        for fate in fates(&settings, 0) {
+            assert_eq!(fate, Fate::Transmit(vec![None, None]));
+        }
+    }
+
+    #[test]
+    fn test_that_delays_are_at_most_the_max_delay() {
+        let mut settings = settings_without_faults();
+        settings.delay_probability = 1.0;
+
+        for fate in fates(&settings, 0) {
+            match fate {
+                Fate::Transmit(delays) => {
+                    assert_eq!(delays.len(), 1);
+                    assert!(delays[0].unwrap() <= settings.max_delay);
+                }
+                _ => panic!("Expected the message to be transmitted."),
+            }
+        }
+    }
+
+    #[test]
+    fn test_that_the_same_seed_gives_the_same_faults() {
+        let settings = FaultSettings {
+            drop_probability: 0.2,
+            duplicate_probability: 0.2,
+            delay_probability: 0.2,
+            max_delay: Duration::from_millis(100),
+            reorder_probability: 0.2,
+            seed: 0,
+        };
+
+        assert_eq!(fates(&settings, 7), fates(&settings, 7));
+        assert_ne!(fates(&settings, 7), fates(&settings, 8));
+    }
+
+    #[test]
+    fn test_that_the_fault_injector_drops_messages() {
+        let mut settings = settings_without_faults();
+        settings.drop_probability = 1.0;
+        let (fault_injector, mediator, transport) =
+            fault_injector(settings);
+
+        fault_injector.send_bytes_to(&[1], 2);
+        fault_injector.shutdown();
+
+        assert!(sent_messages(&transport).is_empty());
+        assert_eq!(mediator.run_result().injected_faults.dropped, 1);
+    }
+
+    #[test]
+    fn test_that_the_fault_injector_duplicates_messages() {
+        let mut settings = settings_without_faults();
+        settings.duplicate_probability = 1.0;
+        let (fault_injector, mediator, transport) =
+            fault_injector(settings);
+
+        fault_injector.send_bytes_to(&[1], 2);
+        fault_injector.shutdown();
+
+        assert_eq!(sent_messages(&transport), vec![vec![1], vec![1]]);
+        assert_eq!(mediator.run_result().injected_faults.duplicated, 1);
+    }
+
+    #[test]
+    fn test_that_the_fault_injector_delays_messages() {
+        let mut settings = settings_without_faults();
+        settings.delay_probability = 1.0;
+        settings.max_delay = Duration::from_millis(50);
+        let (fault_injector, mediator, transport) =
+            fault_injector(settings);
+
+        fault_injector.send_bytes_to(&[1], 2);
+        assert_eq!(mediator.run_result().injected_faults.delayed, 1);
+
+        wait_for_sent_messages(&transport, 1);
+        assert_eq!(sent_messages(&transport), vec![vec![1]]);
+        fault_injector.shutdown();
+    }
+
+    #[test]
+    fn test_that_the_fault_injector_reorders_messages() {
+        let mut settings = settings_without_faults();
+        settings.reorder_probability = 1.0;
+        let (mut fault_injector, mediator, transport) =
+            fault_injector(settings);
+
+        fault_injector.send_bytes_to(&[1], 2);
+        assert!(sent_messages(&transport).is_empty());
+        fault_injector.settings.reorder_probability = 0.0;
+        fault_injector.send_bytes_to(&[2], 2);
+        fault_injector.shutdown();
+
+        assert_eq!(sent_messages(&transport), vec![vec![2], vec![1]]);
+        assert_eq!(mediator.run_result().injected_faults.reordered, 1);
+    }
+
+    #[test]
+    fn test_that_a_held_back_message_is_sent_without_a_next_message() {
+        let mut settings = settings_without_faults();
+        settings.reorder_probability = 1.0;
+        let (fault_injector, _mediator, transport) =
+            fault_injector(settings);
+
+        fault_injector.send_bytes_to(&[1], 2);
+
+        wait_for_sent_messages(&transport, 1);
+        assert_eq!(sent_messages(&transport), vec![vec![1]]);
+        fault_injector.shutdown();
+    }
+}
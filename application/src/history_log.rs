@@ -0,0 +1,243 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::Instant;
+
+use commons::history::{self, Operation, OperationKind};
+use commons::types::{NodeId, Timestamp};
+
+// Writes the operations of this node to a history file, one json
+// object per line. An operation is written when it ends. Operations
+// that are still pending when the node exits are written by finish().
+pub struct HistoryLog {
+    node_id: NodeId,
+    writer: BufWriter<File>,
+    start_instant: Instant,
+    start_wall_clock: u64,
+    pending_operations: BTreeMap<usize, Operation>,
+    next_operation_id: usize,
+}
+
+impl HistoryLog {
+    pub fn new(node_id: NodeId, file_path: &str) -> HistoryLog {
+        let file =
+            File::create(file_path).expect("Could not create the history file.");
+
+        HistoryLog {
+            node_id: node_id,
+            writer: BufWriter::new(file),
+            start_instant: Instant::now(),
+            start_wall_clock: history::now_in_micros(),
+            pending_operations: BTreeMap::new(),
+            next_operation_id: 0,
+        }
+    }
+
+    // Returns an id that is used to record the end of the operation.
+    // The version of a write is known when it starts, and that of a
+    // read when it ends.
+    pub fn invoke(
+        &mut self,
+        kind: OperationKind,
+        value: Option<String>,
+        version: Option<Timestamp>,
+    ) -> usize {
+        // The wall clock is only read once, when the node starts, so
+        // that the wall clock times of the operations of this node are
+        // in the same order as their monotonic times. Reading it for
+        // each operation can make back-to-back operations overlap.
+        let start = self.monotonic_now();
+        let operation = Operation {
+            node_id: self.node_id,
+            kind: kind,
+            value: value,
+            timestamp: self.start_wall_clock + start,
+            start: start,
+            end: None,
+            version: version,
+        };
+
+        let operation_id = self.next_operation_id;
+        self.next_operation_id += 1;
+        self.pending_operations.insert(operation_id, operation);
+        operation_id
+    }
+
+    pub fn respond(
+        &mut self,
+        operation_id: usize,
+        value: Option<String>,
+        version: Option<Timestamp>,
+    ) {
+        let mut operation = self
+            .pending_operations
+            .remove(&operation_id)
+            .expect("Responded to an operation that is not pending.");
+        if value.is_some() {
+            operation.value = value;
+        }
+        if version.is_some() {
+            operation.version = version;
+        }
+        operation.end = Some(self.monotonic_now());
+
+        self.write_operation(&operation);
+    }
+
+    pub fn finish(&mut self) {
+        let pending_operations: Vec<Operation> =
+            self.pending_operations.values().cloned().collect();
+        for operation in pending_operations.iter() {
+            self.write_operation(operation);
+        }
+        self.pending_operations.clear();
+
+        self.writer
+            .flush()
+            .expect("Could not flush the history file.");
+    }
+
+    fn monotonic_now(&self) -> u64 {
+        self.start_instant.elapsed().as_micros() as u64
+    }
+
+    fn write_operation(&mut self, operation: &Operation) {
+        let json = serde_json::to_string(operation)
+            .expect("Could not serialize an operation.");
+        writeln!(self.writer, "{}", json)
+            .expect("Could not write to the history file.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    use commons::linearizability;
+
+    use super::*;
+
+    // Unique to this process, so that test runs at the same time don't
+    // write to each other's history files.
+    fn history_file_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!(
+            "history_log_test_{}_{}.history",
+            name,
+            process::id()
+        ))
+    }
+
+    fn read_operations(file_path: &str) -> Vec<Operation> {
+        let string = fs::read_to_string(file_path).unwrap();
+        history::operations_from_json_lines(&string).unwrap()
+    }
+
+    #[test]
+    fn test_that_operations_are_written_when_they_end() {
+        let file_path = history_file_path("end");
+        let file_path = file_path.to_str().unwrap();
+        let mut history_log = HistoryLog::new(4, file_path);
+
+        let write_id = history_log.invoke(
+            OperationKind::Write,
+            Some("a".to_string()),
+            Some(1),
+        );
+        let read_id = history_log.invoke(OperationKind::Read, None, None);
+        history_log.respond(read_id, Some("b".to_string()), Some(0));
+        history_log.respond(write_id, None, None);
+        history_log.finish();
+
+        let operations = read_operations(file_path);
+        assert_eq!(operations.len(), 2);
+        assert_eq!(operations[0].kind, OperationKind::Read);
+        assert_eq!(operations[0].value, Some("b".to_string()));
+        assert_eq!(operations[0].version, Some(0));
+        assert_eq!(operations[1].kind, OperationKind::Write);
+        assert_eq!(operations[1].value, Some("a".to_string()));
+        assert_eq!(operations[1].version, Some(1));
+        for operation in operations.iter() {
+            assert_eq!(operation.node_id, 4);
+            assert!(operation.start <= operation.end.unwrap());
+        }
+    }
+
+    #[test]
+    fn test_that_back_to_back_operations_do_not_overlap_on_the_wall_clock(
+    ) {
+        let file_path = history_file_path("back_to_back");
+        let file_path = file_path.to_str().unwrap();
+        let mut history_log = HistoryLog::new(4, file_path);
+
+        for _ in 0..1000 {
+            let write_id = history_log.invoke(
+                OperationKind::Write,
+                Some("a".to_string()),
+                Some(1),
+            );
+            history_log.respond(write_id, None, None);
+        }
+        history_log.finish();
+
+        let operations: Vec<Operation> = read_operations(file_path)
+            .iter()
+            .map(Operation::on_wall_clock)
+            .collect();
+        for pair in operations.windows(2) {
+            assert!(pair[0].end.unwrap() <= pair[1].start);
+        }
+    }
+
+    #[test]
+    fn test_that_pending_operations_are_written_on_finish() {
+        let file_path = history_file_path("pending");
+        let file_path = file_path.to_str().unwrap();
+        let mut history_log = HistoryLog::new(4, file_path);
+
+        history_log.invoke(
+            OperationKind::Write,
+            Some("a".to_string()),
+            Some(1),
+        );
+        history_log.finish();
+
+        let operations = read_operations(file_path);
+        assert_eq!(operations.len(), 1);
+        assert_eq!(operations[0].end, None);
+    }
+
+    // The write loop of the writer writes "Val: 1" and "Val: 2", and a
+    // client writes "Val: 1" again and the initial value through the
+    // client endpoint, between them.
+    #[test]
+    fn test_that_endpoint_writes_and_the_write_loop_are_linearizable() {
+        let file_path = history_file_path("endpoint");
+        let file_path = file_path.to_str().unwrap();
+        let mut history_log = HistoryLog::new(4, file_path);
+
+        let values = ["Val: 1", "Val: 1", "", "Val: 2"];
+        for (i, value) in values.iter().enumerate() {
+            let version = i as Timestamp + 1;
+            let write_id = history_log.invoke(
+                OperationKind::Write,
+                Some(value.to_string()),
+                Some(version),
+            );
+            history_log.respond(write_id, None, None);
+            let read_id =
+                history_log.invoke(OperationKind::Read, None, None);
+            history_log.respond(
+                read_id,
+                Some(value.to_string()),
+                Some(version),
+            );
+        }
+        history_log.finish();
+
+        let operations = read_operations(file_path);
+        assert_eq!(linearizability::check(&operations), Ok(()));
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use commons::types::NodeId;
+
+pub const ANY_INCARNATION: u64 = 0;
+
+/*
+Every time a node starts, it gets a new incarnation, which is the
+wall clock time when it started, in microseconds. Since the counters
+of a node start at 0 again when it restarts, a delayed message from a
+previous incarnation could otherwise be taken for a fresh one. Every
+envelope carries the incarnation of its sender, and replies also
+carry the incarnation of the node they reply to. A message is
+discarded if
+
+- it is from an older incarnation of the sender than one this node
+  has already received a message from, or
+- it is a reply to another incarnation of this node.
+
+Requests are broadcast to all incarnations, with receiver
+incarnation 0.
+*/
+pub struct Incarnations {
+    own_incarnation: u64,
+    peer_incarnations: Mutex<HashMap<NodeId, u64>>,
+}
+
+impl Incarnations {
+    pub fn new(own_incarnation: u64) -> Incarnations {
+        Incarnations {
+            own_incarnation: own_incarnation,
+            peer_incarnations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn own_incarnation(&self) -> u64 {
+        self.own_incarnation
+    }
+
+    // The latest incarnation of the node that this node has received
+    // a message from.
+    pub fn incarnation_of(&self, node_id: NodeId) -> u64 {
+        *self
+            .peer_incarnations
+            .lock()
+            .unwrap()
+            .get(&node_id)
+            .unwrap_or(&ANY_INCARNATION)
+    }
+
+    // Returns false if the message is from a previous incarnation of
+    // the sender, or replies to a previous incarnation of this node.
+    // Otherwise, the incarnation of the sender is remembered.
+    pub fn accept(
+        &self,
+        sender: NodeId,
+        sender_incarnation: u64,
+        receiver_incarnation: u64,
+    ) -> bool {
+        if receiver_incarnation != ANY_INCARNATION
+            && receiver_incarnation != self.own_incarnation
+        {
+            return false;
+        }
+
+        let mut peer_incarnations = self.peer_incarnations.lock().unwrap();
+        let latest_incarnation = peer_incarnations
+            .entry(sender)
+            .or_insert(sender_incarnation);
+        if sender_incarnation < *latest_incarnation {
+            return false;
+        }
+        *latest_incarnation = sender_incarnation;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_the_first_message_from_a_node_is_accepted() {
+        let incarnations = Incarnations::new(100);
+
+        assert!(incarnations.accept(2, 50, ANY_INCARNATION));
+        assert_eq!(incarnations.incarnation_of(2), 50);
+        assert_eq!(incarnations.incarnation_of(3), ANY_INCARNATION);
+    }
+
+    #[test]
+    fn test_that_a_restarted_node_is_accepted() {
+        let incarnations = Incarnations::new(100);
+
+        assert!(incarnations.accept(2, 50, ANY_INCARNATION));
+        assert!(incarnations.accept(2, 70, ANY_INCARNATION));
+        assert_eq!(incarnations.incarnation_of(2), 70);
+    }
+
+    #[test]
+    fn test_that_a_message_from_a_previous_incarnation_is_discarded() {
+        let incarnations = Incarnations::new(100);
+
+        assert!(incarnations.accept(2, 70, ANY_INCARNATION));
+        assert!(!incarnations.accept(2, 50, ANY_INCARNATION));
+        assert_eq!(incarnations.incarnation_of(2), 70);
+    }
+
+    #[test]
+    fn test_that_a_reply_to_another_incarnation_is_discarded() {
+        let incarnations = Incarnations::new(100);
+
+        assert!(incarnations.accept(2, 50, 100));
+        assert!(!incarnations.accept(2, 50, 90));
+    }
+}
@@ -1,27 +1,51 @@
 //#![allow(dead_code, unused_variables, unused_imports, unused_mut)]
 
 use std::fs;
-use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::mpsc;
 use std::sync::Arc;
-use std::thread;
-use std::time::Duration;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use commons::arguments;
-use commons::types::Int;
 
-use crate::mediator::{Med, Mediator, MediatorImpl};
+use crate::admin_endpoint::AdminEndpoint;
+use crate::client_endpoint::{ClientEndpoint, ClientEndpointSettings};
+use crate::client_switches::ClientSwitches;
+use crate::mediator::{Med, Mediator, MediatorImpl, WriteError};
+use crate::metrics_endpoint::MetricsEndpoint;
 use crate::settings::SETTINGS;
 use crate::terminal_output::printlnu;
 
 mod abd_node;
+mod admin_endpoint;
+mod authentication;
+mod client_endpoint;
+mod client_switches;
 mod communicator;
 mod configuration_manager;
+mod encryption;
+mod failure_detector;
+mod fault_injector;
+mod history_log;
+mod incarnations;
 mod mediator;
 mod messages;
+mod metrics_endpoint;
+mod partition_schedule;
+mod protocol_versions;
 mod quorum;
 mod responsible_cell;
+mod retransmission;
 mod settings;
+#[cfg(test)]
+mod simulation;
 mod terminal_output;
+mod transport;
+mod wire_format;
+
+// When the run ends, the operations in progress get this long to
+// finish before they are aborted.
+const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(2);
 
 fn main() {
     SETTINGS.node_id();
@@ -37,21 +61,56 @@ fn main() {
         100 * SETTINGS.number_of_nodes() as u64,
     ));
 
-    let (read_tx, write_tx) =
-        start_client_threads_and_get_channel_send_ends(&mediator);
+    let run_start = Instant::now();
+    let client_switches = Arc::new(ClientSwitches::new(
+        SETTINGS.should_write(),
+        SETTINGS.should_read(),
+    ));
+    let client_threads = start_client_threads(&mediator, &client_switches);
+    let client_endpoint = start_client_endpoint(&mediator);
+    let admin_endpoint = start_admin_endpoint(&mediator, &client_switches);
+    let metrics_endpoint = start_metrics_endpoint(&mediator);
 
-    sleep_time_specified_by_arguments();
+    wait_for_end_of_run_or_signal();
 
-    read_tx.send(()).expect("Error when sending on read_tx.");
-    write_tx.send(()).expect("Error when sending on write_tx.");
+    // The client threads stop when the mediator refuses their next
+    // operation, or when they are woken up while switched off.
+    mediator.shutdown(SHUTDOWN_DEADLINE);
+    client_switches.shutdown();
+    for client_thread in client_threads {
+        client_thread.join().expect("A client thread panicked.");
+    }
+    if let Some(client_endpoint) = client_endpoint {
+        client_endpoint.shutdown();
+    }
+    if let Some(admin_endpoint) = admin_endpoint {
+        admin_endpoint.shutdown();
+    }
+    if let Some(metrics_endpoint) = metrics_endpoint {
+        metrics_endpoint.shutdown();
+    }
 
     let mut run_result = mediator.run_result();
 
     run_result.metadata.node_id = SETTINGS.node_id();
-    run_result.metadata.is_reader = SETTINGS.should_read();
-    run_result.metadata.is_writer = SETTINGS.should_write();
-    run_result.metadata.run_length =
-        SETTINGS.run_length().as_secs() as Int;
+    // A node that wasn't started with --read may still have read, if
+    // reading was switched on through the admin endpoint or it served
+    // reads from the client endpoint.
+    run_result.metadata.is_reader =
+        SETTINGS.should_read() || run_result.read_ops > 0;
+    // A writer that had writing switched off through the admin
+    // endpoint for the whole run didn't write, and has no write acks.
+    run_result.metadata.is_writer =
+        SETTINGS.should_write() && run_result.write_ops > 0;
+    // The writer has no read thread, so its reads were all served
+    // from the client endpoint.
+    run_result.metadata.writer_served_client_reads =
+        SETTINGS.should_write() && run_result.read_ops > 0;
+    run_result.metadata.fastest_quorum_first =
+        SETTINGS.fastest_quorum_first();
+    // The time actually run, which is shorter than the run length if
+    // the node was stopped by a signal.
+    run_result.metadata.run_length = run_start.elapsed().as_secs_f64();
 
     let json = serde_json::to_string(&*run_result).unwrap();
     printlnu(format!("{}", &json));
@@ -62,81 +121,142 @@ fn main() {
         json,
     )
     .expect("Could not write the json result file");
+
+    mediator.history_log().finish();
 }
 
-fn start_client_threads_and_get_channel_send_ends<M: Med>(
+// The writer gets a write thread, and the other nodes a read thread,
+// which waits while reading is switched off, so that reading can be
+// switched on through the admin endpoint.
+fn start_client_threads<M: Med>(
     mediator: &Arc<M>,
-) -> (Sender<()>, Sender<()>) {
-    let (read_tx, read_rx) = mpsc::channel();
-    let (write_tx, write_rx) = mpsc::channel();
-
-    let read_thread_mediator = Arc::clone(mediator);
-    thread::spawn(move || {
-        if SETTINGS.should_read() {
-            client_reads(read_rx, read_thread_mediator);
-        }
-    });
+    client_switches: &Arc<ClientSwitches>,
+) -> Vec<JoinHandle<()>> {
+    let mut client_threads = Vec::new();
 
-    let write_thread_mediator = Arc::clone(mediator);
-    thread::spawn(move || {
-        if SETTINGS.should_write() {
-            client_writes(write_rx, write_thread_mediator);
-        }
-    });
+    if client_switches.is_writer() {
+        let write_thread_mediator = Arc::clone(mediator);
+        let write_thread_switches = Arc::clone(client_switches);
+        client_threads.push(thread::spawn(move || {
+            client_writes(write_thread_mediator, write_thread_switches);
+        }));
+    } else {
+        let read_thread_mediator = Arc::clone(mediator);
+        let read_thread_switches = Arc::clone(client_switches);
+        client_threads.push(thread::spawn(move || {
+            client_reads(read_thread_mediator, read_thread_switches);
+        }));
+    }
+
+    client_threads
+}
+
+fn start_client_endpoint<M: Med>(
+    mediator: &Arc<M>,
+) -> Option<ClientEndpoint> {
+    let socket_addr = SETTINGS.client_socket_addr()?;
+    let settings = ClientEndpointSettings {
+        timeout: SETTINGS.client_timeout(),
+        max_connections: SETTINGS.max_client_connections(),
+        max_value_size: SETTINGS.max_value_size(),
+        accepts_writes: SETTINGS.should_write(),
+    };
+
+    printlnu(format!("Client endpoint listening on {}", socket_addr));
+    Some(ClientEndpoint::start(socket_addr, settings, Arc::clone(mediator)))
+}
+
+fn start_admin_endpoint(
+    mediator: &Arc<MediatorImpl>,
+    client_switches: &Arc<ClientSwitches>,
+) -> Option<AdminEndpoint> {
+    let socket_addr = SETTINGS.admin_socket_addr()?;
+
+    let mediator = Arc::clone(mediator);
+    printlnu(format!("Admin endpoint listening on {}", socket_addr));
+    Some(AdminEndpoint::start(
+        socket_addr,
+        move |client_switches| mediator.node_state(client_switches),
+        Arc::clone(client_switches),
+    ))
+}
+
+fn start_metrics_endpoint(
+    mediator: &Arc<MediatorImpl>,
+) -> Option<MetricsEndpoint> {
+    let socket_addr = SETTINGS.metrics_socket_addr()?;
 
-    (read_tx, write_tx)
+    printlnu(format!("Metrics endpoint listening on {}", socket_addr));
+    Some(MetricsEndpoint::start(socket_addr, Arc::clone(mediator)))
 }
 
-fn client_reads<M: Med>(read_rx: Receiver<()>, mediator: Arc<M>) {
+fn client_reads<M: Med>(
+    mediator: Arc<M>,
+    client_switches: Arc<ClientSwitches>,
+) {
     let mut read_number = 0;
-    loop {
+    while client_switches.wait_until_reading() {
         read_number += 1;
 
         if SETTINGS.print_client_operations() {
             printlnu(format!("Start read {}", read_number));
         }
 
-        let res = mediator.read();
+        let res = match mediator.read() {
+            Ok(res) => res,
+            Err(_) => break,
+        };
 
         if SETTINGS.print_client_operations() {
             printlnu(format!("Stop read {}. Result: {}", read_number, res));
         }
-
-        match read_rx.try_recv() {
-            Err(TryRecvError::Empty) => {}
-            _ => break,
-        }
     }
 }
 
-fn client_writes<M: Med>(write_rx: Receiver<()>, mediator: Arc<M>) {
+fn client_writes<M: Med>(
+    mediator: Arc<M>,
+    client_switches: Arc<ClientSwitches>,
+) {
     let mut write_number = 0;
-    loop {
+    while client_switches.wait_until_writing() {
         write_number += 1;
 
         if SETTINGS.print_client_operations() {
             printlnu(format!("Start write {}", write_number));
         }
 
-        mediator.write(format!("Val: {}", write_number));
+        match mediator.write(format!("Val: {}", write_number)) {
+            Ok(()) => {}
+            Err(WriteError::Aborted) => break,
+            Err(error) => panic!(
+                "{} Use --max-value-size to allow larger values.",
+                error
+            ),
+        }
 
         if SETTINGS.print_client_operations() {
             printlnu(format!("End write {}", write_number));
         }
-
-        match write_rx.try_recv() {
-            Err(TryRecvError::Empty) => {}
-            _ => break,
-        }
     }
 }
 
-fn sleep_time_specified_by_arguments() {
-    if SETTINGS.run_length() == Duration::from_secs(0) {
-        loop {
-            thread::sleep(Duration::from_secs(60));
-        }
+// Waits for the run length, or forever if it is 0, but stops waiting
+// on SIGINT or SIGTERM, so that the run result is written also when
+// the node is stopped early.
+fn wait_for_end_of_run_or_signal() {
+    let (signal_tx, signal_rx) = mpsc::channel();
+    ctrlc::set_handler(move || {
+        let _ = signal_tx.send(());
+    })
+    .expect("Could not set the signal handler.");
+
+    let signal_received = if SETTINGS.run_length() == Duration::from_secs(0)
+    {
+        signal_rx.recv().is_ok()
     } else {
-        thread::sleep(SETTINGS.run_length());
+        signal_rx.recv_timeout(SETTINGS.run_length()).is_ok()
+    };
+    if signal_received {
+        printlnu("Received a signal, shutting down.".to_string());
     }
 }
@@ -1,46 +1,135 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
 use std::marker::{Send, Sync};
-use std::sync::{Arc, Mutex, MutexGuard};
-
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use commons::admin_protocol::{
+    ClientOperationsState, Configuration, NodeState, PeerState,
+};
+use commons::arguments;
+use commons::history::OperationKind;
 use commons::run_result::RunResult;
-use commons::types::{Int, NodeId};
+use commons::types::{Int, NodeId, Timestamp};
 
-use crate::abd_node::AbdNode;
+use crate::abd_node::{AbdNode, Aborted};
+use crate::authentication::Keys;
+use crate::client_switches::ClientSwitches;
 use crate::communicator::Communicator;
 use crate::configuration_manager::ConfigurationManager;
+use crate::failure_detector::{FailureDetector, PeerHealth, PeerStatus};
+use crate::fault_injector::FaultInjector;
+use crate::partition_schedule;
+use crate::history_log::HistoryLog;
+use crate::quorum::Quorum;
 use crate::responsible_cell::ResponsibleCell;
+use crate::retransmission::RetransmissionPolicy;
 use crate::settings::SETTINGS;
-//use crate::terminal_output::printlnu;
+use crate::terminal_output::printlnu;
+use crate::transport::{self, TransportError};
+use crate::wire_format::WireFormat;
 
 pub trait Mediator {
     // Communicator
 
-    fn send_json_to(&self, json: &str, receiver: NodeId);
-    fn json_received(&self, json: &str);
+    fn send_bytes_to(&self, bytes: &[u8], receiver: NodeId);
+    fn bytes_received(&self, bytes: &[u8]);
+    fn transport_error(&self, error: TransportError);
 
     // Configuration manager
 
     fn node_id(&self) -> NodeId;
     fn node_ids(&self) -> &HashSet<NodeId>;
     fn number_of_nodes(&self) -> Int;
+    fn cluster_id(&self) -> &str;
+    fn wire_format(&self) -> WireFormat;
+    fn keys(&self) -> Option<&Keys>;
+    fn retransmission_policy(&self) -> &RetransmissionPolicy;
+    fn fastest_quorum_first(&self) -> bool;
+    fn record_latencies(&self) -> bool;
+
+    // Failure detector
+
+    fn heard_from(&self, node_id: NodeId, round_trip_time: Option<Duration>);
+    fn peer_health(&self, node_id: NodeId) -> PeerHealth;
 
     // Evaluation
 
     fn run_result(&self) -> MutexGuard<RunResult>;
 
+    // Timing
+
+    fn wait_for_majority(&self, quorum: &Quorum, timeout: Duration) -> bool;
+    fn now(&self) -> Duration;
+
+    // Terminal output
+
+    fn log(&self, string: String);
+
     // Abd Node
 
-    fn write(&self, value: String);
-    fn read(&self) -> String;
+    fn write(&self, value: String) -> Result<(), WriteError>;
+    fn read(&self) -> Result<String, Aborted>;
+    fn read_versioned(&self) -> Result<(Timestamp, String), Aborted>;
+}
+
+// Why a write did not take effect, or may not have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteError {
+    Aborted,
+    // The value is larger than --max-value-size, so the write is not
+    // started.
+    ValueTooLarge { size: usize, max_value_size: usize },
+}
+
+impl From<Aborted> for WriteError {
+    fn from(_: Aborted) -> WriteError {
+        WriteError::Aborted
+    }
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::Aborted => {
+                write!(formatter, "The write was aborted.")
+            }
+            WriteError::ValueTooLarge {
+                size,
+                max_value_size,
+            } => write!(
+                formatter,
+                "The value to write is {} bytes, which is larger than the max value size of {} bytes.",
+                size, max_value_size
+            ),
+        }
+    }
 }
 
 pub trait Med: Mediator + Send + Sync + 'static {}
 impl<T: Mediator + Send + Sync + 'static> Med for T {}
 
+struct Operations {
+    in_progress: Int,
+    shutting_down: bool,
+}
+
 pub struct MediatorImpl {
     communicator: ResponsibleCell<Option<Arc<Communicator<MediatorImpl>>>>,
+    fault_injector: ResponsibleCell<Option<FaultInjector<MediatorImpl>>>,
+    failure_detector: FailureDetector,
     configuration_manager: ConfigurationManager,
     run_result: Mutex<RunResult>,
+    history_log: Mutex<HistoryLog>,
+    start_instant: Instant,
+    operations: Mutex<Operations>,
+    operations_ended: Condvar,
+    heartbeat_thread: Mutex<Option<JoinHandle<()>>>,
+    // The AbdNode does one read and one write at a time, but they
+    // can come from both the client threads and the client endpoint.
+    read_lock: Mutex<()>,
+    write_lock: Mutex<()>,
 
     abd_node: ResponsibleCell<Option<AbdNode<MediatorImpl, String>>>,
 }
@@ -48,37 +137,219 @@ pub struct MediatorImpl {
 impl MediatorImpl {
     pub fn new() -> Arc<MediatorImpl> {
         let node_id = SETTINGS.node_id();
-        let socket_addrs = SETTINGS.socket_addrs().clone();
-        let node_ids =
-            socket_addrs.keys().map(|&node_id| node_id).collect();
+        let addresses = SETTINGS.addresses();
+        let node_ids: HashSet<NodeId> =
+            addresses.keys().map(|&node_id| node_id).collect();
+        let failure_detector = FailureDetector::new(
+            &node_ids,
+            SETTINGS.suspicion_timeout(),
+            Duration::from_secs(0),
+        );
 
         let mediator = MediatorImpl {
             communicator: ResponsibleCell::new(None),
+            fault_injector: ResponsibleCell::new(None),
+            failure_detector: failure_detector,
             configuration_manager: ConfigurationManager::new(
-                node_id, node_ids,
+                node_id,
+                node_ids,
+                &SETTINGS,
             ),
             run_result: Mutex::new(RunResult::new()),
+            history_log: Mutex::new(HistoryLog::new(
+                node_id,
+                &arguments::history_file_name_from_node_id(node_id),
+            )),
+            start_instant: Instant::now(),
+            operations: Mutex::new(Operations {
+                in_progress: 0,
+                shutting_down: false,
+            }),
+            operations_ended: Condvar::new(),
+            heartbeat_thread: Mutex::new(None),
+            read_lock: Mutex::new(()),
+            write_lock: Mutex::new(()),
             abd_node: ResponsibleCell::new(None),
         };
         let mediator: Arc<MediatorImpl> = Arc::new(mediator);
 
-        let own_socket_addr = socket_addrs
-            .get(&node_id)
-            .expect("Could not find own socket addres.");
-
+        let transports = transport::transports_from_addresses(
+            node_id,
+            addresses,
+            transport::max_message_size(SETTINGS.max_value_size()),
+            Arc::downgrade(&mediator),
+        );
+        let partition_start = match SETTINGS.partition_start() {
+            Some(wall_clock) => {
+                partition_schedule::instant_from_wall_clock(wall_clock)
+            }
+            None => Instant::now(),
+        };
         let communicator = Communicator::new(
-            *own_socket_addr,
-            socket_addrs,
+            transports,
+            SETTINGS.cipher().cloned(),
+            SETTINGS.partition_schedule().clone(),
+            partition_start,
             Arc::downgrade(&mediator),
         );
         let abd_node = AbdNode::new(Arc::downgrade(&mediator));
 
+        let fault_settings = SETTINGS.fault_settings();
+        if fault_settings.injects_faults() {
+            let fault_injector = FaultInjector::new(
+                fault_settings.clone(),
+                node_id,
+                Arc::clone(&communicator),
+                Arc::downgrade(&mediator),
+            );
+            *mediator.fault_injector.get_mut() = Some(fault_injector);
+        }
+
         *mediator.communicator.get_mut() = Some(communicator);
         *mediator.abd_node.get_mut() = Some(abd_node);
 
+        let heartbeat_interval = SETTINGS.heartbeat_interval();
+        if heartbeat_interval > Duration::from_secs(0) {
+            let heartbeat_thread_mediator = Arc::downgrade(&mediator);
+            let heartbeat_thread = thread::spawn(move || {
+                heartbeat_loop(heartbeat_thread_mediator, heartbeat_interval);
+            });
+            *mediator.heartbeat_thread.lock().unwrap() =
+                Some(heartbeat_thread);
+        }
+
         mediator
     }
-    
+
+    pub fn history_log(&self) -> MutexGuard<'_, HistoryLog> {
+        self.history_log.lock().unwrap()
+    }
+
+    // What the node is doing right now, for the admin endpoint.
+    pub fn node_state(&self, client_switches: &ClientSwitches) -> NodeState {
+        let (timestamp, value) = self.abd_node().timestamp_and_value();
+
+        let mut node_ids: Vec<NodeId> =
+            self.node_ids().iter().cloned().collect();
+        node_ids.sort();
+        let peers = node_ids
+            .iter()
+            .filter(|&&node_id| node_id != self.node_id())
+            .map(|&node_id| {
+                let health = self.peer_health(node_id);
+                let peer_state = PeerState {
+                    suspected: health.status == PeerStatus::Suspected,
+                    round_trip_time_millis: health
+                        .round_trip_time
+                        .map(|time| time.as_secs_f64() * 1000.0),
+                };
+                (node_id, peer_state)
+            })
+            .collect::<BTreeMap<NodeId, PeerState>>();
+
+        let retransmission_policy = self.retransmission_policy();
+        let configuration = Configuration {
+            node_ids: node_ids,
+            cluster_id: self.cluster_id().to_string(),
+            is_writer: client_switches.is_writer(),
+            wire_format: format!("{:?}", self.wire_format()),
+            authenticated: self.keys().is_some(),
+            encrypted: SETTINGS.cipher().is_some(),
+            max_value_size: SETTINGS.max_value_size(),
+            run_length_secs: SETTINGS.run_length().as_secs(),
+            adaptive_retransmission: retransmission_policy.adaptive,
+            initial_retransmission_timeout_millis: retransmission_policy
+                .initial_timeout
+                .as_millis() as u64,
+            fastest_quorum_first: self.fastest_quorum_first(),
+            heartbeat_interval_millis: SETTINGS.heartbeat_interval().as_millis()
+                as u64,
+            suspicion_timeout_millis: SETTINGS.suspicion_timeout().as_millis()
+                as u64,
+        };
+
+        NodeState {
+            node_id: self.node_id(),
+            incarnation: self.abd_node().incarnation(),
+            uptime_millis: self.now().as_millis() as u64,
+            timestamp: timestamp,
+            value: value,
+            quorums: self.abd_node().quorum_states(),
+            peers: peers,
+            client_operations: ClientOperationsState {
+                reading: client_switches.reading(),
+                writing: client_switches.writing(),
+                in_progress: self.operations.lock().unwrap().in_progress,
+            },
+            configuration: configuration,
+            run_result: self.run_result().clone(),
+        }
+    }
+
+    /*
+    Shuts the node down. New operations are refused, and the
+    operations in progress get until the deadline to finish, after
+    which they are aborted. Then the heartbeat thread, the delay
+    thread of the fault injector and the receiving threads of the
+    transports are stopped and joined. The node still has its run
+    result and history log afterwards, so that they can be written.
+    Calling it again does nothing.
+    */
+    pub fn shutdown(&self, deadline: Duration) {
+        let mut operations = self.operations.lock().unwrap();
+        if operations.shutting_down {
+            return;
+        }
+        operations.shutting_down = true;
+
+        let (operations, _) = self
+            .operations_ended
+            .wait_timeout_while(operations, deadline, |operations| {
+                operations.in_progress > 0
+            })
+            .unwrap();
+        if operations.in_progress > 0 {
+            self.log(format!(
+                "Operations aborted at the shutdown deadline: {}",
+                operations.in_progress
+            ));
+        }
+        drop(operations);
+        self.abd_node().abort();
+
+        if let Some(heartbeat_thread) =
+            self.heartbeat_thread.lock().unwrap().take()
+        {
+            heartbeat_thread
+                .join()
+                .expect("The heartbeat thread panicked.");
+        }
+        if let Some(fault_injector) = self.fault_injector.get() {
+            fault_injector.shutdown();
+        }
+        self.communicator().shutdown();
+    }
+
+    fn is_shutting_down(&self) -> bool {
+        self.operations.lock().unwrap().shutting_down
+    }
+
+    // Returns false if the node is shutting down, and the operation
+    // must not start.
+    fn operation_started(&self) -> bool {
+        let mut operations = self.operations.lock().unwrap();
+        if operations.shutting_down {
+            return false;
+        }
+        operations.in_progress += 1;
+        true
+    }
+
+    fn operation_ended(&self) {
+        self.operations.lock().unwrap().in_progress -= 1;
+        self.operations_ended.notify_all();
+    }
+
     // Modules
 
     fn communicator(&self) -> &Communicator<MediatorImpl> {
@@ -98,17 +369,57 @@ impl MediatorImpl {
     fn configuration_manager(&self) -> &ConfigurationManager {
         &self.configuration_manager
     }
+
+    // Sends heartbeats to the other nodes, and suspects the nodes that
+    // haven't been heard from for too long.
+    fn heartbeat(&self) {
+        self.abd_node().send_heartbeats();
+
+        for node_id in self.failure_detector.suspect_silent_nodes(self.now())
+        {
+            self.log(format!("Suspecting that node {} has failed.", node_id));
+            *self
+                .run_result()
+                .suspected_nodes
+                .entry(node_id)
+                .or_insert(0) += 1;
+        }
+    }
+}
+
+fn heartbeat_loop(mediator: Weak<MediatorImpl>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        match mediator.upgrade() {
+            Some(mediator) if !mediator.is_shutting_down() => {
+                mediator.heartbeat()
+            }
+            _ => return,
+        }
+    }
 }
 
 impl Mediator for MediatorImpl {
     // Communicator
 
-    fn send_json_to(&self, json: &str, receiver: NodeId) {
-        self.communicator().send_json_to(json, receiver);
+    fn send_bytes_to(&self, bytes: &[u8], receiver: NodeId) {
+        match self.fault_injector.get() {
+            Some(fault_injector) => {
+                fault_injector.send_bytes_to(bytes, receiver)
+            }
+            None => self.communicator().send_bytes_to(bytes, receiver),
+        }
+    }
+
+    fn bytes_received(&self, bytes: &[u8]) {
+        match self.communicator().decrypt(bytes) {
+            Some(bytes) => self.abd_node().bytes_received(&bytes),
+            None => self.run_result().rejected_messages.undecryptable += 1,
+        }
     }
 
-    fn json_received(&self, json: &str) {
-        self.abd_node().json_received(json);
+    fn transport_error(&self, error: TransportError) {
+        self.communicator().transport_error(error);
     }
 
     // Configuration manager
@@ -125,19 +436,124 @@ impl Mediator for MediatorImpl {
         self.configuration_manager().number_of_nodes()
     }
 
+    fn cluster_id(&self) -> &str {
+        self.configuration_manager().cluster_id()
+    }
+
+    fn wire_format(&self) -> WireFormat {
+        self.configuration_manager().wire_format()
+    }
+
+    fn keys(&self) -> Option<&Keys> {
+        self.configuration_manager().keys()
+    }
+
+    fn retransmission_policy(&self) -> &RetransmissionPolicy {
+        self.configuration_manager().retransmission_policy()
+    }
+
+    fn fastest_quorum_first(&self) -> bool {
+        self.configuration_manager().fastest_quorum_first()
+    }
+
+    fn record_latencies(&self) -> bool {
+        self.configuration_manager().record_latencies()
+    }
+
+    // Failure detector
+
+    fn heard_from(&self, node_id: NodeId, round_trip_time: Option<Duration>) {
+        if self
+            .failure_detector
+            .heard_from(node_id, round_trip_time, self.now())
+        {
+            self.log(format!("Node {} is alive again.", node_id));
+        }
+    }
+
+    fn peer_health(&self, node_id: NodeId) -> PeerHealth {
+        self.failure_detector.health(node_id)
+    }
+
     // Evaluation
 
     fn run_result(&self) -> MutexGuard<RunResult> {
         self.run_result.lock().unwrap()
     }
 
+    // Timing
+
+    fn wait_for_majority(&self, quorum: &Quorum, timeout: Duration) -> bool {
+        quorum.wait_for_majority(timeout)
+    }
+
+    fn now(&self) -> Duration {
+        self.start_instant.elapsed()
+    }
+
+    // Terminal output
+
+    fn log(&self, string: String) {
+        printlnu(string);
+    }
+
     // Abd Node
 
-    fn write(&self, value: String) {
-        self.abd_node().write(value);
+    // An operation that is aborted is left pending in the history,
+    // since it may or may not have taken effect.
+    fn write(&self, value: String) -> Result<(), WriteError> {
+        let max_value_size = SETTINGS.max_value_size();
+        if value.len() > max_value_size {
+            return Err(WriteError::ValueTooLarge {
+                size: value.len(),
+                max_value_size: max_value_size,
+            });
+        }
+
+        if !self.operation_started() {
+            return Err(WriteError::Aborted);
+        }
+        let _write_lock = self.write_lock.lock().unwrap();
+        // Only the writer writes, one write at a time, and each write
+        // increments the timestamp, so this is the version it writes.
+        let (timestamp, _) = self.abd_node().timestamp_and_value();
+        let operation_id = self.history_log().invoke(
+            OperationKind::Write,
+            Some(value.clone()),
+            Some(timestamp + 1),
+        );
+
+        let result = self.abd_node().write(value);
+
+        if result.is_ok() {
+            self.history_log().respond(operation_id, None, None);
+        }
+        self.operation_ended();
+        result.map_err(WriteError::from)
+    }
+
+    fn read(&self) -> Result<String, Aborted> {
+        self.read_versioned().map(|(_, value)| value)
     }
 
-    fn read(&self) -> String {
-        self.abd_node().read()
+    fn read_versioned(&self) -> Result<(Timestamp, String), Aborted> {
+        if !self.operation_started() {
+            return Err(Aborted);
+        }
+        let _read_lock = self.read_lock.lock().unwrap();
+        let operation_id =
+            self.history_log().invoke(OperationKind::Read, None, None);
+
+        let result = self.abd_node().read_versioned();
+
+        if let Ok((timestamp, value)) = &result {
+            self.history_log().respond(
+                operation_id,
+                Some(value.clone()),
+                Some(*timestamp),
+            );
+        }
+        self.operation_ended();
+        result
     }
 }
@@ -1,9 +1,176 @@
+use std::collections::HashSet;
+
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
 use commons::types::{NodeId, Timestamp};
 
+use crate::authentication::{self, Keys};
+use crate::incarnations::Incarnations;
+use crate::protocol_versions::ProtocolVersions;
+use crate::wire_format;
+
+/*
+All messages that AbdNodes send to each other. A message is encoded
+and decoded as an AbdMessage, so a received message is decoded once,
+and then dispatched on its variant.
+*/
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum AbdMessage<V> {
+    Write(WriteMessage<V>),
+    WriteAck(WriteAckMessage),
+    Read1(Read1Message),
+    Read1Ack(Read1AckMessage<V>),
+    Read2(Read2Message<V>),
+    Read2Ack(Read2AckMessage),
+    Heartbeat(HeartbeatMessage),
+    HeartbeatAck(HeartbeatAckMessage),
+}
+
+impl<V> AbdMessage<V> {
+    pub fn sender(&self) -> NodeId {
+        match self {
+            AbdMessage::Write(message) => message.sender,
+            AbdMessage::WriteAck(message) => message.sender,
+            AbdMessage::Read1(message) => message.sender,
+            AbdMessage::Read1Ack(message) => message.sender,
+            AbdMessage::Read2(message) => message.sender,
+            AbdMessage::Read2Ack(message) => message.sender,
+            AbdMessage::Heartbeat(message) => message.sender,
+            AbdMessage::HeartbeatAck(message) => message.sender,
+        }
+    }
+}
+
+
+//
+// Envelope
+//
+
+/*
+The protocol version is raised whenever the messages change. A build
+of version N+1 must still be able to encode and decode the messages
+of version N, and keeps MIN_SUPPORTED_PROTOCOL_VERSION at N, so that
+a cluster can be upgraded one node at a time while the other nodes
+still run version N. See ProtocolVersions for which version is sent
+to which node. When all nodes run version N+1, a later build can
+raise MIN_SUPPORTED_PROTOCOL_VERSION to N+1 and drop version N.
+
+Versions:
+1: The envelope with protocol_version, cluster_id and sender.
+2: max_protocol_version after protocol_version in the envelope. The
+   header of version 1 can't be read as the header of version 2, so
+   version 1 isn't supported.
+3: incarnation and receiver_incarnation at the end of the envelope
+   header. A message of version 2 has no incarnations, so version 2
+   isn't supported.
+4: The Heartbeat and HeartbeatAck messages, which nodes of version 3
+   can't decode, so they are only sent to nodes of version 4.
+*/
+pub const PROTOCOL_VERSION: u32 = 4;
+pub const MIN_SUPPORTED_PROTOCOL_VERSION: u32 = 3;
+pub const HEARTBEAT_PROTOCOL_VERSION: u32 = 4;
+
+/*
+Every message is sent in an envelope. The envelope starts with the
+fields of EnvelopeHeader, which all protocol versions must keep in
+the same order. Then the header of a message from an incompatible
+node can be read, and the message rejected, even if the rest of the
+message can't be decoded. protocol_version is the version the message
+is encoded in, and max_protocol_version the highest version the
+sender speaks. See Incarnations for the incarnation fields.
+*/
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Envelope<Msg> {
+    pub protocol_version: u32,
+    pub max_protocol_version: u32,
+    pub cluster_id: String,
+    pub sender: NodeId,
+    pub incarnation: u64,
+    pub receiver_incarnation: u64,
+    pub message: Msg,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct EnvelopeHeader {
+    pub protocol_version: u32,
+    pub max_protocol_version: u32,
+    pub cluster_id: String,
+    pub sender: NodeId,
+    pub incarnation: u64,
+    pub receiver_incarnation: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    Malformed,
+    IncompatibleProtocolVersion {
+        sender: NodeId,
+        protocol_version: u32,
+    },
+    OtherCluster,
+    UnknownSender,
+    Unauthenticated,
+    PreviousIncarnation,
+}
+
+// Returns the header and the message in the envelope, if it is from a
+// compatible node in the same cluster, and from the current
+// incarnation of the sender. If there are keys, the envelope must be
+// followed by a tag computed with the key of the sender.
+pub fn open_envelope<V: DeserializeOwned>(
+    bytes: &[u8],
+    cluster_id: &str,
+    node_ids: &HashSet<NodeId>,
+    protocol_versions: &ProtocolVersions,
+    keys: Option<&Keys>,
+    incarnations: &Incarnations,
+) -> Result<(EnvelopeHeader, AbdMessage<V>), Rejection> {
+    let (bytes, tag) = match keys {
+        Some(_) => authentication::split_tag(bytes)
+            .ok_or(Rejection::Unauthenticated)?,
+        None => (bytes, &[][..]),
+    };
+
+    let header: EnvelopeHeader =
+        wire_format::decode_prefix(bytes).ok_or(Rejection::Malformed)?;
+    if header.cluster_id != cluster_id {
+        return Err(Rejection::OtherCluster);
+    }
+    if !node_ids.contains(&header.sender) {
+        return Err(Rejection::UnknownSender);
+    }
+    if let Some(keys) = keys {
+        if !keys.verify(header.sender, bytes, tag) {
+            return Err(Rejection::Unauthenticated);
+        }
+    }
+    // Checked after the sender and the tag, so that messages from
+    // outside the cluster can't fill up the incompatible versions that
+    // are logged.
+    if !protocol_versions.supports(header.protocol_version) {
+        return Err(Rejection::IncompatibleProtocolVersion {
+            sender: header.sender,
+            protocol_version: header.protocol_version,
+        });
+    }
+
+    let envelope: Envelope<AbdMessage<V>> =
+        wire_format::decode(bytes).ok_or(Rejection::Malformed)?;
+    if envelope.message.sender() != envelope.sender {
+        return Err(Rejection::Malformed);
+    }
+    if !incarnations.accept(
+        header.sender,
+        header.incarnation,
+        header.receiver_incarnation,
+    ) {
+        return Err(Rejection::PreviousIncarnation);
+    }
+
+    Ok((header, envelope.message))
+}
 
-pub trait Message: Serialize {}
 
 pub trait TimestampValueMessage<V> {
     fn timestamp(&self) -> Timestamp;
@@ -17,13 +184,16 @@ pub trait TimestampValueMessage<V> {
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct WriteMessage<V> {
-    #[serde(rename = "WriteMessage")]
     pub sender: NodeId,
     pub timestamp: Timestamp,
     pub value: V,
 }
 
-impl<V: Serialize> Message for WriteMessage<V> {}
+impl<V> From<WriteMessage<V>> for AbdMessage<V> {
+    fn from(message: WriteMessage<V>) -> AbdMessage<V> {
+        AbdMessage::Write(message)
+    }
+}
 
 impl<V> TimestampValueMessage<V> for WriteMessage<V> {
     fn timestamp(&self) -> Timestamp {
@@ -35,10 +205,6 @@ impl<V> TimestampValueMessage<V> for WriteMessage<V> {
     }
 }
 
-pub fn json_is_write_message(json: &str) -> bool {
-    json.starts_with("{\"WriteMessage\":")
-}
-
 
 //
 // WriteAckMessage
@@ -46,15 +212,14 @@ pub fn json_is_write_message(json: &str) -> bool {
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct WriteAckMessage {
-    #[serde(rename = "WriteAckMessage")]
     pub sender: NodeId,
     pub timestamp: Timestamp,
 }
 
-impl Message for WriteAckMessage {}
-
-pub fn json_is_write_ack_message(json: &str) -> bool {
-    json.starts_with("{\"WriteAckMessage\":")
+impl<V> From<WriteAckMessage> for AbdMessage<V> {
+    fn from(message: WriteAckMessage) -> AbdMessage<V> {
+        AbdMessage::WriteAck(message)
+    }
 }
 
 
@@ -64,15 +229,14 @@ pub fn json_is_write_ack_message(json: &str) -> bool {
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Read1Message {
-    #[serde(rename = "Read1Message")]
     pub sender: NodeId,
     pub sequence_number: Timestamp,
 }
 
-impl Message for Read1Message {}
-
-pub fn json_is_read1_message(json: &str) -> bool {
-    json.starts_with("{\"Read1Message\":")
+impl<V> From<Read1Message> for AbdMessage<V> {
+    fn from(message: Read1Message) -> AbdMessage<V> {
+        AbdMessage::Read1(message)
+    }
 }
 
 
@@ -82,14 +246,17 @@ pub fn json_is_read1_message(json: &str) -> bool {
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Read1AckMessage<V> {
-    #[serde(rename = "Read1AckMessage")]
     pub sender: NodeId,
     pub timestamp: Timestamp,
     pub value: V,
     pub sequence_number: Timestamp,
 }
 
-impl<V: Serialize> Message for Read1AckMessage<V> {}
+impl<V> From<Read1AckMessage<V>> for AbdMessage<V> {
+    fn from(message: Read1AckMessage<V>) -> AbdMessage<V> {
+        AbdMessage::Read1Ack(message)
+    }
+}
 
 impl<V> TimestampValueMessage<V> for Read1AckMessage<V> {
     fn timestamp(&self) -> Timestamp {
@@ -101,10 +268,6 @@ impl<V> TimestampValueMessage<V> for Read1AckMessage<V> {
     }
 }
 
-pub fn json_is_read1_ack_message(json: &str) -> bool {
-    json.starts_with("{\"Read1AckMessage\":")
-}
-
 
 //
 // Read2Message
@@ -112,14 +275,17 @@ pub fn json_is_read1_ack_message(json: &str) -> bool {
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Read2Message<V> {
-    #[serde(rename = "Read2Message")]
     pub sender: NodeId,
     pub timestamp: Timestamp,
     pub value: V,
     pub sequence_number: Timestamp,
 }
 
-impl<V: Serialize> Message for Read2Message<V> {}
+impl<V> From<Read2Message<V>> for AbdMessage<V> {
+    fn from(message: Read2Message<V>) -> AbdMessage<V> {
+        AbdMessage::Read2(message)
+    }
+}
 
 impl<V> TimestampValueMessage<V> for Read2Message<V> {
     fn timestamp(&self) -> Timestamp {
@@ -131,10 +297,6 @@ impl<V> TimestampValueMessage<V> for Read2Message<V> {
     }
 }
 
-pub fn json_is_read2_message(json: &str) -> bool {
-    json.starts_with("{\"Read2Message\":")
-}
-
 
 //
 // Read2AckMessage
@@ -142,13 +304,49 @@ pub fn json_is_read2_message(json: &str) -> bool {
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Read2AckMessage {
-    #[serde(rename = "Read2AckMessage")]
     pub sender: NodeId,
     pub sequence_number: Timestamp,
 }
 
-impl Message for Read2AckMessage {}
+impl<V> From<Read2AckMessage> for AbdMessage<V> {
+    fn from(message: Read2AckMessage) -> AbdMessage<V> {
+        AbdMessage::Read2Ack(message)
+    }
+}
+
+
+//
+// HeartbeatMessage
+//
+
+// sent_at is the time of the sender's Mediator::now(), in
+// microseconds. The ack returns it, so that the sender gets the round
+// trip time without having to remember its heartbeats.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct HeartbeatMessage {
+    pub sender: NodeId,
+    pub sent_at: u64,
+}
+
+impl<V> From<HeartbeatMessage> for AbdMessage<V> {
+    fn from(message: HeartbeatMessage) -> AbdMessage<V> {
+        AbdMessage::Heartbeat(message)
+    }
+}
+
 
-pub fn json_is_read2_ack_message(json: &str) -> bool {
-    json.starts_with("{\"Read2AckMessage\":")
+//
+// HeartbeatAckMessage
+//
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct HeartbeatAckMessage {
+    pub sender: NodeId,
+    pub sent_at: u64,
+}
+
+impl<V> From<HeartbeatAckMessage> for AbdMessage<V> {
+    fn from(message: HeartbeatAckMessage) -> AbdMessage<V> {
+        AbdMessage::HeartbeatAck(message)
+    }
 }
@@ -0,0 +1,413 @@
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use serde::Serialize;
+use tiny_http::{Header, Method, Response, Server};
+
+use commons::histogram::LatencyHistogram;
+use commons::run_result::{
+    MessageTypeResult, QuorumAccessResult, RunResult,
+};
+use commons::types::Int;
+
+use crate::mediator::{Mediator, MediatorImpl};
+
+// The le bounds of the latency buckets are 2^7 to 2^24 microseconds,
+// that is, from about 0.1 ms to about 17 s.
+const MIN_BUCKET_EXPONENT: u32 = 7;
+const MAX_BUCKET_EXPONENT: u32 = 24;
+
+/*
+An http endpoint that serves the counters of the run result, the
+latencies of the reads and writes and the counters of the quorum
+accesses at /metrics, in the text format of Prometheus, so that
+a long running node can be scraped while it runs. The counters are
+the same as in the result file, read when the request comes.
+*/
+pub struct MetricsEndpoint {
+    server: Arc<Server>,
+    serve_thread: JoinHandle<()>,
+}
+
+impl MetricsEndpoint {
+    pub fn start(
+        socket_addr: SocketAddr,
+        mediator: Arc<MediatorImpl>,
+    ) -> MetricsEndpoint {
+        let server = Arc::new(
+            Server::http(socket_addr)
+                .expect("Could not create the metrics endpoint server."),
+        );
+
+        let serve_thread_server = Arc::clone(&server);
+        let serve_thread = thread::spawn(move || {
+            for request in serve_thread_server.incoming_requests() {
+                let response = if request.method() == &Method::Get
+                    && request.url() == "/metrics"
+                {
+                    let content_type = Header::from_bytes(
+                        &b"Content-Type"[..],
+                        &b"text/plain; version=0.0.4"[..],
+                    )
+                    .expect("Could not create the content type header.");
+                    Response::from_string(metrics_text(&mediator))
+                        .with_header(content_type)
+                } else {
+                    Response::from_string("Not found. Try /metrics.\n")
+                        .with_status_code(404)
+                };
+                let _ = request.respond(response);
+            }
+        });
+
+        MetricsEndpoint {
+            server: server,
+            serve_thread: serve_thread,
+        }
+    }
+
+    pub fn shutdown(self) {
+        self.server.unblock();
+        self.serve_thread
+            .join()
+            .expect("The metrics endpoint thread panicked.");
+    }
+}
+
+fn metrics_text(mediator: &MediatorImpl) -> String {
+    let run_result = mediator.run_result().clone();
+    format_metrics(&run_result)
+}
+
+// The name and help of a counter of the quorum accesses, and the field
+// of the quorum access results it counts.
+type QuorumAccessCounter =
+    (&'static str, &'static str, fn(&QuorumAccessResult) -> Int);
+
+fn format_metrics(run_result: &RunResult) -> String {
+    let mut text = String::new();
+
+    write_counters(
+        &mut text,
+        "swmr_operations_total",
+        "The number of completed client operations.",
+        "kind",
+        vec![
+            ("read".to_string(), run_result.read_ops),
+            ("write".to_string(), run_result.write_ops),
+        ],
+    );
+
+    let message_type_results = message_type_results(run_result);
+    write_counters(
+        &mut text,
+        "swmr_messages_sent_total",
+        "The number of sent messages, per message type.",
+        "type",
+        message_type_results
+            .iter()
+            .map(|(name, result)| (name.to_string(), result.sent))
+            .collect(),
+    );
+    write_counters(
+        &mut text,
+        "swmr_messages_received_total",
+        "The number of received messages, per message type.",
+        "type",
+        message_type_results
+            .iter()
+            .map(|(name, result)| (name.to_string(), result.received))
+            .collect(),
+    );
+
+    let quorum_access_results = quorum_access_results(run_result);
+    let quorum_access_counters: Vec<QuorumAccessCounter> = vec![
+        (
+            "swmr_quorum_accesses_total",
+            "The number of quorum accesses, per quorum.",
+            |result| result.accesses,
+        ),
+        (
+            "swmr_aborted_quorum_accesses_total",
+            "The number of quorum accesses that were aborted before a majority had acked.",
+            |result| result.aborted,
+        ),
+        (
+            "swmr_quorum_access_rounds_total",
+            "The number of times a quorum access sent its message, the first time or after a retransmission timeout.",
+            |result| result.rounds,
+        ),
+        (
+            "swmr_retransmissions_total",
+            "The number of times a message of a quorum access was sent again to a node that hadn't acked it.",
+            |result| result.retransmissions,
+        ),
+        (
+            "swmr_acks_before_majority_total",
+            "The number of acks that arrived while the quorum access waited for a majority.",
+            |result| result.acks_before_majority,
+        ),
+        (
+            "swmr_acks_after_majority_total",
+            "The number of acks that arrived after a majority had acked.",
+            |result| result.acks_after_majority,
+        ),
+    ];
+    for (name, help, count) in quorum_access_counters {
+        write_counters(
+            &mut text,
+            name,
+            help,
+            "quorum",
+            quorum_access_results
+                .iter()
+                .map(|(quorum, result)| {
+                    (quorum.to_string(), count(result))
+                })
+                .collect(),
+        );
+    }
+    write_counters(
+        &mut text,
+        "swmr_injected_faults_total",
+        "The number of outgoing messages that got a fault from the fault injector or the partition schedule.",
+        "fault",
+        counters_of_fields(&run_result.injected_faults),
+    );
+    write_counters(
+        &mut text,
+        "swmr_rejected_messages_total",
+        "The number of received messages that were rejected, per reason.",
+        "reason",
+        counters_of_fields(&run_result.rejected_messages),
+    );
+    write_counters(
+        &mut text,
+        "swmr_network_errors_total",
+        "The number of errors from the transports.",
+        "error",
+        counters_of_fields(&run_result.network_errors),
+    );
+
+    let mut suspicions: Vec<(String, Int)> = run_result
+        .suspected_nodes
+        .iter()
+        .map(|(node_id, count)| (node_id.to_string(), *count))
+        .collect();
+    suspicions.sort();
+    write_counters(
+        &mut text,
+        "swmr_suspicions_total",
+        "The number of times each node was suspected to have failed.",
+        "node",
+        suspicions,
+    );
+
+    write_latency_histograms(
+        &mut text,
+        "swmr_operation_latency_seconds",
+        "The latencies of the completed client operations, and of the phases of the reads.",
+        "kind",
+        &[
+            ("read", &run_result.latencies.read),
+            ("write", &run_result.latencies.write),
+            ("read_phase1", &run_result.latencies.read_phase1),
+            ("read_phase2", &run_result.latencies.read_phase2),
+        ],
+    );
+
+    write_latency_histograms(
+        &mut text,
+        "swmr_quorum_time_to_majority_seconds",
+        "The time from the first send of a quorum access until a majority had acked.",
+        "quorum",
+        &quorum_access_results
+            .iter()
+            .map(|(quorum, result)| (*quorum, &result.time_to_majority))
+            .collect::<Vec<_>>(),
+    );
+
+    text
+}
+
+fn message_type_results(
+    run_result: &RunResult,
+) -> Vec<(&'static str, &MessageTypeResult)> {
+    vec![
+        ("write", &run_result.write_message),
+        ("write_ack", &run_result.write_ack_message),
+        ("read1", &run_result.read1_message),
+        ("read1_ack", &run_result.read1_ack_message),
+        ("read2", &run_result.read2_message),
+        ("read2_ack", &run_result.read2_ack_message),
+        ("heartbeat", &run_result.heartbeat_message),
+        ("heartbeat_ack", &run_result.heartbeat_ack_message),
+    ]
+}
+
+fn quorum_access_results(
+    run_result: &RunResult,
+) -> Vec<(&'static str, &QuorumAccessResult)> {
+    let quorum_accesses = &run_result.quorum_accesses;
+    vec![
+        ("write", &quorum_accesses.write),
+        ("read1", &quorum_accesses.read1),
+        ("read2", &quorum_accesses.read2),
+    ]
+}
+
+// The counters of a struct of counters, like InjectedFaults, labeled
+// with the names of its fields, so that new fields are exported
+// without changes here.
+fn counters_of_fields<T: Serialize>(counters: &T) -> Vec<(String, Int)> {
+    let value = serde_json::to_value(counters)
+        .expect("Could not serialize the counters.");
+    value
+        .as_object()
+        .expect("The counters are not a struct.")
+        .iter()
+        .map(|(name, count)| {
+            let count =
+                count.as_i64().expect("A counter is not an integer.");
+            (name.clone(), count as Int)
+        })
+        .collect()
+}
+
+fn write_counters(
+    text: &mut String,
+    name: &str,
+    help: &str,
+    label: &str,
+    counters: Vec<(String, Int)>,
+) {
+    writeln!(text, "# HELP {} {}", name, help).unwrap();
+    writeln!(text, "# TYPE {} counter", name).unwrap();
+    for (label_value, count) in counters {
+        writeln!(
+            text,
+            "{}{{{}=\"{}\"}} {}",
+            name, label, label_value, count
+        )
+        .unwrap();
+    }
+}
+
+fn write_latency_histograms(
+    text: &mut String,
+    name: &str,
+    help: &str,
+    label: &str,
+    histograms: &[(&str, &LatencyHistogram)],
+) {
+    writeln!(text, "# HELP {} {}", name, help).unwrap();
+    writeln!(text, "# TYPE {} histogram", name).unwrap();
+    for (label_value, histogram) in histograms {
+        for exponent in MIN_BUCKET_EXPONENT..=MAX_BUCKET_EXPONENT {
+            let le = (1u64 << exponent) as f64 / 1_000_000.0;
+            writeln!(
+                text,
+                "{}_bucket{{{}=\"{}\",le=\"{}\"}} {}",
+                name,
+                label,
+                label_value,
+                le,
+                histogram.cumulative_count_at_most(exponent)
+            )
+            .unwrap();
+        }
+        writeln!(
+            text,
+            "{}_bucket{{{}=\"{}\",le=\"+Inf\"}} {}",
+            name,
+            label,
+            label_value,
+            histogram.count()
+        )
+        .unwrap();
+        writeln!(
+            text,
+            "{}_sum{{{}=\"{}\"}} {}",
+            name,
+            label,
+            label_value,
+            histogram.sum().as_secs_f64()
+        )
+        .unwrap();
+        writeln!(
+            text,
+            "{}_count{{{}=\"{}\"}} {}",
+            name,
+            label,
+            label_value,
+            histogram.count()
+        )
+        .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn metrics() -> String {
+        let mut run_result = RunResult::new();
+        run_result.write_ops = 12;
+        run_result.read1_message.sent = 30;
+        run_result.quorum_accesses.read2.retransmissions = 4;
+        run_result.quorum_accesses.write.acks_after_majority = 5;
+        run_result.rejected_messages.malformed = 2;
+        run_result.suspected_nodes.insert(3, 1);
+
+        let read_latencies = &mut run_result.latencies.read;
+        read_latencies.record(Duration::from_micros(100));
+        read_latencies.record(Duration::from_micros(300));
+        read_latencies.record(Duration::from_secs(60));
+
+        format_metrics(&run_result)
+    }
+
+    #[test]
+    fn test_that_the_counters_are_labeled() {
+        let metrics = metrics();
+
+        for line in &[
+            "swmr_operations_total{kind=\"write\"} 12",
+            "swmr_messages_sent_total{type=\"read1\"} 30",
+            "swmr_retransmissions_total{quorum=\"read2\"} 4",
+            "swmr_acks_after_majority_total{quorum=\"write\"} 5",
+            "swmr_rejected_messages_total{reason=\"malformed\"} 2",
+            "swmr_suspicions_total{node=\"3\"} 1",
+            "# TYPE swmr_network_errors_total counter",
+        ] {
+            assert!(
+                metrics.lines().any(|l| l == *line),
+                "Missing {}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn test_that_the_latency_buckets_are_cumulative() {
+        let metrics = metrics();
+
+        for line in &[
+            "swmr_operation_latency_seconds_bucket{kind=\"read\",le=\"0.000128\"} 1",
+            "swmr_operation_latency_seconds_bucket{kind=\"read\",le=\"0.000512\"} 2",
+            "swmr_operation_latency_seconds_bucket{kind=\"read\",le=\"16.777216\"} 2",
+            "swmr_operation_latency_seconds_bucket{kind=\"read\",le=\"+Inf\"} 3",
+            "swmr_operation_latency_seconds_count{kind=\"read\"} 3",
+            "swmr_operation_latency_seconds_sum{kind=\"read\"} 60.0004",
+            "swmr_operation_latency_seconds_count{kind=\"write\"} 0",
+            "swmr_operation_latency_seconds_count{kind=\"read_phase2\"} 0",
+            "swmr_quorum_time_to_majority_seconds_count{quorum=\"read1\"} 0",
+        ] {
+            assert!(metrics.lines().any(|l| l == *line), "Missing {}", line);
+        }
+    }
+}
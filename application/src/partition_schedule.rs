@@ -0,0 +1,200 @@
+use std::collections::HashSet;
+use std::fs;
+use std::time::{Duration, Instant};
+
+use commons::history;
+use commons::types::NodeId;
+
+/*
+A schedule of network partitions, read from a partition file. Each
+line of the file is one partition:
+
+10,20,1 2,3 4 5
+
+which means that from second 10 to second 20, counted from the
+partition start, nodes 1 and 2 cannot communicate with nodes 3, 4 and 5.
+Nodes within the same group, and nodes that are not mentioned, can
+still communicate. Empty lines and lines starting with # are ignored.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionSchedule {
+    partitions: Vec<Partition>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Partition {
+    start: Duration,
+    end: Duration,
+    group_a: HashSet<NodeId>,
+    group_b: HashSet<NodeId>,
+}
+
+impl Partition {
+    fn separates(&self, node_a: NodeId, node_b: NodeId) -> bool {
+        (self.group_a.contains(&node_a) && self.group_b.contains(&node_b))
+            || (self.group_b.contains(&node_a)
+                && self.group_a.contains(&node_b))
+    }
+}
+
+impl PartitionSchedule {
+    pub fn new() -> PartitionSchedule {
+        PartitionSchedule {
+            partitions: Vec::new(),
+        }
+    }
+
+    pub fn from_file(file_path: &str) -> PartitionSchedule {
+        let string = fs::read_to_string(file_path)
+            .expect("Unable to read the partition file.");
+        PartitionSchedule::from_string(&string)
+    }
+
+    pub fn from_string(string: &str) -> PartitionSchedule {
+        let mut partitions = Vec::new();
+
+        for line in string.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let components: Vec<&str> = line.split(',').collect();
+            if components.len() != 4 {
+                panic!("The partition file line \"{}\" does not have four components.", line);
+            }
+
+            let partition = Partition {
+                start: seconds_from_string(components[0]),
+                end: seconds_from_string(components[1]),
+                group_a: group_from_string(components[2]),
+                group_b: group_from_string(components[3]),
+            };
+            if !partition.group_a.is_disjoint(&partition.group_b) {
+                panic!("The groups of the partition file line \"{}\" overlap.", line);
+            }
+
+            partitions.push(partition);
+        }
+
+        PartitionSchedule {
+            partitions: partitions,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.partitions.is_empty()
+    }
+
+    // Whether a message between the two nodes is blocked at the given
+    // time since the partition start.
+    pub fn blocks(
+        &self,
+        time: Duration,
+        node_a: NodeId,
+        node_b: NodeId,
+    ) -> bool {
+        self.partitions.iter().any(|partition| {
+            partition.start <= time
+                && time < partition.end
+                && partition.separates(node_a, node_b)
+        })
+    }
+}
+
+// The instant of a wall clock time, in microseconds since the Unix
+// epoch. The partitions are counted from the same wall clock time at
+// all nodes, since their instants can't be compared.
+pub fn instant_from_wall_clock(wall_clock: u64) -> Instant {
+    let now = Instant::now();
+    let wall_clock_now = history::now_in_micros();
+    if wall_clock <= wall_clock_now {
+        let elapsed = Duration::from_micros(wall_clock_now - wall_clock);
+        now.checked_sub(elapsed).unwrap_or(now)
+    } else {
+        now + Duration::from_micros(wall_clock - wall_clock_now)
+    }
+}
+
+fn seconds_from_string(string: &str) -> Duration {
+    let seconds: f64 = string
+        .trim()
+        .parse()
+        .expect("Could not parse a time in the partition file.");
+    Duration::from_millis((seconds * 1000.0) as u64)
+}
+
+fn group_from_string(string: &str) -> HashSet<NodeId> {
+    string
+        .split_whitespace()
+        .map(|node_id| {
+            node_id
+                .parse()
+                .expect("Could not parse a node id in the partition file.")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn test_that_an_empty_schedule_blocks_nothing() {
+        let schedule = PartitionSchedule::from_string("");
+
+        assert!(schedule.is_empty());
+        assert!(!schedule.blocks(seconds(0), 1, 2));
+    }
+
+    #[test]
+    fn test_that_the_groups_are_separated_during_the_partition() {
+        let schedule = PartitionSchedule::from_string("10,20,1 2,3 4 5\n");
+
+        assert!(schedule.blocks(seconds(10), 1, 3));
+        assert!(schedule.blocks(seconds(15), 5, 2));
+        assert!(!schedule.blocks(seconds(15), 1, 2));
+        assert!(!schedule.blocks(seconds(15), 3, 4));
+        assert!(!schedule.blocks(seconds(15), 1, 6));
+    }
+
+    #[test]
+    fn test_that_the_groups_can_communicate_outside_the_partition() {
+        let schedule = PartitionSchedule::from_string("10,20,1 2,3 4 5\n");
+
+        assert!(!schedule.blocks(seconds(9), 1, 3));
+        assert!(!schedule.blocks(seconds(20), 1, 3));
+    }
+
+    #[test]
+    fn test_that_comments_and_multiple_partitions_are_parsed() {
+        let schedule = PartitionSchedule::from_string(
+            "# Isolate node 1, then node 2\n0,5,1,2 3\n\n5,10,2,1 3\n",
+        );
+
+        assert!(schedule.blocks(seconds(1), 1, 2));
+        assert!(!schedule.blocks(seconds(1), 2, 3));
+        assert!(!schedule.blocks(seconds(6), 1, 3));
+        assert!(schedule.blocks(seconds(6), 2, 3));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_that_overlapping_groups_are_rejected() {
+        PartitionSchedule::from_string("0,5,1 2,2 3\n");
+    }
+
+    #[test]
+    fn test_that_a_past_wall_clock_time_is_counted_from() {
+        let wall_clock = history::now_in_micros() - 10_000_000;
+
+        let elapsed = instant_from_wall_clock(wall_clock).elapsed();
+
+        assert!(elapsed >= seconds(10));
+        assert!(elapsed < seconds(11));
+    }
+}
@@ -0,0 +1,141 @@
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use commons::types::NodeId;
+
+/*
+Keeps track of the protocol versions of the other nodes, so that a
+cluster can run two versions at once during a rolling upgrade. Every
+envelope carries the highest version its sender speaks, so each node
+learns the versions of the others from the messages it receives.
+
+A node sends to another node in the highest version both of them
+speak. Until it has received a message from the other node, it sends
+in the lowest version it supports, which a node that hasn't been
+upgraded yet can also receive.
+*/
+pub struct ProtocolVersions {
+    min_supported_version: u32,
+    own_version: u32,
+    peer_versions: Mutex<HashMap<NodeId, u32>>,
+    logged_incompatible_versions: Mutex<HashSet<(NodeId, u32)>>,
+}
+
+impl ProtocolVersions {
+    pub fn new(
+        min_supported_version: u32,
+        own_version: u32,
+    ) -> ProtocolVersions {
+        assert!(min_supported_version <= own_version);
+
+        ProtocolVersions {
+            min_supported_version: min_supported_version,
+            own_version: own_version,
+            peer_versions: Mutex::new(HashMap::new()),
+            logged_incompatible_versions: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn own_version(&self) -> u32 {
+        self.own_version
+    }
+
+    pub fn supports(&self, version: u32) -> bool {
+        self.min_supported_version <= version
+            && version <= self.own_version
+    }
+
+    // The version to send a message to the node in. It is never
+    // below the lowest supported version, since this node can't
+    // encode older versions.
+    pub fn version_for(&self, node_id: NodeId) -> u32 {
+        self.negotiated_version(node_id)
+            .unwrap_or(self.min_supported_version)
+    }
+
+    // The highest version both this node and the other node speak, or
+    // None if nothing has been received from the other node yet.
+    pub fn negotiated_version(&self, node_id: NodeId) -> Option<u32> {
+        self.peer_versions.lock().unwrap().get(&node_id).map(
+            |&peer_version| {
+                cmp::max(
+                    cmp::min(self.own_version, peer_version),
+                    self.min_supported_version,
+                )
+            },
+        )
+    }
+
+    pub fn peer_version_received(&self, node_id: NodeId, version: u32) {
+        self.peer_versions.lock().unwrap().insert(node_id, version);
+    }
+
+    // Whether this is the first incompatible message from the node
+    // with this version, so that it is only logged once.
+    pub fn first_incompatible_message(
+        &self,
+        node_id: NodeId,
+        version: u32,
+    ) -> bool {
+        self.logged_incompatible_versions
+            .lock()
+            .unwrap()
+            .insert((node_id, version))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_only_versions_in_the_range_are_supported() {
+        let protocol_versions = ProtocolVersions::new(2, 3);
+
+        assert!(!protocol_versions.supports(1));
+        assert!(protocol_versions.supports(2));
+        assert!(protocol_versions.supports(3));
+        assert!(!protocol_versions.supports(4));
+    }
+
+    #[test]
+    fn test_that_the_lowest_version_is_sent_to_unknown_nodes() {
+        let protocol_versions = ProtocolVersions::new(2, 3);
+
+        assert_eq!(protocol_versions.version_for(5), 2);
+    }
+
+    #[test]
+    fn test_that_the_highest_common_version_is_sent_to_known_nodes() {
+        let protocol_versions = ProtocolVersions::new(2, 3);
+        protocol_versions.peer_version_received(1, 2);
+        protocol_versions.peer_version_received(2, 3);
+        protocol_versions.peer_version_received(3, 4);
+        protocol_versions.peer_version_received(4, 1);
+
+        assert_eq!(protocol_versions.version_for(1), 2);
+        assert_eq!(protocol_versions.version_for(2), 3);
+        assert_eq!(protocol_versions.version_for(3), 3);
+        assert_eq!(protocol_versions.version_for(4), 2);
+    }
+
+    #[test]
+    fn test_that_only_known_nodes_have_a_negotiated_version() {
+        let protocol_versions = ProtocolVersions::new(2, 3);
+        protocol_versions.peer_version_received(1, 2);
+
+        assert_eq!(protocol_versions.negotiated_version(1), Some(2));
+        assert_eq!(protocol_versions.negotiated_version(2), None);
+    }
+
+    #[test]
+    fn test_that_an_incompatible_version_is_only_first_once() {
+        let protocol_versions = ProtocolVersions::new(2, 3);
+
+        assert!(protocol_versions.first_incompatible_message(1, 5));
+        assert!(!protocol_versions.first_incompatible_message(1, 5));
+        assert!(protocol_versions.first_incompatible_message(1, 6));
+        assert!(protocol_versions.first_incompatible_message(2, 5));
+    }
+}
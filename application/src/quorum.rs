@@ -1,16 +1,22 @@
 
 use std::collections::HashSet;
 use std::sync::{Condvar, Mutex};
+use std::time::Duration;
 
+use commons::admin_protocol::QuorumState;
 use commons::types::{Int, NodeId};
 
+use crate::retransmission::Transmissions;
+
 //use crate::terminal_output::printlnu;
 
 pub struct Quorum {
     acking_nodes: Mutex<HashSet<NodeId>>,
     accessing: Mutex<bool>,
+    aborted: Mutex<bool>,
     majority_reached: Condvar,
     number_of_nodes: Int,
+    transmissions: Transmissions,
 }
 
 impl Quorum {
@@ -18,8 +24,10 @@ impl Quorum {
         Quorum {
             acking_nodes: Mutex::new(HashSet::new()),
             accessing: Mutex::new(false),
+            aborted: Mutex::new(false),
             majority_reached: Condvar::new(),
             number_of_nodes: number_of_nodes,
+            transmissions: Transmissions::new(),
         }
     }
 
@@ -32,8 +40,27 @@ impl Quorum {
         &self.accessing
     }
 
-    pub fn majority_reached(&self) -> &Condvar {
-        &self.majority_reached
+    pub fn transmissions(&self) -> &Transmissions {
+        &self.transmissions
+    }
+
+    pub fn is_accessing(&self) -> bool {
+        *self.accessing.lock().unwrap()
+    }
+
+    // Blocks until a majority has acked or until the timeout expires.
+    // Returns true if the timeout expired while still accessing.
+    pub fn wait_for_majority(&self, timeout: Duration) -> bool {
+        let accessing = self.accessing.lock().unwrap();
+        if !*accessing {
+            return false;
+        }
+
+        let (accessing, result) = self
+            .majority_reached
+            .wait_timeout(accessing, timeout)
+            .unwrap();
+        *accessing && result.timed_out()
     }
 
     pub fn is_idle(&self) -> bool {
@@ -53,16 +80,54 @@ impl Quorum {
         }
     }
 
+    // Ends the access without a majority, and wakes the node waiting
+    // for it. The locks are taken in the same order as in
+    // notify_if_has_ack_from_majority.
+    pub fn abort(&self) {
+        let mut acking_nodes = self.acking_nodes.lock().unwrap();
+        let mut accessing = self.accessing.lock().unwrap();
+        if *accessing {
+            acking_nodes.clear();
+            *accessing = false;
+            *self.aborted.lock().unwrap() = true;
+            self.majority_reached.notify_one();
+        }
+    }
+
+    // Whether the latest access was aborted. The flag is reset, so
+    // that the next access starts without it.
+    pub fn take_aborted(&self) -> bool {
+        let mut aborted = self.aborted.lock().unwrap();
+        let was_aborted = *aborted;
+        *aborted = false;
+        was_aborted
+    }
+
     fn has_ack_from_majority(&self) -> bool {
         let acking_nodes = self.acking_nodes.lock().unwrap();
 
         acking_nodes.len() as Int >= self.number_of_nodes_in_a_majority()
     }
 
-    fn number_of_nodes_in_a_majority(&self) -> Int {
+    pub fn number_of_nodes_in_a_majority(&self) -> Int {
         self.number_of_nodes / 2 + 1
     }
 
+    // The locks are taken in the same order as in
+    // notify_if_has_ack_from_majority.
+    pub fn state(&self) -> QuorumState {
+        let acking_nodes = self.acking_nodes.lock().unwrap();
+        let accessing = self.accessing.lock().unwrap();
+
+        let mut sorted_acking_nodes: Vec<NodeId> =
+            acking_nodes.iter().cloned().collect();
+        sorted_acking_nodes.sort();
+        QuorumState {
+            accessing: *accessing,
+            acking_nodes: sorted_acking_nodes,
+        }
+    }
+
     #[cfg(test)]
     pub fn acking_nodes(&self) -> &Mutex<HashSet<NodeId>> {
         &self.acking_nodes
@@ -84,4 +149,43 @@ mod tests {
             assert_eq!(quorum.number_of_nodes_in_a_majority(), number_of_nodes_in_a_majority[i as usize]);
         }
     }
+
+    #[test]
+    fn test_that_an_abort_wakes_the_waiting_node() {
+        let quorum = Quorum::new(3);
+        *quorum.accessing().lock().unwrap() = true;
+        quorum.insert_node_to_acking_nodes(2);
+
+        quorum.abort();
+
+        assert!(!quorum.wait_for_majority(Duration::from_secs(10)));
+        assert!(quorum.is_idle());
+        assert!(quorum.take_aborted());
+        assert!(!quorum.take_aborted());
+    }
+
+    #[test]
+    fn test_that_an_abort_without_access_does_nothing() {
+        let quorum = Quorum::new(3);
+
+        quorum.abort();
+
+        assert!(!quorum.take_aborted());
+    }
+
+    #[test]
+    fn test_that_the_state_has_the_sorted_acking_nodes() {
+        let quorum = Quorum::new(5);
+        *quorum.accessing().lock().unwrap() = true;
+        quorum.insert_node_to_acking_nodes(4);
+        quorum.insert_node_to_acking_nodes(2);
+
+        assert_eq!(
+            quorum.state(),
+            QuorumState {
+                accessing: true,
+                acking_nodes: vec![2, 4],
+            }
+        );
+    }
 }
@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use commons::types::NodeId;
+
+/*
+During a quorum access, the message is sent to all nodes, and then
+sent again to each node that hasn't acked it yet when the
+retransmission timeout of that node expires. Nodes that have acked
+are never sent the message again, so a few crashed nodes in a large
+cluster only cost a few messages per timeout.
+
+With the adaptive policy, the timeout of a node is estimated from the
+round trip times of its acks, like TCP does (RFC 6298). Each time the
+message is sent again to the same node, the timeout is multiplied by
+the backoff factor, and it is also randomly varied by up to the
+jitter, so that nodes that time out at the same time don't retransmit
+in lockstep. An ack of a message that was sent more than once gives no
+round trip time, since it isn't known which of the sends it acks.
+Instead, the backed off timeout is kept for the next quorum access
+(Karn's algorithm).
+
+With the fixed policy, the timeout is always the initial timeout.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetransmissionPolicy {
+    pub adaptive: bool,
+    pub initial_timeout: Duration,
+    pub min_timeout: Duration,
+    pub max_timeout: Duration,
+    pub backoff_factor: f64,
+    pub jitter: f64,
+}
+
+impl RetransmissionPolicy {
+    pub fn new() -> RetransmissionPolicy {
+        RetransmissionPolicy {
+            adaptive: true,
+            initial_timeout: Duration::from_millis(100),
+            min_timeout: Duration::from_millis(10),
+            max_timeout: Duration::from_millis(1000),
+            backoff_factor: 2.0,
+            jitter: 0.1,
+        }
+    }
+
+    pub fn fixed(timeout: Duration) -> RetransmissionPolicy {
+        RetransmissionPolicy {
+            adaptive: false,
+            initial_timeout: timeout,
+            min_timeout: timeout,
+            max_timeout: timeout,
+            backoff_factor: 1.0,
+            jitter: 0.0,
+        }
+    }
+
+    fn clamp(&self, timeout: Duration) -> Duration {
+        if timeout < self.min_timeout {
+            self.min_timeout
+        } else if timeout > self.max_timeout {
+            self.max_timeout
+        } else {
+            timeout
+        }
+    }
+
+    // The timeout after the message has been sent the given number of
+    // times. It is computed in seconds, so that large factors saturate
+    // at the max timeout instead of overflowing.
+    fn backoff(&self, timeout: Duration, attempts: u32) -> Duration {
+        let factor = self.backoff_factor.powi(attempts as i32 - 1);
+        let seconds = (timeout.as_secs_f64() * factor)
+            .min(self.max_timeout.as_secs_f64());
+        self.clamp(Duration::from_secs_f64(seconds))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Estimate {
+    smoothed_round_trip_time: Duration,
+    round_trip_time_variation: Duration,
+}
+
+// The round trip time estimates and retransmission timeouts of the
+// other nodes, kept across quorum accesses.
+pub struct RoundTripTimes {
+    policy: RetransmissionPolicy,
+    estimates: Mutex<HashMap<NodeId, Estimate>>,
+    timeouts: Mutex<HashMap<NodeId, Duration>>,
+    rng: Mutex<StdRng>,
+}
+
+impl RoundTripTimes {
+    pub fn new(policy: RetransmissionPolicy, seed: u64) -> RoundTripTimes {
+        RoundTripTimes {
+            policy: policy,
+            estimates: Mutex::new(HashMap::new()),
+            timeouts: Mutex::new(HashMap::new()),
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+
+    pub fn policy(&self) -> &RetransmissionPolicy {
+        &self.policy
+    }
+
+    pub fn sample(&self, node_id: NodeId, round_trip_time: Duration) {
+        let mut estimates = self.estimates.lock().unwrap();
+        let estimate = match estimates.get(&node_id) {
+            Some(estimate) => {
+                let smoothed = estimate.smoothed_round_trip_time;
+                let difference = smoothed.abs_diff(round_trip_time);
+                Estimate {
+                    smoothed_round_trip_time: smoothed * 7 / 8
+                        + round_trip_time / 8,
+                    round_trip_time_variation: estimate
+                        .round_trip_time_variation
+                        * 3
+                        / 4
+                        + difference / 4,
+                }
+            }
+            None => Estimate {
+                smoothed_round_trip_time: round_trip_time,
+                round_trip_time_variation: round_trip_time / 2,
+            },
+        };
+        estimates.insert(node_id, estimate);
+
+        let timeout = self.policy.clamp(
+            estimate.smoothed_round_trip_time
+                + estimate.round_trip_time_variation * 4,
+        );
+        self.timeouts.lock().unwrap().insert(node_id, timeout);
+    }
+
+    // Called instead of sample() when a node acks a message that was
+    // sent to it more than once.
+    pub fn backed_off(&self, node_id: NodeId, attempts: u32) {
+        let timeout =
+            self.policy.backoff(self.base_timeout(node_id), attempts);
+        self.timeouts.lock().unwrap().insert(node_id, timeout);
+    }
+
+    // The time to wait for an ack from the node, after the message has
+    // been sent to it the given number of times.
+    pub fn timeout(&self, node_id: NodeId, attempts: u32) -> Duration {
+        if !self.policy.adaptive {
+            return self.policy.initial_timeout;
+        }
+
+        let timeout =
+            self.policy.backoff(self.base_timeout(node_id), attempts);
+        let jitter = if self.policy.jitter > 0.0 {
+            self.rng
+                .lock()
+                .unwrap()
+                .gen_range(-self.policy.jitter, self.policy.jitter)
+        } else {
+            0.0
+        };
+        self.policy.clamp(timeout.mul_f64(1.0 + jitter))
+    }
+
+    fn base_timeout(&self, node_id: NodeId) -> Duration {
+        *self
+            .timeouts
+            .lock()
+            .unwrap()
+            .get(&node_id)
+            .unwrap_or(&self.policy.initial_timeout)
+    }
+
+    #[cfg(test)]
+    fn smoothed_round_trip_time(&self, node_id: NodeId) -> Duration {
+        self.estimates.lock().unwrap()[&node_id].smoothed_round_trip_time
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transmission {
+    pub first_sent: Duration,
+    pub attempts: u32,
+    pub deadline: Duration,
+    pub acked: bool,
+}
+
+// The transmissions of the message of the current, or latest, quorum
+// access, per node. The times are from Mediator::now().
+pub struct Transmissions {
+    transmissions: Mutex<HashMap<NodeId, Transmission>>,
+}
+
+impl Transmissions {
+    pub fn new() -> Transmissions {
+        Transmissions {
+            transmissions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn clear(&self) {
+        self.transmissions.lock().unwrap().clear();
+    }
+
+    pub fn attempts(&self, node_id: NodeId) -> u32 {
+        self.transmissions
+            .lock()
+            .unwrap()
+            .get(&node_id)
+            .map_or(0, |transmission| transmission.attempts)
+    }
+
+    // Schedules the first send to the node, without sending yet.
+    pub fn scheduled(&self, node_id: NodeId, deadline: Duration) {
+        self.transmissions.lock().unwrap().insert(
+            node_id,
+            Transmission {
+                first_sent: deadline,
+                attempts: 0,
+                deadline: deadline,
+                acked: false,
+            },
+        );
+    }
+
+    pub fn sent(&self, node_id: NodeId, now: Duration, timeout: Duration) {
+        let mut transmissions = self.transmissions.lock().unwrap();
+        let transmission =
+            transmissions.entry(node_id).or_insert(Transmission {
+                first_sent: now,
+                attempts: 0,
+                deadline: now,
+                acked: false,
+            });
+        if transmission.attempts == 0 {
+            transmission.first_sent = now;
+        }
+        transmission.attempts += 1;
+        transmission.deadline = now + timeout;
+    }
+
+    // Returns the transmission to the node, if this is the first ack
+    // from it and the message has been sent to it.
+    pub fn acked(&self, node_id: NodeId) -> Option<Transmission> {
+        let mut transmissions = self.transmissions.lock().unwrap();
+        match transmissions.get_mut(&node_id) {
+            Some(transmission)
+                if !transmission.acked && transmission.attempts > 0 =>
+            {
+                let unacked_transmission = *transmission;
+                transmission.acked = true;
+                Some(unacked_transmission)
+            }
+            _ => None,
+        }
+    }
+
+    // The earliest deadline of the nodes that haven't acked.
+    pub fn next_deadline(&self) -> Option<Duration> {
+        self.transmissions
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|transmission| !transmission.acked)
+            .map(|transmission| transmission.deadline)
+            .min()
+    }
+
+    // The nodes that haven't acked before their deadline, sorted so
+    // that the jitter is drawn in the same order in every run.
+    pub fn due(&self, now: Duration) -> Vec<NodeId> {
+        let mut node_ids: Vec<NodeId> = self
+            .transmissions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, transmission)| {
+                !transmission.acked && transmission.deadline <= now
+            })
+            .map(|(&node_id, _)| node_id)
+            .collect();
+        node_ids.sort();
+        node_ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn policy_without_jitter() -> RetransmissionPolicy {
+        let mut policy = RetransmissionPolicy::new();
+        policy.jitter = 0.0;
+        policy
+    }
+
+    #[test]
+    fn test_that_the_initial_timeout_is_used_for_unknown_nodes() {
+        let round_trip_times =
+            RoundTripTimes::new(policy_without_jitter(), 0);
+
+        assert_eq!(round_trip_times.timeout(2, 1), millis(100));
+    }
+
+    #[test]
+    fn test_that_the_timeout_follows_the_round_trip_time() {
+        let round_trip_times =
+            RoundTripTimes::new(policy_without_jitter(), 0);
+
+        round_trip_times.sample(2, millis(20));
+        assert_eq!(round_trip_times.timeout(2, 1), millis(60));
+
+        round_trip_times.sample(2, millis(20));
+        assert_eq!(round_trip_times.smoothed_round_trip_time(2), millis(20));
+        assert_eq!(round_trip_times.timeout(2, 1), millis(50));
+
+        round_trip_times.sample(2, millis(100));
+        assert_eq!(round_trip_times.smoothed_round_trip_time(2), millis(30));
+        assert_eq!(
+            round_trip_times.timeout(2, 1),
+            Duration::from_micros(132_500)
+        );
+    }
+
+    #[test]
+    fn test_that_the_timeout_is_kept_between_min_and_max() {
+        let round_trip_times =
+            RoundTripTimes::new(policy_without_jitter(), 0);
+
+        round_trip_times.sample(2, Duration::from_micros(100));
+        round_trip_times.sample(3, millis(5000));
+
+        assert_eq!(round_trip_times.timeout(2, 1), millis(10));
+        assert_eq!(round_trip_times.timeout(3, 1), millis(1000));
+    }
+
+    #[test]
+    fn test_that_the_timeout_backs_off_exponentially() {
+        let round_trip_times =
+            RoundTripTimes::new(policy_without_jitter(), 0);
+
+        assert_eq!(round_trip_times.timeout(2, 2), millis(200));
+        assert_eq!(round_trip_times.timeout(2, 3), millis(400));
+        assert_eq!(round_trip_times.timeout(2, 5), millis(1000));
+        assert_eq!(round_trip_times.timeout(2, 100), millis(1000));
+    }
+
+    #[test]
+    fn test_that_a_backed_off_timeout_is_kept() {
+        let round_trip_times =
+            RoundTripTimes::new(policy_without_jitter(), 0);
+
+        round_trip_times.backed_off(2, 3);
+
+        assert_eq!(round_trip_times.timeout(2, 1), millis(400));
+    }
+
+    #[test]
+    fn test_that_the_jitter_stays_within_bounds() {
+        let round_trip_times =
+            RoundTripTimes::new(RetransmissionPolicy::new(), 0);
+
+        let timeouts: Vec<Duration> = (0..100)
+            .map(|_| round_trip_times.timeout(2, 1))
+            .collect();
+
+        assert!(timeouts.iter().all(|&timeout| millis(90) <= timeout
+            && timeout <= millis(110)));
+        assert!(timeouts.iter().any(|&timeout| timeout != millis(100)));
+    }
+
+    #[test]
+    fn test_that_the_fixed_policy_ignores_round_trip_times() {
+        let round_trip_times =
+            RoundTripTimes::new(RetransmissionPolicy::fixed(millis(100)), 0);
+
+        round_trip_times.sample(2, millis(20));
+
+        assert_eq!(round_trip_times.timeout(2, 1), millis(100));
+        assert_eq!(round_trip_times.timeout(2, 4), millis(100));
+    }
+
+    #[test]
+    fn test_that_only_unacked_nodes_are_due() {
+        let transmissions = Transmissions::new();
+        transmissions.sent(1, millis(0), millis(100));
+        transmissions.sent(2, millis(0), millis(50));
+        transmissions.sent(3, millis(0), millis(50));
+
+        assert!(transmissions.acked(3).is_some());
+
+        assert_eq!(transmissions.next_deadline(), Some(millis(50)));
+        assert!(transmissions.due(millis(40)).is_empty());
+        assert_eq!(transmissions.due(millis(60)), vec![2]);
+        assert_eq!(transmissions.due(millis(100)), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_that_a_node_is_only_acked_once() {
+        let transmissions = Transmissions::new();
+        transmissions.sent(2, millis(10), millis(100));
+        transmissions.sent(2, millis(110), millis(200));
+
+        let transmission = transmissions.acked(2).unwrap();
+
+        assert_eq!(transmission.first_sent, millis(10));
+        assert_eq!(transmission.attempts, 2);
+        assert_eq!(transmissions.acked(2), None);
+        assert_eq!(transmissions.acked(3), None);
+    }
+}
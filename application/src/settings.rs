@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 use std::fs;
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::time::Duration;
 
 use clap::{App, AppSettings, Arg, ArgMatches};
@@ -10,6 +10,14 @@ use lazy_static::lazy_static;
 use commons::arguments;
 use commons::types::{Int, NodeId};
 
+use crate::authentication::Keys;
+use crate::encryption::Cipher;
+use crate::fault_injector::FaultSettings;
+use crate::partition_schedule::PartitionSchedule;
+use crate::retransmission::RetransmissionPolicy;
+use crate::transport::Address;
+use crate::wire_format::WireFormat;
+
 lazy_static! {
     pub static ref SETTINGS: Settings = Settings::new();
 }
@@ -17,11 +25,29 @@ lazy_static! {
 #[derive(Debug)]
 pub struct Settings {
     node_id: NodeId,
-    socket_addrs: HashMap<NodeId, SocketAddr>,
+    addresses: HashMap<NodeId, Address>,
     terminal_color: Color,
     client_operation: ClientOperation,
     print_client_operations: bool,
+    record_latencies: bool,
     run_length: Duration,
+    fault_settings: FaultSettings,
+    partition_schedule: PartitionSchedule,
+    partition_start: Option<u64>,
+    max_value_size: usize,
+    wire_format: WireFormat,
+    cluster_id: String,
+    keys: Option<Keys>,
+    cipher: Option<Cipher>,
+    retransmission_policy: RetransmissionPolicy,
+    heartbeat_interval: Duration,
+    suspicion_timeout: Duration,
+    fastest_quorum_first: bool,
+    client_socket_addr: Option<SocketAddr>,
+    client_timeout: Duration,
+    max_client_connections: usize,
+    admin_socket_addr: Option<SocketAddr>,
+    metrics_socket_addr: Option<SocketAddr>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -34,16 +60,62 @@ pub enum ClientOperation {
 impl Settings {
     fn new() -> Settings {
         let matches = get_matches();
+        let node_id = node_id_from_matches(&matches);
+        let addresses = addresses_from_matches(&matches);
+        let own_address = addresses
+            .get(&node_id)
+            .expect("Could not find own address.");
+        let client_socket_addr =
+            client_socket_addr_from_matches(&matches, own_address);
+        let admin_socket_addr =
+            admin_socket_addr_from_matches(&matches, own_address);
+        let metrics_socket_addr =
+            metrics_socket_addr_from_matches(&matches, own_address);
 
         Settings {
-            node_id: node_id_from_matches(&matches),
-            socket_addrs: socket_addrs_from_matches(&matches),
+            node_id: node_id,
+            addresses: addresses,
             terminal_color: color_from_matches(&matches),
             client_operation: client_operation_from_matches(&matches),
             print_client_operations: print_client_operations_from_matches(
                 &matches,
             ),
+            // The metrics endpoint serves the latencies, so they are
+            // also recorded when it is turned on.
+            record_latencies: matches.is_present("record-latencies")
+                || metrics_socket_addr.is_some(),
             run_length: run_length_from_matches(&matches),
+            fault_settings: fault_settings_from_matches(&matches),
+            partition_schedule: partition_schedule_from_matches(&matches),
+            partition_start: partition_start_from_matches(&matches),
+            max_value_size: max_value_size_from_matches(&matches),
+            wire_format: wire_format_from_matches(&matches),
+            cluster_id: cluster_id_from_matches(&matches),
+            keys: keys_from_matches(&matches),
+            cipher: cipher_from_matches(&matches),
+            retransmission_policy: retransmission_policy_from_matches(
+                &matches,
+            ),
+            heartbeat_interval: milliseconds_from_matches(
+                &matches,
+                "heartbeat-interval",
+            ),
+            suspicion_timeout: milliseconds_from_matches(
+                &matches,
+                "suspicion-timeout",
+            ),
+            fastest_quorum_first: matches
+                .is_present("fastest-quorum-first"),
+            client_socket_addr: client_socket_addr,
+            client_timeout: milliseconds_from_matches(
+                &matches,
+                "client-timeout",
+            ),
+            max_client_connections: max_client_connections_from_matches(
+                &matches,
+            ),
+            admin_socket_addr: admin_socket_addr,
+            metrics_socket_addr: metrics_socket_addr,
         }
     }
 
@@ -51,8 +123,8 @@ impl Settings {
         self.node_id
     }
 
-    pub fn socket_addrs(&self) -> &HashMap<NodeId, SocketAddr> {
-        &self.socket_addrs
+    pub fn addresses(&self) -> &HashMap<NodeId, Address> {
+        &self.addresses
     }
 
     pub fn terminal_color(&self) -> Color {
@@ -63,8 +135,12 @@ impl Settings {
         self.print_client_operations
     }
 
+    pub fn record_latencies(&self) -> bool {
+        self.record_latencies
+    }
+
     pub fn number_of_nodes(&self) -> Int {
-        self.socket_addrs.len() as Int
+        self.addresses.len() as Int
     }
 
     pub fn should_read(&self) -> bool {
@@ -78,6 +154,80 @@ impl Settings {
     pub fn run_length(&self) -> Duration {
         self.run_length
     }
+
+    pub fn fault_settings(&self) -> &FaultSettings {
+        &self.fault_settings
+    }
+
+    pub fn partition_schedule(&self) -> &PartitionSchedule {
+        &self.partition_schedule
+    }
+
+    // The wall clock time the partition schedule is counted from, in
+    // microseconds since the Unix epoch. None if it is counted from
+    // when the node started.
+    pub fn partition_start(&self) -> Option<u64> {
+        self.partition_start
+    }
+
+    pub fn max_value_size(&self) -> usize {
+        self.max_value_size
+    }
+
+    pub fn wire_format(&self) -> WireFormat {
+        self.wire_format
+    }
+
+    pub fn cluster_id(&self) -> &str {
+        &self.cluster_id
+    }
+
+    pub fn keys(&self) -> Option<&Keys> {
+        self.keys.as_ref()
+    }
+
+    pub fn cipher(&self) -> Option<&Cipher> {
+        self.cipher.as_ref()
+    }
+
+    pub fn retransmission_policy(&self) -> &RetransmissionPolicy {
+        &self.retransmission_policy
+    }
+
+    pub fn heartbeat_interval(&self) -> Duration {
+        self.heartbeat_interval
+    }
+
+    pub fn suspicion_timeout(&self) -> Duration {
+        self.suspicion_timeout
+    }
+
+    pub fn fastest_quorum_first(&self) -> bool {
+        self.fastest_quorum_first
+    }
+
+    // None if the client endpoint is turned off.
+    pub fn client_socket_addr(&self) -> Option<SocketAddr> {
+        self.client_socket_addr
+    }
+
+    pub fn client_timeout(&self) -> Duration {
+        self.client_timeout
+    }
+
+    pub fn max_client_connections(&self) -> usize {
+        self.max_client_connections
+    }
+
+    // None if the admin endpoint is turned off.
+    pub fn admin_socket_addr(&self) -> Option<SocketAddr> {
+        self.admin_socket_addr
+    }
+
+    // None if the metrics endpoint is turned off.
+    pub fn metrics_socket_addr(&self) -> Option<SocketAddr> {
+        self.metrics_socket_addr
+    }
 }
 
 fn get_matches() -> ArgMatches<'static> {
@@ -87,13 +237,77 @@ fn get_matches() -> ArgMatches<'static> {
         .about("The application code, that is an instance of an ABD node.")
         .arg(node_id_argument())
         .arg(arguments::hosts_file(
-            "The file with host ids, addresses and ports.",
+            "The file with host ids and addresses. An address is ip:port for UDP, or has a scheme: udp://ip:port, tcp://ip:port or unix://path.",
         ))
         .arg(color_argument())
         .arg(write_argument())
         .arg(read_argument())
         .arg(arguments::print_client_operations())
+        .arg(arguments::record_latencies())
         .arg(arguments::run_length())
+        .arg(probability_argument(
+            "drop-probability",
+            "The probability that an outgoing message is dropped.",
+        ))
+        .arg(probability_argument(
+            "duplicate-probability",
+            "The probability that an outgoing message is sent twice.",
+        ))
+        .arg(probability_argument(
+            "delay-probability",
+            "The probability that an outgoing message is delayed.",
+        ))
+        .arg(max_delay_argument())
+        .arg(probability_argument(
+            "reorder-probability",
+            "The probability that an outgoing message is held back and sent after the next message.",
+        ))
+        .arg(fault_seed_argument())
+        .arg(arguments::partition_file())
+        .arg(partition_start_argument())
+        .arg(max_value_size_argument())
+        .arg(wire_format_argument())
+        .arg(cluster_id_argument())
+        .arg(key_file_argument())
+        .arg(encryption_key_file_argument())
+        .arg(retransmission_policy_argument())
+        .arg(milliseconds_argument(
+            "retransmission-timeout",
+            "100",
+            "The number of milliseconds to wait for an ack before a message is sent again. With the adaptive policy, it is only used for nodes without round trip time estimates.",
+        ))
+        .arg(milliseconds_argument(
+            "min-retransmission-timeout",
+            "10",
+            "The minimum number of milliseconds of an adaptive retransmission timeout.",
+        ))
+        .arg(milliseconds_argument(
+            "max-retransmission-timeout",
+            "1000",
+            "The maximum number of milliseconds of an adaptive retransmission timeout.",
+        ))
+        .arg(retransmission_backoff_argument())
+        .arg(retransmission_jitter_argument())
+        .arg(milliseconds_argument(
+            "heartbeat-interval",
+            "100",
+            "The number of milliseconds between the heartbeats this node sends to all nodes. 0 turns off the heartbeats.",
+        ))
+        .arg(milliseconds_argument(
+            "suspicion-timeout",
+            "1000",
+            "The number of milliseconds without any message from a node before it is suspected to have failed.",
+        ))
+        .arg(fastest_quorum_first_argument())
+        .arg(arguments::client_port_offset())
+        .arg(milliseconds_argument(
+            "client-timeout",
+            "5000",
+            "The number of milliseconds a request to the client endpoint may take before it gets an error. The operation itself is not stopped.",
+        ))
+        .arg(max_client_connections_argument())
+        .arg(arguments::admin_port_offset())
+        .arg(arguments::metrics_port_offset())
         .get_matches()
 }
 
@@ -108,29 +322,27 @@ fn node_id_from_matches(matches: &ArgMatches<'static>) -> NodeId {
     matches.value_of("node-id").unwrap().parse().unwrap()
 }
 
-fn socket_addrs_from_matches(
+fn addresses_from_matches(
     matches: &ArgMatches<'static>,
-) -> HashMap<NodeId, SocketAddr> {
+) -> HashMap<NodeId, Address> {
     let hosts_file_path = matches.value_of("hosts-file").unwrap();
     let string =
         fs::read_to_string(hosts_file_path).expect("Unable to read file");
-    socket_addrs_from_string(string)
+    addresses_from_string(string)
 }
 
-fn socket_addrs_from_string(
-    string: String,
-) -> HashMap<NodeId, SocketAddr> {
-    let mut socket_addrs = HashMap::new();
+fn addresses_from_string(string: String) -> HashMap<NodeId, Address> {
+    let mut addresses = HashMap::new();
 
     for line in string.lines() {
         let components: Vec<&str> = line.split(",").collect();
         let id = components[0].parse().unwrap();
-        let socket_addr = components[1].parse().unwrap();
+        let address = Address::from_string(components[1]);
 
-        socket_addrs.insert(id, socket_addr);
+        addresses.insert(id, address);
     }
 
-    socket_addrs
+    addresses
 }
 
 fn color_argument() -> Arg<'static, 'static> {
@@ -191,3 +403,335 @@ fn run_length_from_matches(matches: &ArgMatches<'static>) -> Duration {
         .unwrap();
     Duration::from_secs(seconds)
 }
+
+fn probability_argument(
+    name: &'static str,
+    help_text: &'static str,
+) -> Arg<'static, 'static> {
+    Arg::with_name(name)
+        .long(name)
+        .takes_value(true)
+        .default_value("0")
+        .help(help_text)
+}
+
+fn probability_from_matches(
+    matches: &ArgMatches<'static>,
+    name: &str,
+) -> f64 {
+    let probability: f64 = matches
+        .value_of(name)
+        .unwrap()
+        .parse()
+        .expect("Could not parse a probability.");
+    if !(0.0..=1.0).contains(&probability) {
+        panic!("The {} must be between 0 and 1.", name);
+    }
+    probability
+}
+
+fn max_delay_argument() -> Arg<'static, 'static> {
+    Arg::with_name("max-delay")
+        .long("max-delay")
+        .takes_value(true)
+        .default_value("100")
+        .help("The maximum number of milliseconds a delayed message is delayed.")
+}
+
+fn fault_seed_argument() -> Arg<'static, 'static> {
+    Arg::with_name("fault-seed")
+        .long("fault-seed")
+        .takes_value(true)
+        .default_value("0")
+        .help("The seed for the random number generator that decides which messages get faults. The node id is added to it.")
+}
+
+fn fault_settings_from_matches(
+    matches: &ArgMatches<'static>,
+) -> FaultSettings {
+    let max_delay = matches
+        .value_of("max-delay")
+        .unwrap()
+        .parse()
+        .expect("Could not parse the max delay.");
+    let seed = matches
+        .value_of("fault-seed")
+        .unwrap()
+        .parse()
+        .expect("Could not parse the fault seed.");
+
+    FaultSettings {
+        drop_probability: probability_from_matches(
+            matches,
+            "drop-probability",
+        ),
+        duplicate_probability: probability_from_matches(
+            matches,
+            "duplicate-probability",
+        ),
+        delay_probability: probability_from_matches(
+            matches,
+            "delay-probability",
+        ),
+        max_delay: Duration::from_millis(max_delay),
+        reorder_probability: probability_from_matches(
+            matches,
+            "reorder-probability",
+        ),
+        seed: seed,
+    }
+}
+
+fn partition_schedule_from_matches(
+    matches: &ArgMatches<'static>,
+) -> PartitionSchedule {
+    match matches.value_of("partition-file") {
+        Some(file_path) => PartitionSchedule::from_file(file_path),
+        None => PartitionSchedule::new(),
+    }
+}
+
+fn partition_start_argument() -> Arg<'static, 'static> {
+    Arg::with_name("partition-start")
+        .long("partition-start")
+        .takes_value(true)
+        .requires("partition-file")
+        .help("The time the partition schedule is counted from, in microseconds since the Unix epoch. If not given, it is counted from when the node started. The local starter gives all nodes the same time, so that each partition starts and ends at the same time at all nodes.")
+}
+
+fn partition_start_from_matches(
+    matches: &ArgMatches<'static>,
+) -> Option<u64> {
+    matches.value_of("partition-start").map(|partition_start| {
+        partition_start
+            .parse()
+            .expect("Could not parse the partition start.")
+    })
+}
+
+fn max_value_size_argument() -> Arg<'static, 'static> {
+    Arg::with_name("max-value-size")
+        .long("max-value-size")
+        .takes_value(true)
+        .default_value("65536")
+        .help("The maximum number of bytes of a written value. Messages larger than what such a value needs are ignored when received.")
+}
+
+fn max_value_size_from_matches(matches: &ArgMatches<'static>) -> usize {
+    matches
+        .value_of("max-value-size")
+        .unwrap()
+        .parse()
+        .expect("Could not parse the max value size.")
+}
+
+fn wire_format_argument() -> Arg<'static, 'static> {
+    Arg::with_name("wire-format")
+        .long("wire-format")
+        .takes_value(true)
+        .possible_values(&["binary", "json"])
+        .default_value("binary")
+        .help("The format messages are sent in. Json is slower and larger, but easier to read when debugging. Messages in both formats are received.")
+}
+
+fn wire_format_from_matches(matches: &ArgMatches<'static>) -> WireFormat {
+    WireFormat::from_string(matches.value_of("wire-format").unwrap())
+}
+
+fn cluster_id_argument() -> Arg<'static, 'static> {
+    Arg::with_name("cluster-id")
+        .long("cluster-id")
+        .takes_value(true)
+        .default_value("default")
+        .help("The id of the cluster this node belongs to. Messages from nodes in other clusters are rejected.")
+}
+
+fn cluster_id_from_matches(matches: &ArgMatches<'static>) -> String {
+    matches.value_of("cluster-id").unwrap().to_string()
+}
+
+fn key_file_argument() -> Arg<'static, 'static> {
+    Arg::with_name("key-file")
+        .long("key-file")
+        .takes_value(true)
+        .help("A file with the keys the nodes authenticate their messages with. Each line is a node id and the key of that node as hex, for example \"1,000102030405060708090a0b0c0d0e0f\". Without it, messages are not authenticated.")
+}
+
+fn keys_from_matches(matches: &ArgMatches<'static>) -> Option<Keys> {
+    matches.value_of("key-file").map(Keys::from_file)
+}
+
+fn encryption_key_file_argument() -> Arg<'static, 'static> {
+    Arg::with_name("encryption-key-file")
+        .long("encryption-key-file")
+        .takes_value(true)
+        .help("A file with the key, as 64 hex characters, that all messages between the nodes of the cluster are encrypted with. All nodes must have the same key. Without it, messages are not encrypted.")
+}
+
+fn cipher_from_matches(matches: &ArgMatches<'static>) -> Option<Cipher> {
+    matches.value_of("encryption-key-file").map(Cipher::from_file)
+}
+
+fn retransmission_policy_argument() -> Arg<'static, 'static> {
+    Arg::with_name("retransmission-policy")
+        .long("retransmission-policy")
+        .takes_value(true)
+        .possible_values(&["adaptive", "fixed"])
+        .default_value("adaptive")
+        .help("How long to wait before a message is sent again to the nodes that haven't acked it. Adaptive estimates the timeout from the round trip times to each node and backs off exponentially. Fixed always waits the retransmission timeout.")
+}
+
+fn milliseconds_argument(
+    name: &'static str,
+    default_value: &'static str,
+    help_text: &'static str,
+) -> Arg<'static, 'static> {
+    Arg::with_name(name)
+        .long(name)
+        .takes_value(true)
+        .default_value(default_value)
+        .help(help_text)
+}
+
+fn milliseconds_from_matches(
+    matches: &ArgMatches<'static>,
+    name: &str,
+) -> Duration {
+    let milliseconds = matches
+        .value_of(name)
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|_| panic!("Could not parse the {}.", name));
+    Duration::from_millis(milliseconds)
+}
+
+fn retransmission_backoff_argument() -> Arg<'static, 'static> {
+    Arg::with_name("retransmission-backoff")
+        .long("retransmission-backoff")
+        .takes_value(true)
+        .default_value("2")
+        .help("The factor an adaptive retransmission timeout is multiplied by each time a message is sent again to the same node.")
+}
+
+fn retransmission_jitter_argument() -> Arg<'static, 'static> {
+    Arg::with_name("retransmission-jitter")
+        .long("retransmission-jitter")
+        .takes_value(true)
+        .default_value("0.1")
+        .help("The fraction that an adaptive retransmission timeout is randomly varied by, in both directions.")
+}
+
+fn retransmission_policy_from_matches(
+    matches: &ArgMatches<'static>,
+) -> RetransmissionPolicy {
+    let timeout =
+        milliseconds_from_matches(matches, "retransmission-timeout");
+    if matches.value_of("retransmission-policy").unwrap() == "fixed" {
+        return RetransmissionPolicy::fixed(timeout);
+    }
+
+    let min_timeout =
+        milliseconds_from_matches(matches, "min-retransmission-timeout");
+    let max_timeout =
+        milliseconds_from_matches(matches, "max-retransmission-timeout");
+    if min_timeout > max_timeout {
+        panic!("The min retransmission timeout must not be larger than the max retransmission timeout.");
+    }
+    let backoff_factor: f64 = matches
+        .value_of("retransmission-backoff")
+        .unwrap()
+        .parse()
+        .expect("Could not parse the retransmission backoff.");
+    if backoff_factor < 1.0 {
+        panic!("The retransmission backoff must be at least 1.");
+    }
+
+    let mut retransmission_policy = RetransmissionPolicy::new();
+    retransmission_policy.initial_timeout = timeout;
+    retransmission_policy.min_timeout = min_timeout;
+    retransmission_policy.max_timeout = max_timeout;
+    retransmission_policy.backoff_factor = backoff_factor;
+    retransmission_policy.jitter =
+        probability_from_matches(matches, "retransmission-jitter");
+    retransmission_policy
+}
+
+fn fastest_quorum_first_argument() -> Arg<'static, 'static> {
+    Arg::with_name("fastest-quorum-first")
+        .long("fastest-quorum-first")
+        .takes_value(false)
+        .help("Makes a quorum access first send its message only to a majority of the nodes, the ones with the shortest round trip times that aren't suspected. The other nodes get the message when the first retransmission timeout expires.")
+}
+
+// The client endpoint only listens on the loopback interface, since it
+// isn't authenticated. It is on the port of the node's own address plus
+// the offset, so that the nodes of a local cluster get different ports.
+fn client_socket_addr_from_matches(
+    matches: &ArgMatches<'static>,
+    own_address: &Address,
+) -> Option<SocketAddr> {
+    let offset = arguments::client_port_offset_from_matches(matches);
+    endpoint_port(own_address, offset)
+        .map(|port| SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port))
+}
+
+// The admin endpoint only listens on the loopback interface, since it
+// isn't authenticated.
+fn admin_socket_addr_from_matches(
+    matches: &ArgMatches<'static>,
+    own_address: &Address,
+) -> Option<SocketAddr> {
+    let offset = arguments::admin_port_offset_from_matches(matches);
+    endpoint_port(own_address, offset)
+        .map(|port| SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port))
+}
+
+// The metrics endpoint listens on all interfaces, so that Prometheus
+// can scrape the nodes of a remote cluster.
+fn metrics_socket_addr_from_matches(
+    matches: &ArgMatches<'static>,
+    own_address: &Address,
+) -> Option<SocketAddr> {
+    let offset = arguments::metrics_port_offset_from_matches(matches);
+    endpoint_port(own_address, offset).map(|port| {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port)
+    })
+}
+
+// None if the offset is 0, which turns the endpoint off.
+fn endpoint_port(own_address: &Address, offset: u16) -> Option<u16> {
+    if offset == 0 {
+        return None;
+    }
+
+    let own_port = match own_address {
+        Address::Udp(socket_addr) | Address::Tcp(socket_addr) => {
+            socket_addr.port()
+        }
+        Address::Unix(_) => {
+            panic!("The client, admin and metrics endpoints need a udp or tcp address.")
+        }
+    };
+    let port = own_port
+        .checked_add(offset)
+        .expect("The port offset gives a port above 65535.");
+    Some(port)
+}
+
+fn max_client_connections_argument() -> Arg<'static, 'static> {
+    Arg::with_name("max-client-connections")
+        .long("max-client-connections")
+        .takes_value(true)
+        .default_value("16")
+        .help("The maximum number of connections to the client endpoint at the same time. More connections get an error and are closed.")
+}
+
+fn max_client_connections_from_matches(
+    matches: &ArgMatches<'static>,
+) -> usize {
+    matches
+        .value_of("max-client-connections")
+        .unwrap()
+        .parse()
+        .expect("Could not parse the max number of client connections.")
+}
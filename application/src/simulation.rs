@@ -0,0 +1,586 @@
+use std::cmp::Ordering;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashSet};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use commons::history::{Operation, OperationKind};
+use commons::types::{Int, NodeId};
+
+use crate::mediator::Mediator;
+use crate::partition_schedule::PartitionSchedule;
+use crate::quorum::Quorum;
+use crate::retransmission::RetransmissionPolicy;
+use crate::wire_format::WireFormat;
+
+use self::simulated_mediator::SimulatedMediator;
+
+mod simulated_mediator;
+mod tests;
+
+/*
+A deterministic simulator for AbdNode. Every node gets a
+SimulatedMediator instead of a MediatorImpl, so messages are not sent
+over UDP but put in an event queue, and the quorum access timeouts
+are measured on a virtual clock instead of the wall clock.
+
+Client operations still run on their own threads, since AbdNode
+blocks inside read() and write(), but only one thread is allowed to
+run at a time. The simulator and the client threads pass a baton
+between each other, so the order in which things happen is only
+decided by the seeded random number generator. Running the same seed
+with the same settings therefore yields the exact same run.
+*/
+
+#[derive(Debug, Clone)]
+pub struct SimulationSettings {
+    pub number_of_nodes: Int,
+    pub number_of_readers: Int,
+    pub number_of_writes: Int,
+    pub number_of_reads: Int,
+    pub min_message_delay: Duration,
+    pub max_message_delay: Duration,
+    pub message_loss_probability: f64,
+    pub partition_schedule: PartitionSchedule,
+    pub wire_format: WireFormat,
+    pub retransmission_policy: RetransmissionPolicy,
+    pub fastest_quorum_first: bool,
+    pub record_latencies: bool,
+    pub max_virtual_time: Duration,
+}
+
+impl SimulationSettings {
+    pub fn new() -> SimulationSettings {
+        SimulationSettings {
+            number_of_nodes: 5,
+            number_of_readers: 4,
+            number_of_writes: 20,
+            number_of_reads: 20,
+            min_message_delay: Duration::from_millis(1),
+            max_message_delay: Duration::from_millis(150),
+            message_loss_probability: 0.0,
+            partition_schedule: PartitionSchedule::new(),
+            wire_format: WireFormat::Binary,
+            retransmission_policy: RetransmissionPolicy::new(),
+            fastest_quorum_first: false,
+            record_latencies: false,
+            max_virtual_time: Duration::from_secs(3600),
+        }
+    }
+
+    // Like for local_starter, the node with the highest id is the
+    // writer and the nodes with the lowest ids are the readers.
+    fn operation_kind_of(&self, node_id: NodeId) -> Option<OperationKind> {
+        if node_id == self.number_of_nodes && self.number_of_writes > 0 {
+            Some(OperationKind::Write)
+        } else if node_id <= self.number_of_readers {
+            Some(OperationKind::Read)
+        } else {
+            None
+        }
+    }
+
+    fn number_of_operations_of(&self, kind: OperationKind) -> Int {
+        match kind {
+            OperationKind::Write => self.number_of_writes,
+            OperationKind::Read => self.number_of_reads,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulationReport {
+    pub seed: u64,
+    pub finished: bool,
+    pub panicked: bool,
+    pub end_time: Duration,
+    pub history: Vec<Operation>,
+    pub messages_delivered: Int,
+    pub messages_lost: Int,
+    pub messages_partitioned: Int,
+}
+
+pub fn run(settings: &SimulationSettings, seed: u64) -> SimulationReport {
+    let simulator = Arc::new(Simulator::new(settings.clone(), seed));
+    let node_ids: HashSet<NodeId> =
+        (1..settings.number_of_nodes + 1).collect();
+
+    let mut mediators = BTreeMap::new();
+    for &node_id in node_ids.iter() {
+        let mediator = SimulatedMediator::new(
+            node_id,
+            node_ids.clone(),
+            Arc::clone(&simulator),
+        );
+        mediators.insert(node_id, mediator);
+    }
+
+    for (&node_id, mediator) in mediators.iter() {
+        if let Some(kind) = settings.operation_kind_of(node_id) {
+            simulator.start_client(mediator, kind);
+        }
+    }
+
+    simulator.run_event_loop(&mediators);
+    simulator.report()
+}
+
+//
+// Events
+//
+
+enum EventKind {
+    Deliver { receiver: NodeId, bytes: Vec<u8> },
+    Timeout { node_id: NodeId, timer_id: u64 },
+    StartOperation { node_id: NodeId },
+}
+
+struct Event {
+    time: Duration,
+    sequence_number: u64,
+    kind: EventKind,
+}
+
+impl PartialEq for Event {
+    fn eq(&self, other: &Event) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Event {}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Event) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Event {
+    fn cmp(&self, other: &Event) -> Ordering {
+        (self.time, self.sequence_number)
+            .cmp(&(other.time, other.sequence_number))
+    }
+}
+
+//
+// Simulator
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BatonHolder {
+    Simulator,
+    Client(NodeId),
+}
+
+struct Timer {
+    id: u64,
+    fired: bool,
+}
+
+struct State {
+    now: Duration,
+    rng: StdRng,
+    events: BinaryHeap<Reverse<Event>>,
+    next_sequence_number: u64,
+    outbox: Vec<(NodeId, NodeId, Vec<u8>)>,
+    timers: BTreeMap<NodeId, Timer>,
+    finished_clients: HashSet<NodeId>,
+    history: Vec<Operation>,
+    messages_delivered: Int,
+    messages_lost: Int,
+    messages_partitioned: Int,
+    panicked: bool,
+}
+
+pub struct Simulator {
+    settings: SimulationSettings,
+    seed: u64,
+    state: Mutex<State>,
+    baton: Mutex<BatonHolder>,
+    baton_passed: Condvar,
+}
+
+impl Simulator {
+    fn new(settings: SimulationSettings, seed: u64) -> Simulator {
+        let state = State {
+            now: Duration::from_secs(0),
+            rng: StdRng::seed_from_u64(seed),
+            events: BinaryHeap::new(),
+            next_sequence_number: 0,
+            outbox: Vec::new(),
+            timers: BTreeMap::new(),
+            finished_clients: HashSet::new(),
+            history: Vec::new(),
+            messages_delivered: 0,
+            messages_lost: 0,
+            messages_partitioned: 0,
+            panicked: false,
+        };
+
+        Simulator {
+            settings: settings,
+            seed: seed,
+            state: Mutex::new(state),
+            baton: Mutex::new(BatonHolder::Simulator),
+            baton_passed: Condvar::new(),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    fn now(&self) -> Duration {
+        self.state().now
+    }
+
+    fn schedule(&self, state: &mut State, delay: Duration, kind: EventKind) {
+        let event = Event {
+            time: state.now + delay,
+            sequence_number: state.next_sequence_number,
+            kind: kind,
+        };
+        state.next_sequence_number += 1;
+        state.events.push(Reverse(event));
+    }
+
+    fn random_delay(&self, state: &mut State) -> Duration {
+        let min = self.settings.min_message_delay.as_micros() as u64;
+        let max = self.settings.max_message_delay.as_micros() as u64;
+        Duration::from_micros(state.rng.gen_range(min, max + 1))
+    }
+
+    fn report(&self) -> SimulationReport {
+        let state = self.state();
+        let finished = state.events.is_empty()
+            && state.finished_clients.len() == self.number_of_clients();
+
+        SimulationReport {
+            seed: self.seed,
+            finished: finished,
+            panicked: state.panicked,
+            end_time: state.now,
+            history: state.history.clone(),
+            messages_delivered: state.messages_delivered,
+            messages_lost: state.messages_lost,
+            messages_partitioned: state.messages_partitioned,
+        }
+    }
+
+    fn number_of_clients(&self) -> usize {
+        (1..self.settings.number_of_nodes + 1)
+            .filter(|&node_id| {
+                self.settings.operation_kind_of(node_id).is_some()
+            })
+            .count()
+    }
+
+
+    //
+    // Event loop
+    //
+
+    fn run_event_loop(
+        &self,
+        mediators: &BTreeMap<NodeId, Arc<SimulatedMediator>>,
+    ) {
+        loop {
+            let event = {
+                let mut state = self.state();
+                match state.events.pop() {
+                    Some(Reverse(event)) => event,
+                    None => break,
+                }
+            };
+
+            if event.time > self.settings.max_virtual_time {
+                break;
+            }
+            self.state().now = event.time;
+
+            match event.kind {
+                EventKind::Deliver { receiver, bytes } => {
+                    self.state().messages_delivered += 1;
+                    mediators[&receiver].bytes_received(&bytes);
+                    self.poll_client_if_waiting(receiver);
+                }
+                EventKind::Timeout { node_id, timer_id } => {
+                    self.fire_timer(node_id, timer_id);
+                }
+                EventKind::StartOperation { node_id } => {
+                    self.resume_client(node_id);
+                }
+            }
+
+            self.flush_outbox();
+
+            if self.state().panicked {
+                break;
+            }
+        }
+    }
+
+    // The messages sent during one step are sorted before they are
+    // given delays, since AbdNode broadcasts in HashSet order, which
+    // differs between runs.
+    fn flush_outbox(&self) {
+        let mut state = self.state();
+        let mut outbox: Vec<_> = state.outbox.drain(..).collect();
+        outbox.sort();
+
+        for (sender, receiver, bytes) in outbox.into_iter() {
+            let partitioned = self
+                .settings
+                .partition_schedule
+                .blocks(state.now, sender, receiver);
+            let lost: bool = state
+                .rng
+                .gen_bool(self.settings.message_loss_probability);
+            if partitioned {
+                state.messages_partitioned += 1;
+            } else if lost {
+                state.messages_lost += 1;
+            } else {
+                let delay = self.random_delay(&mut state);
+                self.schedule(
+                    &mut state,
+                    delay,
+                    EventKind::Deliver {
+                        receiver: receiver,
+                        bytes: bytes,
+                    },
+                );
+            }
+        }
+    }
+
+    fn poll_client_if_waiting(&self, node_id: NodeId) {
+        if self.state().timers.contains_key(&node_id) {
+            self.resume_client(node_id);
+        }
+    }
+
+    fn fire_timer(&self, node_id: NodeId, timer_id: u64) {
+        let is_current_timer = {
+            let mut state = self.state();
+            match state.timers.get_mut(&node_id) {
+                Some(timer) if timer.id == timer_id => {
+                    timer.fired = true;
+                    true
+                }
+                _ => false,
+            }
+        };
+
+        if is_current_timer {
+            self.resume_client(node_id);
+        }
+    }
+
+    fn resume_client(&self, node_id: NodeId) {
+        if self.state().finished_clients.contains(&node_id) {
+            return;
+        }
+
+        self.pass_baton_and_wait(
+            BatonHolder::Client(node_id),
+            BatonHolder::Simulator,
+        );
+    }
+
+
+    //
+    // Baton passing
+    //
+
+    fn pass_baton_and_wait(&self, to: BatonHolder, me: BatonHolder) {
+        let mut holder = self.baton.lock().unwrap();
+        *holder = to;
+        self.baton_passed.notify_all();
+        self.wait_for_baton_with_guard(holder, me);
+    }
+
+    fn wait_for_baton(&self, me: BatonHolder) {
+        let holder = self.baton.lock().unwrap();
+        self.wait_for_baton_with_guard(holder, me);
+    }
+
+    fn wait_for_baton_with_guard(
+        &self,
+        mut holder: MutexGuard<BatonHolder>,
+        me: BatonHolder,
+    ) {
+        while *holder != me {
+            holder = self.baton_passed.wait(holder).unwrap();
+        }
+    }
+
+    fn release_baton(&self) {
+        let mut holder = self.baton.lock().unwrap();
+        *holder = BatonHolder::Simulator;
+        self.baton_passed.notify_all();
+    }
+
+
+    //
+    // Client side
+    //
+
+    fn start_client(
+        self: &Arc<Self>,
+        mediator: &Arc<SimulatedMediator>,
+        kind: OperationKind,
+    ) {
+        let node_id = mediator.node_id();
+        {
+            let mut state = self.state();
+            self.schedule(
+                &mut state,
+                Duration::from_secs(0),
+                EventKind::StartOperation { node_id: node_id },
+            );
+        }
+
+        let simulator = Arc::clone(self);
+        let mediator = Arc::clone(mediator);
+        thread::spawn(move || {
+            let _guard = ClientGuard {
+                node_id: node_id,
+                simulator: &simulator,
+            };
+            simulator.wait_for_baton(BatonHolder::Client(node_id));
+            simulator.client_operations(&mediator, kind);
+        });
+    }
+
+    fn client_operations(
+        &self,
+        mediator: &Arc<SimulatedMediator>,
+        kind: OperationKind,
+    ) {
+        let node_id = mediator.node_id();
+        let number_of_operations =
+            self.settings.number_of_operations_of(kind);
+
+        for operation_number in 1..number_of_operations + 1 {
+            let start = self.now();
+            let value = match kind {
+                OperationKind::Write => {
+                    let value = format!("Val: {}", operation_number);
+                    mediator
+                        .write(value.clone())
+                        .expect("Operations are never aborted in the simulation.");
+                    value
+                }
+                OperationKind::Read => mediator
+                    .read()
+                    .expect("Operations are never aborted in the simulation."),
+            };
+
+            let mut state = self.state();
+            let operation = Operation {
+                node_id: node_id,
+                kind: kind,
+                value: Some(value),
+                timestamp: start.as_micros() as u64,
+                start: start.as_micros() as u64,
+                end: Some(state.now.as_micros() as u64),
+                version: None,
+            };
+            state.history.push(operation);
+
+            if operation_number < number_of_operations {
+                let think_time = self.random_delay(&mut state);
+                self.schedule(
+                    &mut state,
+                    think_time,
+                    EventKind::StartOperation { node_id: node_id },
+                );
+                drop(state);
+
+                self.pass_baton_and_wait(
+                    BatonHolder::Simulator,
+                    BatonHolder::Client(node_id),
+                );
+            }
+        }
+    }
+
+    // Called by the SimulatedMediator instead of waiting on the
+    // quorum's Condvar. The deadline is kept across polls, so that
+    // the timeout works like in the real wait_for_majority.
+    fn wait_for_majority(
+        &self,
+        node_id: NodeId,
+        quorum: &Quorum,
+        timeout: Duration,
+    ) -> bool {
+        if !quorum.is_accessing() {
+            self.state().timers.remove(&node_id);
+            return false;
+        }
+
+        {
+            let mut state = self.state();
+            if !state.timers.contains_key(&node_id) {
+                let timer_id = state.next_sequence_number;
+                state.timers.insert(
+                    node_id,
+                    Timer {
+                        id: timer_id,
+                        fired: false,
+                    },
+                );
+                self.schedule(
+                    &mut state,
+                    timeout,
+                    EventKind::Timeout {
+                        node_id: node_id,
+                        timer_id: timer_id,
+                    },
+                );
+            }
+        }
+
+        self.pass_baton_and_wait(
+            BatonHolder::Simulator,
+            BatonHolder::Client(node_id),
+        );
+
+        let mut state = self.state();
+        let fired = state.timers[&node_id].fired;
+        if !quorum.is_accessing() || fired {
+            state.timers.remove(&node_id);
+        }
+
+        quorum.is_accessing() && fired
+    }
+
+    fn send_bytes(&self, bytes: &[u8], sender: NodeId, receiver: NodeId) {
+        self.state()
+            .outbox
+            .push((sender, receiver, bytes.to_vec()));
+    }
+}
+
+// Hands the baton back to the simulator when a client thread ends,
+// also if it ends because AbdNode panicked.
+struct ClientGuard<'a> {
+    node_id: NodeId,
+    simulator: &'a Simulator,
+}
+
+impl<'a> Drop for ClientGuard<'a> {
+    fn drop(&mut self) {
+        {
+            let mut state = self.simulator.state();
+            state.finished_clients.insert(self.node_id);
+            state.panicked |= thread::panicking();
+        }
+        self.simulator.release_baton();
+    }
+}
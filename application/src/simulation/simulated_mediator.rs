@@ -0,0 +1,155 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use commons::run_result::RunResult;
+use commons::types::{Int, NodeId, Timestamp};
+
+use crate::abd_node::{AbdNode, Aborted};
+use crate::authentication::Keys;
+use crate::failure_detector::PeerHealth;
+use crate::mediator::{Mediator, WriteError};
+use crate::quorum::Quorum;
+use crate::responsible_cell::ResponsibleCell;
+use crate::retransmission::RetransmissionPolicy;
+use crate::transport::TransportError;
+use crate::wire_format::WireFormat;
+
+use super::Simulator;
+
+pub struct SimulatedMediator {
+    node_id: NodeId,
+    node_ids: HashSet<NodeId>,
+    simulator: Arc<Simulator>,
+
+    run_result: Mutex<RunResult>,
+    abd_node: ResponsibleCell<Option<AbdNode<SimulatedMediator, String>>>,
+}
+
+impl SimulatedMediator {
+    pub fn new(
+        node_id: NodeId,
+        node_ids: HashSet<NodeId>,
+        simulator: Arc<Simulator>,
+    ) -> Arc<SimulatedMediator> {
+        let mediator = SimulatedMediator {
+            node_id: node_id,
+            node_ids: node_ids,
+            simulator: simulator,
+            run_result: Mutex::new(RunResult::new()),
+            abd_node: ResponsibleCell::new(None),
+        };
+        let mediator = Arc::new(mediator);
+        let abd_node = AbdNode::new(Arc::downgrade(&mediator));
+        *mediator.abd_node.get_mut() = Some(abd_node);
+
+        mediator
+    }
+
+    fn abd_node(&self) -> &AbdNode<SimulatedMediator, String> {
+        self.abd_node
+            .get()
+            .as_ref()
+            .expect("AbdNode not set on SimulatedMediator.")
+    }
+}
+
+impl Mediator for SimulatedMediator {
+    // Communicator
+
+    fn send_bytes_to(&self, bytes: &[u8], receiver: NodeId) {
+        self.simulator.send_bytes(bytes, self.node_id, receiver);
+    }
+
+    fn bytes_received(&self, bytes: &[u8]) {
+        self.abd_node().bytes_received(bytes);
+    }
+
+    // The simulator delivers the messages itself, without transports.
+    fn transport_error(&self, _error: TransportError) {}
+
+    // Configuration manager
+
+    fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    fn node_ids(&self) -> &HashSet<NodeId> {
+        &self.node_ids
+    }
+
+    fn number_of_nodes(&self) -> Int {
+        self.node_ids.len() as Int
+    }
+
+    fn cluster_id(&self) -> &str {
+        "simulation"
+    }
+
+    fn wire_format(&self) -> WireFormat {
+        self.simulator.settings.wire_format
+    }
+
+    fn keys(&self) -> Option<&Keys> {
+        None
+    }
+
+    fn retransmission_policy(&self) -> &RetransmissionPolicy {
+        &self.simulator.settings.retransmission_policy
+    }
+
+    fn fastest_quorum_first(&self) -> bool {
+        self.simulator.settings.fastest_quorum_first
+    }
+
+    fn record_latencies(&self) -> bool {
+        self.simulator.settings.record_latencies
+    }
+
+    // Failure detector
+
+    // There are no heartbeats in the simulation, so all nodes are
+    // regarded as alive, with unknown round trip times.
+    fn heard_from(&self, _node_id: NodeId, _round_trip_time: Option<Duration>) {
+    }
+
+    fn peer_health(&self, _node_id: NodeId) -> PeerHealth {
+        PeerHealth::unknown()
+    }
+
+    // Evaluation
+
+    fn run_result(&self) -> MutexGuard<RunResult> {
+        self.run_result.lock().unwrap()
+    }
+
+    // Timing
+
+    fn wait_for_majority(&self, quorum: &Quorum, timeout: Duration) -> bool {
+        self.simulator
+            .wait_for_majority(self.node_id, quorum, timeout)
+    }
+
+    fn now(&self) -> Duration {
+        self.simulator.now()
+    }
+
+    // Terminal output
+
+    // Rejected messages are only counted in the simulation.
+    fn log(&self, _string: String) {}
+
+    // Abd Node
+
+    fn write(&self, value: String) -> Result<(), WriteError> {
+        self.abd_node().write(value).map_err(WriteError::from)
+    }
+
+    fn read(&self) -> Result<String, Aborted> {
+        self.read_versioned().map(|(_, value)| value)
+    }
+
+    fn read_versioned(&self) -> Result<(Timestamp, String), Aborted> {
+        self.abd_node().read_versioned()
+    }
+}
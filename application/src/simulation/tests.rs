@@ -0,0 +1,218 @@
+use std::env;
+
+use commons::linearizability;
+
+use super::*;
+
+// Set SIMULATION_SEED to replay a single seed, for example one that
+// a failing test reported.
+fn seeds() -> Vec<u64> {
+    match env::var("SIMULATION_SEED") {
+        Ok(seed) => vec![seed.parse().expect("Could not parse the seed.")],
+        Err(_) => (0..10).collect(),
+    }
+}
+
+fn lossy_settings() -> SimulationSettings {
+    let mut settings = SimulationSettings::new();
+    settings.message_loss_probability = 0.3;
+    settings
+}
+
+fn write_number_of(value: &Option<String>) -> Int {
+    match value.as_ref().map(String::as_str) {
+        None | Some("") => 0,
+        Some(value) => value["Val: ".len()..].parse().unwrap(),
+    }
+}
+
+fn check_that_all_operations_terminate(settings: &SimulationSettings) {
+    for seed in seeds() {
+        let report = run(settings, seed);
+
+        assert!(!report.panicked, "Seed {} panicked.", seed);
+        assert!(report.finished, "Seed {} did not finish.", seed);
+
+        let expected_number_of_operations = settings.number_of_writes
+            + settings.number_of_readers * settings.number_of_reads;
+        assert_eq!(
+            report.history.len() as Int,
+            expected_number_of_operations,
+            "Seed {} has the wrong number of operations.",
+            seed
+        );
+    }
+}
+
+// Every read must return the value of a write that started before
+// the read ended, and that is not older than any write that ended
+// before the read started.
+fn check_that_reads_are_regular(settings: &SimulationSettings) {
+    for seed in seeds() {
+        let report = run(settings, seed);
+        let writes: Vec<&Operation> = report
+            .history
+            .iter()
+            .filter(|operation| operation.kind == OperationKind::Write)
+            .collect();
+
+        for read in report
+            .history
+            .iter()
+            .filter(|operation| operation.kind == OperationKind::Read)
+        {
+            let read_number = write_number_of(&read.value);
+            let latest_completed_write_number = writes
+                .iter()
+                .filter(|write| write.end.unwrap() < read.start)
+                .map(|write| write_number_of(&write.value))
+                .max()
+                .unwrap_or(0);
+
+            assert!(
+                read_number >= latest_completed_write_number,
+                "Seed {}: {:?} returned an overwritten value.",
+                seed,
+                read
+            );
+            if read_number > 0 {
+                let write = writes[read_number as usize - 1];
+                assert!(
+                    write.start <= read.end.unwrap(),
+                    "Seed {}: {:?} returned a value from the future.",
+                    seed,
+                    read
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_that_all_operations_terminate() {
+    check_that_all_operations_terminate(&SimulationSettings::new());
+}
+
+#[test]
+fn test_that_all_operations_terminate_with_message_loss() {
+    check_that_all_operations_terminate(&lossy_settings());
+}
+
+#[test]
+fn test_that_reads_are_regular() {
+    check_that_reads_are_regular(&SimulationSettings::new());
+}
+
+#[test]
+fn test_that_reads_are_regular_with_message_loss() {
+    check_that_reads_are_regular(&lossy_settings());
+}
+
+fn check_that_runs_are_linearizable(settings: &SimulationSettings) {
+    for seed in seeds() {
+        let report = run(settings, seed);
+
+        if let Err(violation) = linearizability::check(&report.history) {
+            panic!("Seed {} is not linearizable: {}", seed, violation);
+        }
+    }
+}
+
+#[test]
+fn test_that_runs_are_linearizable() {
+    check_that_runs_are_linearizable(&SimulationSettings::new());
+}
+
+#[test]
+fn test_that_runs_are_linearizable_with_message_loss() {
+    check_that_runs_are_linearizable(&lossy_settings());
+}
+
+#[test]
+fn test_that_runs_are_linearizable_with_json_messages() {
+    let mut settings = lossy_settings();
+    settings.wire_format = WireFormat::Json;
+    check_that_runs_are_linearizable(&settings);
+}
+
+#[test]
+fn test_that_runs_are_linearizable_with_the_fastest_quorum_first() {
+    let mut settings = lossy_settings();
+    settings.fastest_quorum_first = true;
+    check_that_runs_are_linearizable(&settings);
+}
+
+#[test]
+fn test_that_the_same_seed_gives_the_same_run() {
+    let settings = lossy_settings();
+
+    for seed in seeds() {
+        assert_eq!(run(&settings, seed), run(&settings, seed));
+    }
+}
+
+#[test]
+fn test_that_different_seeds_give_different_runs() {
+    let settings = SimulationSettings::new();
+
+    assert_ne!(run(&settings, 1).history, run(&settings, 2).history);
+}
+
+#[test]
+fn test_that_lost_messages_are_retransmitted() {
+    let settings = lossy_settings();
+    let report = run(&settings, 0);
+
+    assert!(report.messages_lost > 0);
+    assert!(report.finished);
+}
+
+#[test]
+fn test_that_the_run_stops_at_the_max_virtual_time() {
+    let mut settings = SimulationSettings::new();
+    settings.message_loss_probability = 1.0;
+    settings.max_virtual_time = Duration::from_secs(10);
+    let report = run(&settings, 0);
+
+    assert!(!report.finished);
+    assert!(report.history.is_empty());
+    assert!(report.end_time <= settings.max_virtual_time);
+}
+
+// Nodes 1 and 2 are in a minority partition from second 2 to second 6,
+// so none of their operations can both start and end in that interval.
+// The majority, with the writer 5 and the readers 3 and 4, keeps
+// serving.
+#[test]
+fn test_that_only_the_majority_partition_makes_progress() {
+    let mut settings = SimulationSettings::new();
+    settings.number_of_writes = 100;
+    settings.number_of_reads = 100;
+    settings.partition_schedule =
+        PartitionSchedule::from_string("2,6,1 2,3 4 5");
+    let partition_start = Duration::from_secs(2).as_micros() as u64;
+    let partition_end = Duration::from_secs(6).as_micros() as u64;
+
+    for seed in seeds() {
+        let report = run(&settings, seed);
+        assert!(report.finished, "Seed {} did not finish.", seed);
+        assert!(report.messages_partitioned > 0);
+
+        let nodes_with_progress_during_partition: HashSet<NodeId> = report
+            .history
+            .iter()
+            .filter(|operation| {
+                operation.start >= partition_start
+                    && operation.end.unwrap() < partition_end
+            })
+            .map(|operation| operation.node_id)
+            .collect();
+
+        let majority: HashSet<NodeId> = vec![3, 4, 5].into_iter().collect();
+        assert_eq!(
+            nodes_with_progress_during_partition, majority,
+            "Seed {} made progress in the wrong nodes.",
+            seed
+        );
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use commons::types::NodeId;
+
+use crate::mediator::Med;
+
+use self::tcp::TcpTransport;
+use self::udp::UdpTransport;
+use self::unix::UnixTransport;
+
+mod fragmentation;
+mod stream;
+mod tcp;
+mod udp;
+mod unix;
+
+/*
+A Transport sends bytes to other nodes, and delivers the bytes it
+receives to the mediator. Which transport is used for a node is
+decided by the address of the node in the hosts file:
+
+1,udp://127.0.0.1:62001
+2,tcp://127.0.0.1:62002
+3,unix:///tmp/node3.sock
+
+An address without a scheme, like 127.0.0.1:62001, is a UDP address.
+Each node listens with the transport of its own address, and sends to
+each other node with the transport of that node's address. So in the
+example above, node 1 sends to node 2 over TCP, and node 2 sends to
+node 1 over UDP.
+*/
+pub trait Transport: Send + Sync {
+    fn send_to(
+        &self,
+        bytes: &[u8],
+        receiver_id: NodeId,
+    ) -> Result<(), TransportError>;
+
+    // Stops receiving, closes the connections and joins the threads
+    // of the transport. Messages sent afterwards are dropped. Calling
+    // it again does nothing.
+    fn shutdown(&self);
+}
+
+/*
+An error when sending or receiving. The errors don't stop the node:
+a message that can't be sent is dropped, like a lost datagram, and
+ABD sends it again. The Communicator counts the errors under
+network_errors in the result file, and logs them.
+*/
+#[derive(Debug)]
+pub enum TransportError {
+    // The node has no address in the hosts file.
+    UnknownReceiver(NodeId),
+    // For example, the connection to the node was refused.
+    Send(NodeId, io::Error),
+    // For example, an ICMP port unreachable for an earlier datagram,
+    // which Linux reports on the next receive from a UDP socket.
+    Receive(io::Error),
+    // Partial messages of the sender were evicted, since it sent more
+    // incomplete messages than are kept for a sender.
+    Evicted(SocketAddr, usize),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::UnknownReceiver(receiver_id) => write!(
+                formatter,
+                "Node {} has no address in the hosts file.",
+                receiver_id
+            ),
+            TransportError::Send(receiver_id, error) => write!(
+                formatter,
+                "Could not send to node {}: {}",
+                receiver_id, error
+            ),
+            TransportError::Receive(error) => {
+                write!(formatter, "Could not receive: {}", error)
+            }
+            TransportError::Evicted(sender, number_of_messages) => write!(
+                formatter,
+                "Evicted {} partial messages from {}, which has too many incomplete messages.",
+                number_of_messages, sender
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    Udp(SocketAddr),
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl Address {
+    pub fn from_string(string: &str) -> Address {
+        let string = string.trim();
+        let (scheme, rest) = match string.find("://") {
+            Some(index) => (&string[..index], &string[index + 3..]),
+            None => ("udp", string),
+        };
+
+        match scheme {
+            "udp" => Address::Udp(socket_addr_from_string(rest)),
+            "tcp" => Address::Tcp(socket_addr_from_string(rest)),
+            "unix" => Address::Unix(PathBuf::from(rest)),
+            _ => panic!(
+                "Unknown transport {} in the address {}.",
+                scheme, string
+            ),
+        }
+    }
+}
+
+fn socket_addr_from_string(string: &str) -> SocketAddr {
+    string.parse().expect("Could not parse a socket address.")
+}
+
+// The overhead of a message, besides its value, is well below this.
+const MAX_MESSAGE_OVERHEAD: usize = 1024;
+
+// In the worst case, every byte of the value is escaped as \uXXXX in
+// json, which is six bytes.
+pub fn max_message_size(max_value_size: usize) -> usize {
+    6 * max_value_size + MAX_MESSAGE_OVERHEAD
+}
+
+// Starts listening on the address of this node, and returns the
+// transport to use for each node. Received messages larger than the
+// max message size are ignored.
+pub fn transports_from_addresses<M: Med>(
+    node_id: NodeId,
+    addresses: &HashMap<NodeId, Address>,
+    max_message_size: usize,
+    mediator: Weak<M>,
+) -> HashMap<NodeId, Arc<dyn Transport>> {
+    let mut udp_socket_addrs = HashMap::new();
+    let mut tcp_socket_addrs = HashMap::new();
+    let mut unix_paths = HashMap::new();
+    for (&id, address) in addresses.iter() {
+        match address {
+            Address::Udp(socket_addr) => {
+                udp_socket_addrs.insert(id, *socket_addr);
+            }
+            Address::Tcp(socket_addr) => {
+                tcp_socket_addrs.insert(id, *socket_addr);
+            }
+            Address::Unix(path) => {
+                unix_paths.insert(id, path.clone());
+            }
+        }
+    }
+
+    let own_address = addresses
+        .get(&node_id)
+        .expect("Could not find own address.");
+    let own_udp_socket_addr = match own_address {
+        Address::Udp(socket_addr) => Some(*socket_addr),
+        _ => None,
+    };
+    let tcp_listener = match own_address {
+        Address::Tcp(socket_addr) => Some(tcp::listen(
+            *socket_addr,
+            max_message_size,
+            mediator.clone(),
+        )),
+        _ => None,
+    };
+    let unix_listener = match own_address {
+        Address::Unix(path) => {
+            Some(unix::listen(path, max_message_size, mediator.clone()))
+        }
+        _ => None,
+    };
+
+    let udp_transport: Arc<dyn Transport> = Arc::new(UdpTransport::new(
+        own_udp_socket_addr,
+        udp_socket_addrs,
+        max_message_size,
+        mediator,
+    ));
+    let tcp_transport: Arc<dyn Transport> =
+        Arc::new(TcpTransport::new(tcp_socket_addrs, tcp_listener));
+    let unix_transport: Arc<dyn Transport> =
+        Arc::new(UnixTransport::new(unix_paths, unix_listener));
+
+    addresses
+        .iter()
+        .map(|(&id, address)| {
+            let transport = match address {
+                Address::Udp(_) => Arc::clone(&udp_transport),
+                Address::Tcp(_) => Arc::clone(&tcp_transport),
+                Address::Unix(_) => Arc::clone(&unix_transport),
+            };
+            (id, transport)
+        })
+        .collect()
+}
+
+fn deliver<M: Med>(mediator: &Weak<M>, bytes: &[u8]) {
+    mediator
+        .upgrade()
+        .expect("Error upgrading mediator in a transport")
+        .bytes_received(bytes);
+}
+
+fn report_error<M: Med>(mediator: &Weak<M>, error: TransportError) {
+    mediator
+        .upgrade()
+        .expect("Error upgrading mediator in a transport")
+        .transport_error(error);
+}
+
+const MIN_ERROR_BACKOFF: Duration = Duration::from_millis(1);
+const MAX_ERROR_BACKOFF: Duration = Duration::from_millis(500);
+
+/*
+How long a receiving thread waits after an error before it receives
+or accepts again, so that an error that doesn't go away, like running
+out of file descriptors, doesn't make the thread spin and flood the
+log. The wait doubles with every error in a row, up to
+MAX_ERROR_BACKOFF, and starts over after a success.
+*/
+struct ErrorBackoff {
+    next_backoff: Duration,
+}
+
+impl ErrorBackoff {
+    fn new() -> ErrorBackoff {
+        ErrorBackoff {
+            next_backoff: MIN_ERROR_BACKOFF,
+        }
+    }
+
+    fn error(&mut self) -> Duration {
+        let backoff = self.next_backoff;
+        self.next_backoff = (backoff * 2).min(MAX_ERROR_BACKOFF);
+        backoff
+    }
+
+    fn success(&mut self) {
+        self.next_backoff = MIN_ERROR_BACKOFF;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_the_error_backoff_doubles_up_to_the_max() {
+        let mut backoff = ErrorBackoff::new();
+
+        assert_eq!(backoff.error(), Duration::from_millis(1));
+        assert_eq!(backoff.error(), Duration::from_millis(2));
+        assert_eq!(backoff.error(), Duration::from_millis(4));
+        for _ in 0..20 {
+            backoff.error();
+        }
+        assert_eq!(backoff.error(), MAX_ERROR_BACKOFF);
+    }
+
+    #[test]
+    fn test_that_the_error_backoff_starts_over_after_a_success() {
+        let mut backoff = ErrorBackoff::new();
+        backoff.error();
+        backoff.error();
+
+        backoff.success();
+
+        assert_eq!(backoff.error(), MIN_ERROR_BACKOFF);
+    }
+
+    #[test]
+    fn test_that_an_address_without_scheme_is_udp() {
+        assert_eq!(
+            Address::from_string("127.0.0.1:62001"),
+            Address::Udp("127.0.0.1:62001".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_that_the_scheme_decides_the_transport() {
+        assert_eq!(
+            Address::from_string("udp://127.0.0.1:62001"),
+            Address::Udp("127.0.0.1:62001".parse().unwrap())
+        );
+        assert_eq!(
+            Address::from_string("tcp://10.0.0.2:62002"),
+            Address::Tcp("10.0.0.2:62002".parse().unwrap())
+        );
+        assert_eq!(
+            Address::from_string("unix:///tmp/node3.sock"),
+            Address::Unix(PathBuf::from("/tmp/node3.sock"))
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_that_an_unknown_scheme_is_rejected() {
+        Address::from_string("sctp://127.0.0.1:62001");
+    }
+}
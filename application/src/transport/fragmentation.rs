@@ -0,0 +1,478 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+// The payload of a datagram is kept below the typical MTU of 1500
+// bytes, so that the datagrams aren't fragmented by IP, where losing
+// one IP fragment loses the whole datagram.
+pub const MAX_FRAGMENT_PAYLOAD_SIZE: usize = 1400;
+pub const HEADER_SIZE: usize = 12;
+pub const MAX_DATAGRAM_SIZE: usize =
+    HEADER_SIZE + MAX_FRAGMENT_PAYLOAD_SIZE;
+
+// A message that hasn't received all its fragments within this time
+// is discarded, since some of its fragments were lost. ABD then
+// retransmits the whole message.
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The limits of the partial messages that are kept for each sender,
+// so that a sender that never completes its messages can't make the
+// receiver run out of memory. When a limit is exceeded, the oldest
+// partial messages of the sender are evicted. The bytes are limited
+// to this many messages of the max message size.
+const MAX_PARTIAL_MESSAGES_PER_SENDER: usize = 16;
+const MAX_PARTIAL_MESSAGE_SIZES_PER_SENDER: usize = 4;
+
+/*
+A message is split into fragments, each sent as one datagram. Each
+fragment starts with a header of the message id as a big-endian u64,
+followed by the index of the fragment and the number of fragments in
+the message, as big-endian u16s.
+*/
+pub fn fragments(message_id: u64, bytes: &[u8]) -> Vec<Vec<u8>> {
+    let chunks: Vec<&[u8]> = if bytes.is_empty() {
+        vec![bytes]
+    } else {
+        bytes.chunks(MAX_FRAGMENT_PAYLOAD_SIZE).collect()
+    };
+    if chunks.len() > u16::MAX as usize {
+        panic!(
+            "A message of {} bytes is too large to be fragmented.",
+            bytes.len()
+        );
+    }
+    let fragment_count = chunks.len() as u16;
+
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut fragment =
+                Vec::with_capacity(HEADER_SIZE + chunk.len());
+            fragment.extend_from_slice(&message_id.to_be_bytes());
+            fragment.extend_from_slice(&(index as u16).to_be_bytes());
+            fragment.extend_from_slice(&fragment_count.to_be_bytes());
+            fragment.extend_from_slice(chunk);
+            fragment
+        })
+        .collect()
+}
+
+struct PartialMessage {
+    fragments: Vec<Option<Vec<u8>>>,
+    number_of_received_fragments: usize,
+    number_of_received_bytes: usize,
+    first_received: Instant,
+}
+
+// The partial messages of one sender, by message id.
+struct SenderMessages {
+    partial_messages: HashMap<u64, PartialMessage>,
+    number_of_received_bytes: usize,
+}
+
+impl SenderMessages {
+    fn new() -> SenderMessages {
+        SenderMessages {
+            partial_messages: HashMap::new(),
+            number_of_received_bytes: 0,
+        }
+    }
+
+    fn remove(&mut self, message_id: u64) -> Option<PartialMessage> {
+        let partial_message = self.partial_messages.remove(&message_id)?;
+        self.number_of_received_bytes -=
+            partial_message.number_of_received_bytes;
+        Some(partial_message)
+    }
+
+    fn oldest_message_id(&self) -> Option<u64> {
+        self.partial_messages
+            .iter()
+            .min_by_key(|(_, partial_message)| {
+                partial_message.first_received
+            })
+            .map(|(message_id, _)| *message_id)
+    }
+}
+
+// Collects the fragments of the messages from all senders, and
+// returns each message when all its fragments have been received.
+pub struct Reassembler {
+    max_message_size: usize,
+    senders: HashMap<SocketAddr, SenderMessages>,
+    number_of_evicted_messages: usize,
+    last_cleanup: Instant,
+}
+
+impl Reassembler {
+    pub fn new(max_message_size: usize) -> Reassembler {
+        Reassembler {
+            max_message_size: max_message_size,
+            senders: HashMap::new(),
+            number_of_evicted_messages: 0,
+            last_cleanup: Instant::now(),
+        }
+    }
+
+    // The number of partial messages that were evicted since this was
+    // last called.
+    pub fn take_number_of_evicted_messages(&mut self) -> usize {
+        let number_of_evicted_messages = self.number_of_evicted_messages;
+        self.number_of_evicted_messages = 0;
+        number_of_evicted_messages
+    }
+
+    // Malformed fragments, and fragments of messages larger than the
+    // max message size, are ignored.
+    pub fn fragment_received(
+        &mut self,
+        sender: SocketAddr,
+        datagram: &[u8],
+    ) -> Option<Vec<u8>> {
+        self.remove_timed_out_messages();
+
+        if datagram.len() < HEADER_SIZE {
+            return None;
+        }
+        let message_id =
+            u64::from_be_bytes(datagram[0..8].try_into().unwrap());
+        let index = u16::from_be_bytes(datagram[8..10].try_into().unwrap())
+            as usize;
+        let fragment_count =
+            u16::from_be_bytes(datagram[10..12].try_into().unwrap())
+                as usize;
+        let payload = &datagram[HEADER_SIZE..];
+
+        if index >= fragment_count
+            || (fragment_count - 1) * MAX_FRAGMENT_PAYLOAD_SIZE
+                >= self.max_message_size
+        {
+            return None;
+        }
+
+        if fragment_count == 1 {
+            return Some(payload.to_vec());
+        }
+
+        let sender_messages = self
+            .senders
+            .entry(sender)
+            .or_insert_with(SenderMessages::new);
+        let partial_message = sender_messages
+            .partial_messages
+            .entry(message_id)
+            .or_insert_with(|| PartialMessage {
+                fragments: vec![None; fragment_count],
+                number_of_received_fragments: 0,
+                number_of_received_bytes: 0,
+                first_received: Instant::now(),
+            });
+        if partial_message.fragments.len() != fragment_count {
+            return None;
+        }
+        if partial_message.fragments[index].is_none() {
+            partial_message.fragments[index] = Some(payload.to_vec());
+            partial_message.number_of_received_fragments += 1;
+            partial_message.number_of_received_bytes += payload.len();
+            sender_messages.number_of_received_bytes += payload.len();
+        }
+
+        if partial_message.number_of_received_fragments < fragment_count {
+            self.evict_oldest_messages(sender);
+            return None;
+        }
+
+        let partial_message = sender_messages.remove(message_id).unwrap();
+        if sender_messages.partial_messages.is_empty() {
+            self.senders.remove(&sender);
+        }
+        let bytes = partial_message
+            .fragments
+            .into_iter()
+            .flat_map(|fragment| fragment.unwrap())
+            .collect();
+        Some(bytes)
+    }
+
+    fn evict_oldest_messages(&mut self, sender: SocketAddr) {
+        let max_number_of_bytes =
+            MAX_PARTIAL_MESSAGE_SIZES_PER_SENDER * self.max_message_size;
+        let sender_messages = self.senders.get_mut(&sender).unwrap();
+        while sender_messages.partial_messages.len()
+            > MAX_PARTIAL_MESSAGES_PER_SENDER
+            || sender_messages.number_of_received_bytes
+                > max_number_of_bytes
+        {
+            let message_id = sender_messages.oldest_message_id().unwrap();
+            sender_messages.remove(message_id);
+            self.number_of_evicted_messages += 1;
+        }
+        if sender_messages.partial_messages.is_empty() {
+            self.senders.remove(&sender);
+        }
+    }
+
+    fn remove_timed_out_messages(&mut self) {
+        if self.last_cleanup.elapsed() < REASSEMBLY_TIMEOUT {
+            return;
+        }
+        self.last_cleanup = Instant::now();
+
+        for sender_messages in self.senders.values_mut() {
+            let timed_out_message_ids: Vec<u64> = sender_messages
+                .partial_messages
+                .iter()
+                .filter(|(_, partial_message)| {
+                    partial_message.first_received.elapsed()
+                        >= REASSEMBLY_TIMEOUT
+                })
+                .map(|(message_id, _)| *message_id)
+                .collect();
+            for message_id in timed_out_message_ids {
+                sender_messages.remove(message_id);
+            }
+        }
+        self.senders.retain(|_, sender_messages| {
+            !sender_messages.partial_messages.is_empty()
+        });
+    }
+
+    #[cfg(test)]
+    fn number_of_partial_messages(&self) -> usize {
+        self.senders
+            .values()
+            .map(|sender_messages| sender_messages.partial_messages.len())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    fn sender() -> SocketAddr {
+        "127.0.0.1:62001".parse().unwrap()
+    }
+
+    fn bytes_of_size(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_that_a_small_message_is_a_single_fragment() {
+        let fragments = fragments(7, b"small");
+        assert_eq!(fragments.len(), 1);
+
+        let mut reassembler = Reassembler::new(100_000);
+        assert_eq!(
+            reassembler.fragment_received(sender(), &fragments[0]),
+            Some(b"small".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_that_an_empty_message_is_a_single_fragment() {
+        let fragments = fragments(7, b"");
+        assert_eq!(fragments.len(), 1);
+
+        let mut reassembler = Reassembler::new(100_000);
+        assert_eq!(
+            reassembler.fragment_received(sender(), &fragments[0]),
+            Some(Vec::new())
+        );
+    }
+
+    #[test]
+    fn test_that_fragments_fit_in_a_datagram() {
+        for fragment in fragments(7, &bytes_of_size(50_000)).iter() {
+            assert!(fragment.len() <= MAX_DATAGRAM_SIZE);
+        }
+    }
+
+    #[test]
+    fn test_that_fragments_in_any_order_are_reassembled() {
+        let bytes = bytes_of_size(50_000);
+        let mut fragments = fragments(7, &bytes);
+        fragments.reverse();
+        let duplicate = fragments[3].clone();
+        fragments.insert(5, duplicate);
+
+        let mut reassembler = Reassembler::new(100_000);
+        let mut messages = Vec::new();
+        for fragment in fragments.iter() {
+            if let Some(message) =
+                reassembler.fragment_received(sender(), fragment)
+            {
+                messages.push(message);
+            }
+        }
+
+        assert_eq!(messages, vec![bytes]);
+        assert_eq!(reassembler.number_of_partial_messages(), 0);
+    }
+
+    #[test]
+    fn test_that_interleaved_messages_are_kept_apart() {
+        let bytes_a = bytes_of_size(5_000);
+        let bytes_b = bytes_of_size(3_000);
+        let fragments_a = fragments(1, &bytes_a);
+        let fragments_b = fragments(2, &bytes_b);
+
+        let mut reassembler = Reassembler::new(100_000);
+        let mut messages = Vec::new();
+        for (fragment_a, fragment_b) in
+            fragments_a.iter().zip(&fragments_b)
+        {
+            messages.extend(
+                reassembler.fragment_received(sender(), fragment_a),
+            );
+            messages.extend(
+                reassembler.fragment_received(sender(), fragment_b),
+            );
+        }
+        for fragment_a in fragments_a.iter().skip(fragments_b.len()) {
+            messages.extend(
+                reassembler.fragment_received(sender(), fragment_a),
+            );
+        }
+
+        assert_eq!(messages, vec![bytes_b, bytes_a]);
+    }
+
+    #[test]
+    fn test_that_messages_larger_than_the_max_are_ignored() {
+        let mut reassembler = Reassembler::new(10_000);
+        for fragment in fragments(7, &bytes_of_size(20_000)).iter() {
+            assert_eq!(
+                reassembler.fragment_received(sender(), fragment),
+                None
+            );
+        }
+        assert_eq!(reassembler.number_of_partial_messages(), 0);
+    }
+
+    #[test]
+    fn test_that_malformed_fragments_are_ignored() {
+        let mut reassembler = Reassembler::new(100_000);
+        assert_eq!(
+            reassembler.fragment_received(sender(), b"short"),
+            None
+        );
+
+        let mut fragment = fragments(7, b"abc").remove(0);
+        fragment[9] = 1;
+        assert_eq!(
+            reassembler.fragment_received(sender(), &fragment),
+            None
+        );
+    }
+
+    #[test]
+    fn test_that_the_oldest_partial_messages_of_a_sender_are_evicted() {
+        let mut reassembler = Reassembler::new(100_000);
+        for message_id in 0..MAX_PARTIAL_MESSAGES_PER_SENDER as u64 + 2 {
+            let fragments = fragments(message_id, &bytes_of_size(5_000));
+            reassembler.fragment_received(sender(), &fragments[0]);
+        }
+
+        assert_eq!(
+            reassembler.number_of_partial_messages(),
+            MAX_PARTIAL_MESSAGES_PER_SENDER
+        );
+        assert_eq!(reassembler.take_number_of_evicted_messages(), 2);
+        assert_eq!(reassembler.take_number_of_evicted_messages(), 0);
+
+        // The newest messages can still be completed, the oldest not.
+        let newest = MAX_PARTIAL_MESSAGES_PER_SENDER as u64 + 1;
+        let bytes = bytes_of_size(5_000);
+        let mut messages = Vec::new();
+        for message_id in [0, newest].iter() {
+            for fragment in fragments(*message_id, &bytes).iter().skip(1) {
+                messages.extend(
+                    reassembler.fragment_received(sender(), fragment),
+                );
+            }
+        }
+        assert_eq!(messages, vec![bytes]);
+    }
+
+    #[test]
+    fn test_that_the_partial_messages_of_a_sender_are_limited_in_bytes() {
+        let max_message_size = 10_000;
+        let mut reassembler = Reassembler::new(max_message_size);
+        // Incomplete messages of 8400 bytes, of which four fit.
+        let fragments_per_message =
+            max_message_size / MAX_FRAGMENT_PAYLOAD_SIZE - 1;
+        let number_of_messages = MAX_PARTIAL_MESSAGE_SIZES_PER_SENDER + 1;
+        for message_id in 0..number_of_messages as u64 {
+            let fragments = fragments(
+                message_id,
+                &bytes_of_size(max_message_size - 1),
+            );
+            for fragment in fragments.iter().take(fragments_per_message) {
+                reassembler.fragment_received(sender(), fragment);
+            }
+        }
+
+        assert_eq!(
+            reassembler.number_of_partial_messages(),
+            MAX_PARTIAL_MESSAGE_SIZES_PER_SENDER
+        );
+        assert_eq!(reassembler.take_number_of_evicted_messages(), 1);
+    }
+
+    #[test]
+    fn test_that_senders_do_not_evict_each_others_messages() {
+        let other_sender: SocketAddr = "127.0.0.1:62002".parse().unwrap();
+        let mut reassembler = Reassembler::new(100_000);
+        for message_id in 0..MAX_PARTIAL_MESSAGES_PER_SENDER as u64 {
+            let fragments = fragments(message_id, &bytes_of_size(5_000));
+            reassembler.fragment_received(sender(), &fragments[0]);
+            reassembler.fragment_received(other_sender, &fragments[0]);
+        }
+
+        assert_eq!(
+            reassembler.number_of_partial_messages(),
+            2 * MAX_PARTIAL_MESSAGES_PER_SENDER
+        );
+        assert_eq!(reassembler.take_number_of_evicted_messages(), 0);
+    }
+
+    // Random datagrams, with headers that are often close enough to
+    // valid ones to reach the reassembly.
+    #[test]
+    fn test_that_random_datagrams_are_ignored_or_reassembled() {
+        let max_message_size = 10_000;
+        let mut reassembler = Reassembler::new(max_message_size);
+        let mut rng = StdRng::seed_from_u64(42);
+
+        for _ in 0..10_000 {
+            let mut datagram =
+                vec![0; rng.gen_range(0, MAX_DATAGRAM_SIZE + 1)];
+            rng.fill(&mut datagram[..]);
+            if datagram.len() >= HEADER_SIZE && rng.gen() {
+                let message_id: u64 = rng.gen_range(0, 4);
+                let index: u16 = rng.gen_range(0, 10);
+                let fragment_count: u16 = rng.gen_range(0, 10);
+                datagram[0..8].copy_from_slice(&message_id.to_be_bytes());
+                datagram[8..10].copy_from_slice(&index.to_be_bytes());
+                datagram[10..12]
+                    .copy_from_slice(&fragment_count.to_be_bytes());
+            }
+
+            if let Some(bytes) =
+                reassembler.fragment_received(sender(), &datagram)
+            {
+                assert!(
+                    bytes.len()
+                        < max_message_size + MAX_FRAGMENT_PAYLOAD_SIZE
+                );
+            }
+        }
+    }
+}
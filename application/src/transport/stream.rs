@@ -0,0 +1,574 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use commons::types::NodeId;
+
+use crate::mediator::Med;
+
+use super::{ErrorBackoff, Transport, TransportError};
+
+// After a failed connection attempt, messages to the node are dropped
+// for this long before a new attempt is made, so that a node that is
+// down doesn't slow down every broadcast.
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(500);
+// A message to a node that doesn't read what it is sent, for example
+// because it is stuck, is given up after this long. The connection is
+// then closed, and treated like a failed connection attempt.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// A connection-oriented stream, like TCP or a Unix domain socket.
+pub trait Stream: Read + Write + Send + Sized + 'static {
+    type Address: Send + Sync;
+
+    fn connect(address: &Self::Address) -> io::Result<Self>;
+    fn try_clone_stream(&self) -> io::Result<Self>;
+    fn set_write_timeout(&self, timeout: Duration) -> io::Result<()>;
+    // Closes both directions, which wakes a thread that is blocked
+    // reading from a clone of the stream.
+    fn close(&self);
+}
+
+struct Connection<S> {
+    stream: Option<S>,
+    last_failed_connect: Option<Instant>,
+}
+
+impl<S: Stream> Connection<S> {
+    fn connect_if_needed(
+        &mut self,
+        address: &S::Address,
+    ) -> io::Result<()> {
+        if self.stream.is_some() {
+            return Ok(());
+        }
+
+        if let Some(last_failed_connect) = self.last_failed_connect {
+            if last_failed_connect.elapsed() < RECONNECT_INTERVAL {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "Waiting to reconnect after a failed connection attempt.",
+                ));
+            }
+        }
+
+        let connected = S::connect(address).and_then(|stream| {
+            stream.set_write_timeout(WRITE_TIMEOUT)?;
+            Ok(stream)
+        });
+        match connected {
+            Ok(stream) => {
+                self.stream = Some(stream);
+                self.last_failed_connect = None;
+                Ok(())
+            }
+            Err(error) => {
+                self.last_failed_connect = Some(Instant::now());
+                Err(error)
+            }
+        }
+    }
+}
+
+/*
+Sends messages over one outgoing connection per node. Each message is
+sent as a frame: its length as a big-endian u32, followed by the
+bytes. A connection is opened when the first message is sent to the
+node. If the connection has been closed, for example because the
+node restarted, a new connection is opened and the message is sent
+again. Messages that can't be sent are dropped, just like lost UDP
+datagrams, and ABD retransmits them.
+*/
+pub struct StreamTransport<S: Stream> {
+    addresses: HashMap<NodeId, S::Address>,
+    connections: HashMap<NodeId, Mutex<Connection<S>>>,
+    listener: Mutex<Option<Listener<S>>>,
+    shutting_down: AtomicBool,
+}
+
+impl<S: Stream> StreamTransport<S> {
+    // The listener is the one of this node, if this node has an
+    // address of this kind of stream.
+    pub fn new(
+        addresses: HashMap<NodeId, S::Address>,
+        listener: Option<Listener<S>>,
+    ) -> StreamTransport<S> {
+        let connections = addresses
+            .keys()
+            .map(|&node_id| {
+                let connection = Connection {
+                    stream: None,
+                    last_failed_connect: None,
+                };
+                (node_id, Mutex::new(connection))
+            })
+            .collect();
+
+        StreamTransport {
+            addresses: addresses,
+            connections: connections,
+            listener: Mutex::new(listener),
+            shutting_down: AtomicBool::new(false),
+        }
+    }
+}
+
+impl<S: Stream> Transport for StreamTransport<S> {
+    fn send_to(
+        &self,
+        bytes: &[u8],
+        receiver_id: NodeId,
+    ) -> Result<(), TransportError> {
+        if self.shutting_down.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let address = self
+            .addresses
+            .get(&receiver_id)
+            .ok_or(TransportError::UnknownReceiver(receiver_id))?;
+        let mut connection =
+            self.connections[&receiver_id].lock().unwrap();
+
+        let mut result = Ok(());
+        for _ in 0..2 {
+            connection.connect_if_needed(address).map_err(|error| {
+                TransportError::Send(receiver_id, error)
+            })?;
+
+            let stream = connection.stream.as_mut().unwrap();
+            match write_frame(stream, bytes) {
+                Ok(()) => return Ok(()),
+                Err(error) if is_timeout(&error) => {
+                    connection.stream = None;
+                    connection.last_failed_connect = Some(Instant::now());
+                    return Err(TransportError::Send(receiver_id, error));
+                }
+                Err(error) => {
+                    connection.stream = None;
+                    result = Err(TransportError::Send(receiver_id, error));
+                }
+            }
+        }
+        result
+    }
+
+    fn shutdown(&self) {
+        if self.shutting_down.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        if let Some(listener) = self.listener.lock().unwrap().take() {
+            listener.shutdown();
+        }
+        for connection in self.connections.values() {
+            if let Some(stream) = connection.lock().unwrap().stream.take()
+            {
+                stream.close();
+            }
+        }
+    }
+}
+
+// The open accepted connections by connection number, with a clone of
+// each stream, so that it can be closed while its thread is blocked
+// reading from it. A connection removes itself when its thread ends.
+type AcceptedConnections<S> =
+    Arc<Mutex<HashMap<u64, (S, JoinHandle<()>)>>>;
+
+// The thread that accepts the incoming connections of this node, and
+// the threads that read from them.
+pub struct Listener<S: Stream> {
+    own_address: S::Address,
+    shutting_down: Arc<AtomicBool>,
+    accept_thread: JoinHandle<()>,
+    accepted_connections: AcceptedConnections<S>,
+}
+
+impl<S: Stream> Listener<S> {
+    // Spawns the accept thread, which accepts connections with the
+    // accept function. The own address is connected to when shutting
+    // down, to wake the accept thread.
+    pub fn spawn<A, M>(
+        accept: A,
+        own_address: S::Address,
+        max_message_size: usize,
+        mediator: Weak<M>,
+    ) -> Listener<S>
+    where
+        A: FnMut() -> io::Result<S> + Send + 'static,
+        M: Med,
+    {
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let accepted_connections = Arc::new(Mutex::new(HashMap::new()));
+
+        let accept_thread_shutting_down = Arc::clone(&shutting_down);
+        let accept_thread_connections = Arc::clone(&accepted_connections);
+        let accept_thread = thread::spawn(move || {
+            accept_loop(
+                accept,
+                max_message_size,
+                accept_thread_shutting_down,
+                accept_thread_connections,
+                mediator,
+            );
+        });
+
+        Listener {
+            own_address: own_address,
+            shutting_down: shutting_down,
+            accept_thread: accept_thread,
+            accepted_connections: accepted_connections,
+        }
+    }
+
+    fn shutdown(self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        let _ = S::connect(&self.own_address);
+        self.accept_thread
+            .join()
+            .expect("The accept thread panicked.");
+
+        let accepted_connections = std::mem::take(
+            &mut *self.accepted_connections.lock().unwrap(),
+        );
+        for (_, (stream, read_thread)) in accepted_connections {
+            stream.close();
+            read_thread.join().expect("A connection thread panicked.");
+        }
+    }
+}
+
+// Spawns a thread for every accepted connection, that delivers the
+// received messages to the mediator until the connection is closed.
+fn accept_loop<S, A, M>(
+    mut accept: A,
+    max_message_size: usize,
+    shutting_down: Arc<AtomicBool>,
+    accepted_connections: AcceptedConnections<S>,
+    mediator: Weak<M>,
+) where
+    S: Stream,
+    A: FnMut() -> io::Result<S>,
+    M: Med,
+{
+    let mut connection_number = 0;
+    let mut error_backoff = ErrorBackoff::new();
+    loop {
+        let stream = accept();
+        if shutting_down.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                super::report_error(
+                    &mediator,
+                    TransportError::Receive(error),
+                );
+                thread::sleep(error_backoff.error());
+                continue;
+            }
+        };
+        let stream_clone = match stream.try_clone_stream() {
+            Ok(stream_clone) => stream_clone,
+            Err(error) => {
+                super::report_error(
+                    &mediator,
+                    TransportError::Receive(error),
+                );
+                thread::sleep(error_backoff.error());
+                continue;
+            }
+        };
+        error_backoff.success();
+        connection_number += 1;
+        let connection_mediator = mediator.clone();
+        let connection_number_of_thread = connection_number;
+        let connections_of_thread = Arc::clone(&accepted_connections);
+        // The lock is held until the connection is inserted, so that
+        // the thread can't remove it before.
+        let mut connections = accepted_connections.lock().unwrap();
+        let read_thread = thread::spawn(move || {
+            read_loop(stream, max_message_size, connection_mediator);
+            connections_of_thread
+                .lock()
+                .unwrap()
+                .remove(&connection_number_of_thread);
+        });
+        connections.insert(connection_number, (stream_clone, read_thread));
+    }
+}
+
+// A frame that is larger than the max message size closes the
+// connection, since the sender is not a well-behaved node. A
+// connection that is closed between frames is not an error.
+fn read_loop<S: Stream, M: Med>(
+    mut stream: S,
+    max_message_size: usize,
+    mediator: Weak<M>,
+) {
+    loop {
+        match read_frame(&mut stream, max_message_size) {
+            Ok(bytes) => super::deliver(&mediator, &bytes),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                return
+            }
+            Err(error) => {
+                super::report_error(
+                    &mediator,
+                    TransportError::Receive(error),
+                );
+                return;
+            }
+        }
+    }
+}
+
+fn is_timeout(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::WouldBlock
+        || error.kind() == io::ErrorKind::TimedOut
+}
+
+pub fn write_frame<W: Write>(
+    writer: &mut W,
+    bytes: &[u8],
+) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(4 + bytes.len());
+    frame.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    frame.extend_from_slice(bytes);
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+pub fn read_frame<R: Read>(
+    reader: &mut R,
+    max_frame_size: usize,
+) -> io::Result<Vec<u8>> {
+    let mut length_bytes = [0; 4];
+    reader.read_exact(&mut length_bytes)?;
+    let length = u32::from_be_bytes(length_bytes) as usize;
+    if length > max_frame_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("A frame of {} bytes is too large.", length),
+        ));
+    }
+
+    let mut bytes = vec![0; length];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::env;
+    use std::io::Cursor;
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::os::unix::net::UnixStream;
+    use std::process;
+    use std::sync::Arc;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::abd_node::tests::MockMediator;
+    use crate::incarnations::ANY_INCARNATION;
+    use crate::mediator::Mediator;
+    use crate::messages::{
+        AbdMessage, Envelope, WriteMessage, PROTOCOL_VERSION,
+    };
+    use crate::transport::{tcp, unix};
+    use crate::wire_format::WireFormat;
+
+    use super::*;
+
+    fn write_message_bytes() -> Vec<u8> {
+        let envelope = Envelope {
+            protocol_version: PROTOCOL_VERSION,
+            max_protocol_version: PROTOCOL_VERSION,
+            cluster_id: "test".to_string(),
+            sender: 2,
+            incarnation: 1,
+            receiver_incarnation: ANY_INCARNATION,
+            message: AbdMessage::Write(WriteMessage {
+                sender: 2,
+                timestamp: 7,
+                value: "Haskell".to_string(),
+            }),
+        };
+        WireFormat::Binary.encode(&envelope)
+    }
+
+    // Node 1 sends a message to itself, through its own listener.
+    fn assert_that_a_message_is_sent_and_received<S: Stream>(
+        address: S::Address,
+        listen: impl FnOnce(Weak<MockMediator>) -> Listener<S>,
+    ) {
+        let node_ids: HashSet<NodeId> = vec![1, 2].into_iter().collect();
+        let mediator = MockMediator::new(1, node_ids);
+        let listener = listen(Arc::downgrade(&mediator));
+        let mut addresses = HashMap::new();
+        addresses.insert(1, address);
+        let transport = StreamTransport::new(addresses, Some(listener));
+
+        transport.send_to(&write_message_bytes(), 1).unwrap();
+
+        let start = Instant::now();
+        while mediator.run_result().write_message.received == 0
+            && start.elapsed() < Duration::from_secs(5)
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(mediator.run_result().write_message.received, 1);
+        transport.shutdown();
+    }
+
+    #[test]
+    fn test_that_a_message_is_sent_and_received_over_tcp() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let socket_addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+        assert_that_a_message_is_sent_and_received::<TcpStream>(
+            socket_addr,
+            |mediator| tcp::listen(socket_addr, 1000, mediator),
+        );
+    }
+
+    #[test]
+    fn test_that_sending_to_a_node_that_does_not_read_times_out() {
+        // Connections are accepted by the backlog, but never read from.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut addresses = HashMap::new();
+        addresses.insert(1, listener.local_addr().unwrap());
+        let transport: StreamTransport<TcpStream> =
+            StreamTransport::new(addresses, None);
+
+        let start = Instant::now();
+        let mut result = Ok(());
+        for _ in 0..100 {
+            result = transport.send_to(&[0; 1_000_000], 1);
+            if result.is_err() {
+                break;
+            }
+        }
+
+        assert!(result.is_err());
+        assert!(start.elapsed() < Duration::from_secs(10));
+        // The node is given up for a while, so the next send fails
+        // at once instead of waiting again.
+        let start = Instant::now();
+        assert!(transport.send_to(b"message", 1).is_err());
+        assert!(start.elapsed() < WRITE_TIMEOUT);
+    }
+
+    #[test]
+    fn test_that_a_closed_connection_is_removed() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let socket_addr = SocketAddr::from(([127, 0, 0, 1], port));
+        let node_ids: HashSet<NodeId> = vec![1, 2].into_iter().collect();
+        let mediator = MockMediator::new(1, node_ids);
+        let listener: Listener<TcpStream> =
+            tcp::listen(socket_addr, 1000, Arc::downgrade(&mediator));
+
+        for _ in 0..3 {
+            let mut stream = TcpStream::connect(socket_addr).unwrap();
+            write_frame(&mut stream, &write_message_bytes()).unwrap();
+        }
+
+        let start = Instant::now();
+        while (mediator.run_result().write_message.received < 3
+            || !listener.accepted_connections.lock().unwrap().is_empty())
+            && start.elapsed() < Duration::from_secs(5)
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(mediator.run_result().write_message.received, 3);
+        assert!(listener.accepted_connections.lock().unwrap().is_empty());
+        listener.shutdown();
+    }
+
+    #[test]
+    fn test_that_a_message_is_sent_and_received_over_unix_sockets() {
+        // Unique to this process, so that test runs at the same time
+        // don't take each other's socket.
+        let path = env::temp_dir()
+            .join(format!("stream_transport_test_{}.sock", process::id()));
+        let listen_path = path.clone();
+
+        assert_that_a_message_is_sent_and_received::<UnixStream>(
+            path,
+            |mediator| unix::listen(&listen_path, 1000, mediator),
+        );
+    }
+
+    #[test]
+    fn test_that_frames_are_read_in_the_order_they_were_written() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, b"first").unwrap();
+        write_frame(&mut buffer, b"").unwrap();
+        write_frame(&mut buffer, b"third").unwrap();
+
+        let mut reader = Cursor::new(buffer);
+        assert_eq!(
+            read_frame(&mut reader, 100).unwrap(),
+            b"first".to_vec()
+        );
+        assert_eq!(read_frame(&mut reader, 100).unwrap(), b"".to_vec());
+        assert_eq!(
+            read_frame(&mut reader, 100).unwrap(),
+            b"third".to_vec()
+        );
+        assert!(read_frame(&mut reader, 100).is_err());
+    }
+
+    #[test]
+    fn test_that_a_truncated_frame_is_an_error() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, b"truncated").unwrap();
+        buffer.truncate(8);
+
+        let mut reader = Cursor::new(buffer);
+        assert!(read_frame(&mut reader, 100).is_err());
+    }
+
+    #[test]
+    fn test_that_a_frame_larger_than_the_max_is_an_error() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, &[0; 101]).unwrap();
+
+        let mut reader = Cursor::new(buffer);
+        assert!(read_frame(&mut reader, 100).is_err());
+    }
+
+    #[test]
+    fn test_that_random_bytes_give_errors_or_small_frames() {
+        let mut rng = StdRng::seed_from_u64(42);
+
+        for _ in 0..1000 {
+            let mut buffer = vec![0; rng.gen_range(0, 200)];
+            rng.fill(&mut buffer[..]);
+            if buffer.len() >= 4 && rng.gen() {
+                buffer[0..3].copy_from_slice(&[0, 0, 0]);
+            }
+
+            let mut reader = Cursor::new(buffer);
+            while let Ok(frame) = read_frame(&mut reader, 100) {
+                assert!(frame.len() <= 100);
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use std::io;
+use std::net::{
+    IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream,
+};
+use std::sync::Weak;
+use std::time::Duration;
+
+use crate::mediator::Med;
+
+use super::stream::{Listener, Stream, StreamTransport};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub type TcpTransport = StreamTransport<TcpStream>;
+
+impl Stream for TcpStream {
+    type Address = SocketAddr;
+
+    fn connect(socket_addr: &SocketAddr) -> io::Result<TcpStream> {
+        let stream =
+            TcpStream::connect_timeout(socket_addr, CONNECT_TIMEOUT)?;
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    }
+
+    fn try_clone_stream(&self) -> io::Result<TcpStream> {
+        self.try_clone()
+    }
+
+    fn set_write_timeout(&self, timeout: Duration) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, Some(timeout))
+    }
+
+    fn close(&self) {
+        let _ = self.shutdown(Shutdown::Both);
+    }
+}
+
+pub fn listen<M: Med>(
+    own_socket_addr: SocketAddr,
+    max_message_size: usize,
+    mediator: Weak<M>,
+) -> Listener<TcpStream> {
+    let bind_socket_addr = SocketAddr::new(
+        IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
+        own_socket_addr.port(),
+    );
+    let listener = TcpListener::bind(bind_socket_addr)
+        .expect("Could not create the tcp listener.");
+    let loopback_socket_addr = SocketAddr::new(
+        IpAddr::V4(Ipv4Addr::LOCALHOST),
+        own_socket_addr.port(),
+    );
+
+    Listener::spawn(
+        move || listener.accept().map(|(stream, _)| stream),
+        loopback_socket_addr,
+        max_message_size,
+        mediator,
+    )
+}
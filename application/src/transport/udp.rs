@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use commons::types::NodeId;
+
+use crate::mediator::Med;
+
+use super::fragmentation::{self, Reassembler};
+use super::{ErrorBackoff, Transport, TransportError};
+
+// How often the receiving thread checks whether the transport is
+// shutting down, when no datagrams arrive.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Messages are split into fragments that fit in a datagram, so that
+// values larger than a datagram can be written. The message ids
+// start at a random number, so that the fragments of a node that
+// restarted aren't mixed up with the fragments it sent before.
+pub struct UdpTransport {
+    socket: UdpSocket,
+    socket_addrs: HashMap<NodeId, SocketAddr>,
+    next_message_id: AtomicU64,
+    shutting_down: Arc<AtomicBool>,
+    recv_thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl UdpTransport {
+    // If this node has a UDP address, the socket is bound to its port
+    // and received datagrams are delivered to the mediator. Otherwise
+    // the socket is only used for sending, from any port.
+    pub fn new<M: Med>(
+        own_socket_addr: Option<SocketAddr>,
+        socket_addrs: HashMap<NodeId, SocketAddr>,
+        max_message_size: usize,
+        mediator: Weak<M>,
+    ) -> UdpTransport {
+        let port =
+            own_socket_addr.map_or(0, |socket_addr| socket_addr.port());
+        let bind_socket_addr =
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
+        let socket = UdpSocket::bind(bind_socket_addr)
+            .expect("Could not create socket.");
+
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let recv_thread = if own_socket_addr.is_some() {
+            let recv_socket =
+                socket.try_clone().expect("Could not clone the socket.");
+            recv_socket
+                .set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL))
+                .expect("Could not set the read timeout of the socket.");
+            let recv_thread_shutting_down = Arc::clone(&shutting_down);
+            Some(thread::spawn(move || {
+                recv_loop(
+                    recv_socket,
+                    max_message_size,
+                    recv_thread_shutting_down,
+                    mediator,
+                );
+            }))
+        } else {
+            None
+        };
+
+        UdpTransport {
+            socket: socket,
+            socket_addrs: socket_addrs,
+            next_message_id: AtomicU64::new(rand::random()),
+            shutting_down: shutting_down,
+            recv_thread: Mutex::new(recv_thread),
+        }
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send_to(
+        &self,
+        bytes: &[u8],
+        receiver_id: NodeId,
+    ) -> Result<(), TransportError> {
+        if self.shutting_down.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let dst_socket_addr = self
+            .socket_addrs
+            .get(&receiver_id)
+            .ok_or(TransportError::UnknownReceiver(receiver_id))?;
+        let message_id =
+            self.next_message_id.fetch_add(1, Ordering::SeqCst);
+
+        for fragment in fragmentation::fragments(message_id, bytes).iter()
+        {
+            self.socket
+                .send_to(fragment, dst_socket_addr)
+                .map_err(|error| TransportError::Send(receiver_id, error))?;
+        }
+        Ok(())
+    }
+
+    fn shutdown(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        if let Some(recv_thread) = self.recv_thread.lock().unwrap().take() {
+            recv_thread
+                .join()
+                .expect("The receiving thread of the socket panicked.");
+        }
+    }
+}
+
+fn recv_loop<M: Med>(
+    socket: UdpSocket,
+    max_message_size: usize,
+    shutting_down: Arc<AtomicBool>,
+    mediator: Weak<M>,
+) {
+    let mut reassembler = Reassembler::new(max_message_size);
+    // Larger than any fragment, so that a datagram that is too large
+    // is ignored by the reassembler instead of being truncated.
+    let mut buf = vec![0; 2 * fragmentation::MAX_DATAGRAM_SIZE];
+
+    let mut error_backoff = ErrorBackoff::new();
+
+    while !shutting_down.load(Ordering::SeqCst) {
+        let (amt, sender) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(error)
+                if error.kind() == io::ErrorKind::WouldBlock
+                    || error.kind() == io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(error) => {
+                super::report_error(&mediator, TransportError::Receive(error));
+                thread::sleep(error_backoff.error());
+                continue;
+            }
+        };
+        error_backoff.success();
+
+        if amt > fragmentation::MAX_DATAGRAM_SIZE {
+            continue;
+        }
+        if let Some(bytes) =
+            reassembler.fragment_received(sender, &buf[0..amt])
+        {
+            super::deliver(&mediator, &bytes);
+        }
+        let number_of_evicted_messages =
+            reassembler.take_number_of_evicted_messages();
+        if number_of_evicted_messages > 0 {
+            super::report_error(
+                &mediator,
+                TransportError::Evicted(
+                    sender,
+                    number_of_evicted_messages,
+                ),
+            );
+        }
+    }
+}
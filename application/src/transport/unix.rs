@@ -0,0 +1,55 @@
+use std::fs;
+use std::io;
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Weak;
+use std::time::Duration;
+
+use crate::mediator::Med;
+
+use super::stream::{Listener, Stream, StreamTransport};
+
+pub type UnixTransport = StreamTransport<UnixStream>;
+
+impl Stream for UnixStream {
+    type Address = PathBuf;
+
+    fn connect(path: &PathBuf) -> io::Result<UnixStream> {
+        UnixStream::connect(path)
+    }
+
+    fn try_clone_stream(&self) -> io::Result<UnixStream> {
+        self.try_clone()
+    }
+
+    fn set_write_timeout(&self, timeout: Duration) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, Some(timeout))
+    }
+
+    fn close(&self) {
+        let _ = self.shutdown(Shutdown::Both);
+    }
+}
+
+pub fn listen<M: Med>(
+    own_path: &Path,
+    max_message_size: usize,
+    mediator: Weak<M>,
+) -> Listener<UnixStream> {
+    // The socket file of an earlier run is left behind when the node
+    // exits, and must be removed before the path can be bound again.
+    if own_path.exists() {
+        fs::remove_file(own_path)
+            .expect("Could not remove the old unix socket file.");
+    }
+    let listener = UnixListener::bind(own_path)
+        .expect("Could not create the unix socket listener.");
+
+    Listener::spawn(
+        move || listener.accept().map(|(stream, _)| stream),
+        own_path.to_path_buf(),
+        max_message_size,
+        mediator,
+    )
+}
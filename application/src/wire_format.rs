@@ -0,0 +1,135 @@
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+const BINARY_MARKER: u8 = 0;
+
+/*
+The format messages are sent in. Binary is bincode, which is compact
+and fast to encode and decode. Json is slower and larger, but easy to
+read when debugging, for example with tcpdump.
+
+A binary message starts with a zero byte, and a json message starts
+with {. So a received message is decoded in the format it was sent
+in, regardless of the format this node sends in, and nodes with
+different wire formats can be mixed.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireFormat {
+    Json,
+    Binary,
+}
+
+impl WireFormat {
+    pub fn from_string(string: &str) -> WireFormat {
+        match string {
+            "json" => WireFormat::Json,
+            "binary" => WireFormat::Binary,
+            _ => panic!("Unknown wire format {}.", string),
+        }
+    }
+
+    pub fn encode<T: Serialize>(&self, message: &T) -> Vec<u8> {
+        match self {
+            WireFormat::Json => serde_json::to_vec(message)
+                .expect("Could not serialize a message"),
+            WireFormat::Binary => {
+                let mut bytes = vec![BINARY_MARKER];
+                bincode_options()
+                    .serialize_into(&mut bytes, message)
+                    .expect("Could not serialize a message");
+                bytes
+            }
+        }
+    }
+}
+
+// Returns None if the bytes are not a message of the expected type.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Option<T> {
+    match bytes.first() {
+        // The limit makes sure that a malformed length in the bytes
+        // can't make bincode allocate more memory than the size of
+        // the message.
+        Some(&BINARY_MARKER) => bincode_options()
+            .with_limit(bytes.len() as u64)
+            .deserialize(&bytes[1..])
+            .ok(),
+        Some(_) => serde_json::from_slice(bytes).ok(),
+        None => None,
+    }
+}
+
+// Like decode, but the bytes only have to start with a T. For json,
+// this means that the object may have more fields than T.
+pub fn decode_prefix<T: DeserializeOwned>(bytes: &[u8]) -> Option<T> {
+    match bytes.first() {
+        Some(&BINARY_MARKER) => bincode_options()
+            .with_limit(bytes.len() as u64)
+            .allow_trailing_bytes()
+            .deserialize(&bytes[1..])
+            .ok(),
+        Some(_) => serde_json::from_slice(bytes).ok(),
+        None => None,
+    }
+}
+
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum TestMessage {
+        Number(u64),
+        Text { sender: i32, text: String },
+    }
+
+    fn test_messages() -> Vec<TestMessage> {
+        vec![
+            TestMessage::Number(7),
+            TestMessage::Text {
+                sender: 3,
+                text: "Rust".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_that_both_formats_decode_what_they_encode() {
+        for &wire_format in [WireFormat::Json, WireFormat::Binary].iter() {
+            for message in test_messages() {
+                let bytes = wire_format.encode(&message);
+                assert_eq!(decode::<TestMessage>(&bytes), Some(message));
+            }
+        }
+    }
+
+    #[test]
+    fn test_that_binary_is_smaller_than_json() {
+        for message in test_messages() {
+            assert!(
+                WireFormat::Binary.encode(&message).len()
+                    < WireFormat::Json.encode(&message).len()
+            );
+        }
+    }
+
+    #[test]
+    fn test_that_malformed_bytes_are_not_decoded() {
+        let mut bytes = WireFormat::Binary.encode(&test_messages()[1]);
+        bytes.truncate(bytes.len() - 1);
+
+        assert_eq!(decode::<TestMessage>(&bytes), None);
+        assert_eq!(decode::<TestMessage>(b"{\"Number\":"), None);
+        assert_eq!(decode::<TestMessage>(&[]), None);
+        assert_eq!(
+            decode::<TestMessage>(&[0, 1, 255, 255, 255, 255]),
+            None
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use commons::arguments;
+use commons::types::NodeId;
+
+lazy_static! {
+    pub static ref ARGUMENTS: Arguments = Arguments::new();
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Read { versioned: bool },
+    Write { value: String },
+    Watch { interval: Duration },
+    Status,
+}
+
+#[derive(Debug)]
+pub struct Arguments {
+    pub socket_addrs: HashMap<NodeId, SocketAddr>,
+    pub client_port_offset: u16,
+    pub node_id: Option<NodeId>,
+    pub timeout: Duration,
+    pub command: Command,
+}
+
+impl Arguments {
+    fn new() -> Arguments {
+        let matches = get_matches();
+
+        Arguments {
+            socket_addrs: arguments::socket_addrs_from_matches(&matches),
+            client_port_offset: arguments::client_port_offset_from_matches(
+                &matches,
+            ),
+            node_id: node_id_from_matches(&matches),
+            timeout: timeout_from_matches(&matches),
+            command: command_from_matches(&matches),
+        }
+    }
+
+    // The node given with --node-id, or else the one with the lowest
+    // node id.
+    pub fn node_id(&self) -> NodeId {
+        match self.node_id {
+            Some(node_id) => node_id,
+            None => *self
+                .socket_addrs
+                .keys()
+                .min()
+                .expect("The hosts file has no nodes."),
+        }
+    }
+
+    // The client endpoint of a node is on the port of its address in
+    // the hosts file plus the offset. It only listens on 127.0.0.1, so
+    // the client must run on the same computer as the node.
+    pub fn client_socket_addr(&self, node_id: NodeId) -> SocketAddr {
+        let socket_addr = self
+            .socket_addrs
+            .get(&node_id)
+            .expect("The node id is not in the hosts file.");
+        let port = socket_addr
+            .port()
+            .checked_add(self.client_port_offset)
+            .expect("The client port offset gives a port above 65535.");
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)
+    }
+
+    pub fn sorted_node_ids(&self) -> Vec<NodeId> {
+        let mut node_ids: Vec<NodeId> =
+            self.socket_addrs.keys().cloned().collect();
+        node_ids.sort();
+        node_ids
+    }
+}
+
+fn get_matches() -> ArgMatches<'static> {
+    App::new("Distributed-SWMR-register: Client")
+        .setting(AppSettings::DisableVersion)
+        .setting(AppSettings::VersionlessSubcommands)
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .about("A command-line client that reads and writes the register through the client endpoints of a running cluster.")
+        .arg(arguments::hosts_file("The file with the node ids and addresses of the nodes, the same file the nodes were started with."))
+        .arg(client_port_offset_argument())
+        .arg(node_id_argument())
+        .arg(timeout_argument())
+        .subcommand(
+            SubCommand::with_name("read")
+                .about("Reads the value of the register and prints it.")
+                .arg(versioned_argument()),
+        )
+        .subcommand(
+            SubCommand::with_name("write")
+                .about("Writes a value to the register. Only the writer node accepts writes.")
+                .arg(
+                    Arg::with_name("value")
+                        .required(true)
+                        .help("The value to write."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("watch")
+                .about("Reads the register repeatedly and prints every new value, with its timestamp, until stopped with Ctrl-C.")
+                .arg(interval_argument()),
+        )
+        .subcommand(
+            SubCommand::with_name("status")
+                .about("Reads the register at every node in the hosts file and prints whether the node answered, how long it took and the timestamp and value it read."),
+        )
+        .get_matches()
+}
+
+// The same argument as the one of the application, but required,
+// since the nodes can't be reached if their endpoints are turned off.
+fn client_port_offset_argument() -> Arg<'static, 'static> {
+    Arg::with_name("client-port-offset")
+        .takes_value(true)
+        .long("client-port-offset")
+        .required(true)
+        .help("The client port offset the nodes were started with.")
+}
+
+fn node_id_argument() -> Arg<'static, 'static> {
+    Arg::with_name("node-id")
+        .short("n")
+        .long("node-id")
+        .takes_value(true)
+        .help("The node to connect to. If not given, the node with the lowest node id is used. Not used by status, which connects to all nodes.")
+}
+
+fn node_id_from_matches(matches: &ArgMatches<'static>) -> Option<NodeId> {
+    matches.value_of("node-id").map(|node_id| {
+        node_id.parse().expect("Could not parse the node id.")
+    })
+}
+
+fn timeout_argument() -> Arg<'static, 'static> {
+    Arg::with_name("timeout")
+        .long("timeout")
+        .takes_value(true)
+        .default_value("10000")
+        .help("The number of milliseconds to wait for a node to accept the connection or to respond, before giving up.")
+}
+
+fn timeout_from_matches(matches: &ArgMatches<'static>) -> Duration {
+    milliseconds_from_matches(matches, "timeout")
+}
+
+fn versioned_argument() -> Arg<'static, 'static> {
+    Arg::with_name("versioned")
+        .short("v")
+        .long("versioned")
+        .takes_value(false)
+        .help("Also prints the timestamp of the value, before the value.")
+}
+
+fn interval_argument() -> Arg<'static, 'static> {
+    Arg::with_name("interval")
+        .short("i")
+        .long("interval")
+        .takes_value(true)
+        .default_value("1000")
+        .help("The number of milliseconds between the reads.")
+}
+
+fn command_from_matches(matches: &ArgMatches<'static>) -> Command {
+    match matches.subcommand() {
+        ("read", Some(matches)) => Command::Read {
+            versioned: matches.is_present("versioned"),
+        },
+        ("write", Some(matches)) => Command::Write {
+            value: matches
+                .value_of("value")
+                .expect("value arg not existing.")
+                .to_string(),
+        },
+        ("watch", Some(matches)) => Command::Watch {
+            interval: milliseconds_from_matches(matches, "interval"),
+        },
+        ("status", Some(_)) => Command::Status,
+        _ => panic!("Unknown subcommand."),
+    }
+}
+
+fn milliseconds_from_matches(
+    matches: &ArgMatches<'static>,
+    name: &str,
+) -> Duration {
+    let milliseconds = matches
+        .value_of(name)
+        .expect(&format!("{} arg not existing.", name))
+        .parse()
+        .expect(&format!("Could not parse {}.", name));
+    Duration::from_millis(milliseconds)
+}
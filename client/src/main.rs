@@ -0,0 +1,294 @@
+#[macro_use]
+extern crate lazy_static;
+
+mod arguments;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use commons::client_protocol::{Request, Response};
+use commons::types::{NodeId, Timestamp};
+
+use crate::arguments::{Command, ARGUMENTS};
+
+fn main() {
+    match ARGUMENTS.command.clone() {
+        Command::Read { versioned } => read(versioned),
+        Command::Write { value } => write(value),
+        Command::Watch { interval } => watch(interval),
+        Command::Status => status(),
+    }
+}
+
+fn read(versioned: bool) {
+    let request = if versioned {
+        Request::GetVersioned
+    } else {
+        Request::Get
+    };
+
+    match request_to_node(ARGUMENTS.node_id(), &request) {
+        Ok(Response::Value(value)) => println!("{}", value),
+        Ok(Response::Versioned(timestamp, value)) => {
+            println!("{} {}", timestamp, value)
+        }
+        Ok(response) => exit_with_error(unexpected(&response)),
+        Err(error) => exit_with_error(error),
+    }
+}
+
+fn write(value: String) {
+    match request_to_node(ARGUMENTS.node_id(), &Request::Put(value)) {
+        Ok(Response::Ok) => {}
+        Ok(response) => exit_with_error(unexpected(&response)),
+        Err(error) => exit_with_error(error),
+    }
+}
+
+// Keeps the connection open between the reads, and reconnects after
+// errors, so that a node that is restarted can be watched across the
+// restart.
+fn watch(interval: Duration) {
+    let node_id = ARGUMENTS.node_id();
+    let mut connection = None;
+    let mut latest_timestamp: Option<Timestamp> = None;
+
+    loop {
+        if connection.is_none() {
+            match Connection::open(
+                ARGUMENTS.client_socket_addr(node_id),
+                ARGUMENTS.timeout,
+            ) {
+                Ok(new_connection) => connection = Some(new_connection),
+                Err(error) => eprintln!("{}", error),
+            }
+        }
+
+        if let Some(open_connection) = connection.as_mut() {
+            match open_connection.request(&Request::GetVersioned) {
+                Ok(Response::Versioned(timestamp, value)) => {
+                    if latest_timestamp != Some(timestamp) {
+                        println!("{} {}", timestamp, value);
+                        latest_timestamp = Some(timestamp);
+                    }
+                }
+                Ok(response) => eprintln!("{}", unexpected(&response)),
+                Err(error) => {
+                    eprintln!("{}", error);
+                    connection = None;
+                }
+            }
+        }
+
+        thread::sleep(interval);
+    }
+}
+
+// Exits with an error if any node didn't answer, so that it can be
+// used in scripts to wait for a cluster to be up.
+fn status() {
+    println!(
+        "{:<6} {:<22} {:<10} {:<10} Value",
+        "Node", "Client endpoint", "Latency", "Timestamp"
+    );
+
+    let mut all_answered = true;
+    for node_id in ARGUMENTS.sorted_node_ids() {
+        let socket_addr = ARGUMENTS.client_socket_addr(node_id);
+        let start = Instant::now();
+        let response = request_to_node(node_id, &Request::GetVersioned);
+        let latency = format!("{} ms", start.elapsed().as_millis());
+
+        match response {
+            Ok(Response::Versioned(timestamp, value)) => println!(
+                "{:<6} {:<22} {:<10} {:<10} {}",
+                node_id, socket_addr, latency, timestamp, value
+            ),
+            Ok(response) => {
+                all_answered = false;
+                println!(
+                    "{:<6} {:<22} {}",
+                    node_id,
+                    socket_addr,
+                    unexpected(&response)
+                );
+            }
+            Err(error) => {
+                all_answered = false;
+                println!("{:<6} {:<22} {}", node_id, socket_addr, error);
+            }
+        }
+    }
+
+    if !all_answered {
+        process::exit(1);
+    }
+}
+
+fn request_to_node(
+    node_id: NodeId,
+    request: &Request,
+) -> Result<Response, String> {
+    Connection::open(
+        ARGUMENTS.client_socket_addr(node_id),
+        ARGUMENTS.timeout,
+    )?
+    .request(request)
+}
+
+fn unexpected(response: &Response) -> String {
+    match response {
+        Response::Error(reason) => {
+            format!("The node answered: {}", reason)
+        }
+        response => {
+            format!("Unexpected response: {}", response.to_line())
+        }
+    }
+}
+
+fn exit_with_error(error: String) -> ! {
+    eprintln!("{}", error);
+    process::exit(1);
+}
+
+struct Connection {
+    socket_addr: SocketAddr,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    fn open(
+        socket_addr: SocketAddr,
+        timeout: Duration,
+    ) -> Result<Connection, String> {
+        let stream = TcpStream::connect_timeout(&socket_addr, timeout)
+            .map_err(|error| {
+                format!("Could not connect to {}: {}", socket_addr, error)
+            })?;
+        stream
+            .set_read_timeout(Some(timeout))
+            .expect("Could not set the read timeout.");
+        stream
+            .set_write_timeout(Some(timeout))
+            .expect("Could not set the write timeout.");
+        let writer =
+            stream.try_clone().expect("Could not clone the connection.");
+
+        Ok(Connection {
+            socket_addr: socket_addr,
+            reader: BufReader::new(stream),
+            writer: writer,
+        })
+    }
+
+    // A request that can't be encoded, like a value with a line break,
+    // is an error without being sent.
+    fn request(&mut self, request: &Request) -> Result<Response, String> {
+        let line = request.to_line()?;
+        writeln!(self.writer, "{}", line)
+            .map_err(|error| self.error(error))?;
+
+        let mut line = String::new();
+        let length = self
+            .reader
+            .read_line(&mut line)
+            .map_err(|error| self.error(error))?;
+        if length == 0 {
+            return Err(format!(
+                "{} closed the connection.",
+                self.socket_addr
+            ));
+        }
+
+        Response::from_line(line.trim_end_matches(&['\r', '\n'][..]))
+    }
+
+    fn error(&self, error: std::io::Error) -> String {
+        format!("Lost the connection to {}: {}", self.socket_addr, error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    // A node that answers each request line with the given response
+    // line, and returns the request lines it got when the connection
+    // is closed.
+    fn spawn_node(
+        response_line: &'static str,
+    ) -> (SocketAddr, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let socket_addr = listener.local_addr().unwrap();
+        let node_thread = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut request_lines = Vec::new();
+            for line in BufReader::new(stream).lines() {
+                request_lines.push(line.unwrap());
+                writeln!(writer, "{}", response_line).unwrap();
+            }
+            request_lines
+        });
+        (socket_addr, node_thread)
+    }
+
+    #[test]
+    fn test_that_a_request_is_sent_and_its_response_parsed() {
+        let (socket_addr, node_thread) = spawn_node("VERSIONED 3 a b");
+        let mut connection =
+            Connection::open(socket_addr, TIMEOUT).unwrap();
+
+        assert_eq!(
+            connection.request(&Request::GetVersioned),
+            Ok(Response::Versioned(3, "a b".to_string()))
+        );
+        assert_eq!(
+            connection.request(&Request::Put("a b".to_string())),
+            Ok(Response::Versioned(3, "a b".to_string()))
+        );
+
+        drop(connection);
+        assert_eq!(
+            node_thread.join().unwrap(),
+            vec!["GET_VERSIONED".to_string(), "PUT a b".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_that_a_value_with_a_line_break_is_not_sent() {
+        let (socket_addr, node_thread) = spawn_node("OK");
+        let mut connection =
+            Connection::open(socket_addr, TIMEOUT).unwrap();
+
+        let request = Request::Put("first\nPUT second".to_string());
+        assert!(connection.request(&request).is_err());
+        assert_eq!(connection.request(&Request::Get), Ok(Response::Ok));
+
+        drop(connection);
+        assert_eq!(node_thread.join().unwrap(), vec!["GET".to_string()]);
+    }
+
+    #[test]
+    fn test_that_a_closed_connection_is_an_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let socket_addr = listener.local_addr().unwrap();
+        let node_thread = thread::spawn(move || {
+            listener.accept().unwrap();
+        });
+        let mut connection =
+            Connection::open(socket_addr, TIMEOUT).unwrap();
+        node_thread.join().unwrap();
+
+        assert!(connection.request(&Request::Get).is_err());
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::run_result::RunResult;
+use crate::types::{Int, NodeId, Timestamp};
+
+/*
+The line-based protocol of the admin endpoint of a node. Each request
+is one line, and gets one line in response:
+
+STATE                   STATE <json>
+SET_READING on|off      OK
+SET_WRITING on|off      OK
+
+A request that fails gets ERROR <reason> instead. The json is a
+NodeState, with what the node is doing right now.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdminRequest {
+    State,
+    SetReading(bool),
+    SetWriting(bool),
+}
+
+#[derive(Debug, Clone)]
+pub enum AdminResponse {
+    State(Box<NodeState>),
+    Ok,
+    Error(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeState {
+    pub node_id: NodeId,
+    pub incarnation: u64,
+    pub uptime_millis: u64,
+    pub timestamp: Timestamp,
+    pub value: String,
+    pub quorums: QuorumStates,
+    pub peers: BTreeMap<NodeId, PeerState>,
+    pub client_operations: ClientOperationsState,
+    pub configuration: Configuration,
+    pub run_result: RunResult,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct QuorumStates {
+    pub write: QuorumState,
+    pub read1: QuorumState,
+    pub read2: QuorumState,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct QuorumState {
+    pub accessing: bool,
+    // Sorted.
+    pub acking_nodes: Vec<NodeId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PeerState {
+    pub suspected: bool,
+    pub round_trip_time_millis: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ClientOperationsState {
+    pub reading: bool,
+    pub writing: bool,
+    pub in_progress: Int,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Configuration {
+    // Sorted.
+    pub node_ids: Vec<NodeId>,
+    pub cluster_id: String,
+    pub is_writer: bool,
+    pub wire_format: String,
+    pub authenticated: bool,
+    pub encrypted: bool,
+    pub max_value_size: usize,
+    pub run_length_secs: u64,
+    pub adaptive_retransmission: bool,
+    pub initial_retransmission_timeout_millis: u64,
+    pub fastest_quorum_first: bool,
+    pub heartbeat_interval_millis: u64,
+    pub suspicion_timeout_millis: u64,
+}
+
+impl AdminRequest {
+    pub fn from_line(line: &str) -> Result<AdminRequest, String> {
+        let mut words = line.split(' ');
+        match (words.next(), words.next(), words.next()) {
+            (Some("STATE"), None, None) => Ok(AdminRequest::State),
+            (Some("SET_READING"), Some(switch), None) => {
+                Ok(AdminRequest::SetReading(on_from_word(switch)?))
+            }
+            (Some("SET_WRITING"), Some(switch), None) => {
+                Ok(AdminRequest::SetWriting(on_from_word(switch)?))
+            }
+            _ => Err(format!("Unknown request \"{}\".", line)),
+        }
+    }
+
+    pub fn to_line(&self) -> String {
+        match self {
+            AdminRequest::State => "STATE".to_string(),
+            AdminRequest::SetReading(on) => {
+                format!("SET_READING {}", word_from_on(*on))
+            }
+            AdminRequest::SetWriting(on) => {
+                format!("SET_WRITING {}", word_from_on(*on))
+            }
+        }
+    }
+}
+
+impl AdminResponse {
+    pub fn from_line(line: &str) -> Result<AdminResponse, String> {
+        if line == "OK" {
+            return Ok(AdminResponse::Ok);
+        }
+        if let Some(json) = line.strip_prefix("STATE ") {
+            let node_state = serde_json::from_str(json).map_err(|error| {
+                format!("Could not parse the node state: {}", error)
+            })?;
+            return Ok(AdminResponse::State(Box::new(node_state)));
+        }
+        if let Some(reason) = line.strip_prefix("ERROR") {
+            return Ok(AdminResponse::Error(reason.trim_start().to_string()));
+        }
+        Err(format!("Unknown response \"{}\".", line))
+    }
+
+    pub fn to_line(&self) -> String {
+        match self {
+            AdminResponse::State(node_state) => format!(
+                "STATE {}",
+                serde_json::to_string(node_state)
+                    .expect("Could not serialize the node state.")
+            ),
+            AdminResponse::Ok => "OK".to_string(),
+            AdminResponse::Error(reason) => format!("ERROR {}", reason),
+        }
+    }
+}
+
+fn on_from_word(word: &str) -> Result<bool, String> {
+    match word {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("Expected on or off, not \"{}\".", word)),
+    }
+}
+
+fn word_from_on(on: bool) -> &'static str {
+    if on {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node_state() -> NodeState {
+        let quorum_state = QuorumState {
+            accessing: true,
+            acking_nodes: vec![1, 3],
+        };
+        let mut peers = BTreeMap::new();
+        peers.insert(
+            2,
+            PeerState {
+                suspected: true,
+                round_trip_time_millis: Some(1.5),
+            },
+        );
+
+        NodeState {
+            node_id: 1,
+            incarnation: 100,
+            uptime_millis: 2000,
+            timestamp: 7,
+            value: "Val: 7".to_string(),
+            quorums: QuorumStates {
+                write: quorum_state.clone(),
+                read1: quorum_state.clone(),
+                read2: quorum_state,
+            },
+            peers: peers,
+            client_operations: ClientOperationsState {
+                reading: true,
+                writing: false,
+                in_progress: 1,
+            },
+            configuration: Configuration {
+                node_ids: vec![1, 2, 3],
+                cluster_id: "default".to_string(),
+                is_writer: false,
+                wire_format: "Json".to_string(),
+                authenticated: false,
+                encrypted: false,
+                max_value_size: 65536,
+                run_length_secs: 0,
+                adaptive_retransmission: true,
+                initial_retransmission_timeout_millis: 100,
+                fastest_quorum_first: false,
+                heartbeat_interval_millis: 100,
+                suspicion_timeout_millis: 1000,
+            },
+            run_result: RunResult::new(),
+        }
+    }
+
+    #[test]
+    fn test_that_requests_are_parsed_from_their_lines() {
+        let requests = vec![
+            AdminRequest::State,
+            AdminRequest::SetReading(true),
+            AdminRequest::SetReading(false),
+            AdminRequest::SetWriting(true),
+        ];
+
+        for request in requests {
+            assert_eq!(
+                AdminRequest::from_line(&request.to_line()),
+                Ok(request)
+            );
+        }
+        assert!(AdminRequest::from_line("SET_READING yes").is_err());
+        assert!(AdminRequest::from_line("STATE now").is_err());
+    }
+
+    #[test]
+    fn test_that_the_state_is_parsed_from_its_line() {
+        let line = AdminResponse::State(Box::new(node_state())).to_line();
+
+        match AdminResponse::from_line(&line) {
+            Ok(AdminResponse::State(parsed)) => {
+                assert_eq!(parsed.quorums, node_state().quorums);
+                assert_eq!(parsed.peers, node_state().peers);
+                assert_eq!(parsed.configuration, node_state().configuration);
+                assert_eq!(parsed.value, "Val: 7");
+            }
+            response => panic!("Unexpected response {:?}", response),
+        }
+    }
+}
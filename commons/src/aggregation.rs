@@ -0,0 +1,671 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::arguments;
+use crate::histogram::LatencyHistogram;
+use crate::run_result::RunResult;
+use crate::types::{Int, NodeId};
+
+/*
+The result files of all the nodes of a run, combined into numbers for
+the whole cluster: whether each result file is sound, the throughput,
+how many messages an operation takes and the latencies. The latency
+histograms of the nodes are merged, so the percentiles of the cluster
+are of all its operations, not averages of the percentiles of the
+nodes.
+
+The messages of a write are the write messages the writer sent,
+including the retransmissions, and the acks it received for them.
+Likewise for the two phases of a read.
+*/
+#[derive(Serialize, Debug, Clone)]
+pub struct Aggregate {
+    pub number_of_nodes: Int,
+    // The nodes without a result file.
+    pub missing_node_ids: Vec<NodeId>,
+    pub unsound_node_ids: Vec<NodeId>,
+    pub cluster: Summary,
+    pub nodes: Vec<Summary>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Summary {
+    // None for the whole cluster.
+    pub node_id: Option<NodeId>,
+    pub sound: bool,
+    // The longest run length, in seconds.
+    pub run_length: f64,
+    pub write_ops: Int,
+    pub read_ops: Int,
+    pub operations_per_second: Option<f64>,
+    pub messages_sent: Int,
+    pub messages_per_write: Option<f64>,
+    pub messages_per_read: Option<f64>,
+    pub retransmissions: Int,
+    pub latencies: Vec<LatencySummary>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LatencySummary {
+    pub name: String,
+    pub count: u64,
+    pub mean_millis: Option<f64>,
+    pub p50_millis: Option<f64>,
+    pub p90_millis: Option<f64>,
+    pub p99_millis: Option<f64>,
+    pub max_millis: Option<f64>,
+}
+
+// The result files in the directory, by node id. Other files are
+// ignored, and so are result files that can't be read or parsed, for
+// example of a node that was killed while writing it, so that they
+// count as missing.
+pub fn run_results_from_directory(
+    directory: &Path,
+) -> BTreeMap<NodeId, RunResult> {
+    let mut run_results = BTreeMap::new();
+    let entries = fs::read_dir(directory).expect(&format!(
+        "Could not read the directory {}",
+        directory.display()
+    ));
+
+    for entry in entries {
+        let path =
+            entry.expect("Could not read a directory entry.").path();
+        let node_id = match path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .and_then(node_id_from_file_name)
+        {
+            Some(node_id) => node_id,
+            None => continue,
+        };
+
+        let json = match fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(error) => {
+                eprintln!("Could not read {}: {}", path.display(), error);
+                continue;
+            }
+        };
+        match serde_json::from_str(&json) {
+            Ok(run_result) => {
+                run_results.insert(node_id, run_result);
+            }
+            Err(error) => {
+                eprintln!("Could not parse {}: {}", path.display(), error)
+            }
+        }
+    }
+
+    run_results
+}
+
+fn node_id_from_file_name(file_name: &str) -> Option<NodeId> {
+    let node_id = file_name
+        .strip_prefix("node")?
+        .strip_suffix(".eval")?
+        .parse()
+        .ok()?;
+    if arguments::run_result_file_name_from_node_id(node_id) == file_name {
+        Some(node_id)
+    } else {
+        None
+    }
+}
+
+// The node ids of a run are 1 to the number of nodes, like in the
+// hosts files of the starters, which is what RunResult::is_sound()
+// expects.
+pub fn aggregate(
+    run_results: &BTreeMap<NodeId, RunResult>,
+    number_of_nodes: Int,
+) -> Aggregate {
+    let missing_node_ids = (1..number_of_nodes + 1)
+        .filter(|node_id| !run_results.contains_key(node_id))
+        .collect();
+    let unsound_node_ids = run_results
+        .iter()
+        .filter(|(_, run_result)| !run_result.is_sound(number_of_nodes))
+        .map(|(&node_id, _)| node_id)
+        .collect();
+
+    let nodes = run_results
+        .iter()
+        .map(|(&node_id, run_result)| {
+            summary(Some(node_id), &[run_result], number_of_nodes)
+        })
+        .collect();
+    let all_run_results: Vec<&RunResult> = run_results.values().collect();
+    let mut cluster = summary(None, &all_run_results, number_of_nodes);
+
+    let mut aggregate = Aggregate {
+        number_of_nodes: number_of_nodes,
+        missing_node_ids: missing_node_ids,
+        unsound_node_ids: unsound_node_ids,
+        cluster: cluster.clone(),
+        nodes: nodes,
+    };
+    cluster.sound = aggregate.is_sound();
+    aggregate.cluster = cluster;
+    aggregate
+}
+
+fn summary(
+    node_id: Option<NodeId>,
+    run_results: &[&RunResult],
+    number_of_nodes: Int,
+) -> Summary {
+    let sum = |count: &dyn Fn(&RunResult) -> Int| -> Int {
+        run_results.iter().map(|run_result| count(run_result)).sum()
+    };
+
+    let run_length = run_results
+        .iter()
+        .map(|run_result| run_result.metadata.run_length)
+        .fold(0.0, f64::max);
+    let write_ops = sum(&|run_result| run_result.write_ops);
+    let read_ops = sum(&|run_result| run_result.read_ops);
+    let write_messages = sum(&|run_result| {
+        run_result.write_message.sent
+            + run_result.write_ack_message.received
+    });
+    let read_messages = sum(&|run_result| {
+        run_result.read1_message.sent
+            + run_result.read1_ack_message.received
+            + run_result.read2_message.sent
+            + run_result.read2_ack_message.received
+    });
+
+    Summary {
+        node_id: node_id,
+        sound: run_results
+            .iter()
+            .all(|run_result| run_result.is_sound(number_of_nodes)),
+        run_length: run_length,
+        write_ops: write_ops,
+        read_ops: read_ops,
+        operations_per_second: if run_length > 0.0 {
+            Some((write_ops + read_ops) as f64 / run_length)
+        } else {
+            None
+        },
+        messages_sent: sum(&|run_result| {
+            run_result.write_message.sent
+                + run_result.write_ack_message.sent
+                + run_result.read1_message.sent
+                + run_result.read1_ack_message.sent
+                + run_result.read2_message.sent
+                + run_result.read2_ack_message.sent
+                + run_result.heartbeat_message.sent
+                + run_result.heartbeat_ack_message.sent
+        }),
+        messages_per_write: ratio(write_messages, write_ops),
+        messages_per_read: ratio(read_messages, read_ops),
+        retransmissions: sum(&|run_result| {
+            let quorum_accesses = &run_result.quorum_accesses;
+            quorum_accesses.write.retransmissions
+                + quorum_accesses.read1.retransmissions
+                + quorum_accesses.read2.retransmissions
+        }),
+        latencies: latency_summaries(run_results),
+    }
+}
+
+fn ratio(numerator: Int, denominator: Int) -> Option<f64> {
+    if denominator == 0 {
+        None
+    } else {
+        Some(numerator as f64 / denominator as f64)
+    }
+}
+
+// The name of a latency histogram, and the histogram of a run result.
+type NamedHistogram = (&'static str, fn(&RunResult) -> &LatencyHistogram);
+
+fn latency_summaries(run_results: &[&RunResult]) -> Vec<LatencySummary> {
+    let histograms: Vec<NamedHistogram> = vec![
+        ("read", |run_result| &run_result.latencies.read),
+        ("write", |run_result| &run_result.latencies.write),
+        ("read_phase1", |run_result| {
+            &run_result.latencies.read_phase1
+        }),
+        ("read_phase2", |run_result| {
+            &run_result.latencies.read_phase2
+        }),
+        ("write_time_to_majority", |run_result| {
+            &run_result.quorum_accesses.write.time_to_majority
+        }),
+        ("read1_time_to_majority", |run_result| {
+            &run_result.quorum_accesses.read1.time_to_majority
+        }),
+        ("read2_time_to_majority", |run_result| {
+            &run_result.quorum_accesses.read2.time_to_majority
+        }),
+    ];
+
+    histograms
+        .into_iter()
+        .map(|(name, histogram_of)| {
+            let mut histogram = LatencyHistogram::new();
+            for run_result in run_results {
+                histogram.merge(histogram_of(run_result));
+            }
+            latency_summary(name, &histogram)
+        })
+        .collect()
+}
+
+fn latency_summary(
+    name: &str,
+    histogram: &LatencyHistogram,
+) -> LatencySummary {
+    let millis = |percentile| {
+        histogram
+            .percentile(percentile)
+            .map(|latency| latency.as_micros() as f64 / 1000.0)
+    };
+    let mean_millis = if histogram.count() == 0 {
+        None
+    } else {
+        Some(
+            histogram.sum().as_secs_f64() * 1000.0
+                / histogram.count() as f64,
+        )
+    };
+
+    LatencySummary {
+        name: name.to_string(),
+        count: histogram.count(),
+        mean_millis: mean_millis,
+        p50_millis: millis(50.0),
+        p90_millis: millis(90.0),
+        p99_millis: millis(99.0),
+        max_millis: millis(100.0),
+    }
+}
+
+impl Aggregate {
+    pub fn is_sound(&self) -> bool {
+        self.missing_node_ids.is_empty()
+            && self.unsound_node_ids.is_empty()
+    }
+
+    // A table of the nodes and the cluster, and a table of the
+    // latencies of the cluster, for the terminal.
+    pub fn to_table(&self) -> String {
+        let mut table = String::new();
+
+        if self.is_sound() {
+            writeln!(
+                table,
+                "All {} result files are sound.",
+                self.number_of_nodes
+            )
+            .unwrap();
+        }
+        if !self.missing_node_ids.is_empty() {
+            writeln!(
+                table,
+                "Missing result files of nodes {}.",
+                node_ids_string(&self.missing_node_ids)
+            )
+            .unwrap();
+        }
+        if !self.unsound_node_ids.is_empty() {
+            writeln!(
+                table,
+                "Unsound result files of nodes {}.",
+                node_ids_string(&self.unsound_node_ids)
+            )
+            .unwrap();
+        }
+        writeln!(table).unwrap();
+
+        writeln!(
+            table,
+            "{:<8}{:>8}{:>10}{:>10}{:>12}{:>12}{:>12}{:>12}{:>16}",
+            "Node",
+            "Sound",
+            "Writes",
+            "Reads",
+            "Ops/s",
+            "Messages",
+            "Msgs/write",
+            "Msgs/read",
+            "Retransmissions"
+        )
+        .unwrap();
+        for summary in self.nodes.iter().chain(Some(&self.cluster)) {
+            let node = match summary.node_id {
+                Some(node_id) => node_id.to_string(),
+                None => "All".to_string(),
+            };
+            writeln!(
+                table,
+                "{:<8}{:>8}{:>10}{:>10}{:>12}{:>12}{:>12}{:>12}{:>16}",
+                node,
+                if summary.sound { "yes" } else { "no" },
+                summary.write_ops,
+                summary.read_ops,
+                table_cell(summary.operations_per_second, 1),
+                summary.messages_sent,
+                table_cell(summary.messages_per_write, 2),
+                table_cell(summary.messages_per_read, 2),
+                summary.retransmissions
+            )
+            .unwrap();
+        }
+        writeln!(table).unwrap();
+
+        writeln!(
+            table,
+            "{:<24}{:>10}{:>10}{:>10}{:>10}{:>10}{:>10}",
+            "Latency (ms)", "Count", "Mean", "p50", "p90", "p99", "Max"
+        )
+        .unwrap();
+        for latency in &self.cluster.latencies {
+            writeln!(
+                table,
+                "{:<24}{:>10}{:>10}{:>10}{:>10}{:>10}{:>10}",
+                latency.name,
+                latency.count,
+                table_cell(latency.mean_millis, 3),
+                table_cell(latency.p50_millis, 3),
+                table_cell(latency.p90_millis, 3),
+                table_cell(latency.p99_millis, 3),
+                table_cell(latency.max_millis, 3)
+            )
+            .unwrap();
+        }
+
+        table
+    }
+
+    // One row per node and a last row for the whole cluster, with
+    // the node "all".
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+
+        let mut header = vec![
+            "node",
+            "sound",
+            "run_length",
+            "write_ops",
+            "read_ops",
+            "operations_per_second",
+            "messages_sent",
+            "messages_per_write",
+            "messages_per_read",
+            "retransmissions",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<String>>();
+        for latency in &self.cluster.latencies {
+            for column in &["count", "mean", "p50", "p90", "p99", "max"] {
+                if *column == "count" {
+                    header.push(format!("{}_count", latency.name));
+                } else {
+                    header.push(format!(
+                        "{}_{}_millis",
+                        latency.name, column
+                    ));
+                }
+            }
+        }
+        writeln!(csv, "{}", header.join(",")).unwrap();
+
+        for summary in self.nodes.iter().chain(Some(&self.cluster)) {
+            let mut row = vec![
+                match summary.node_id {
+                    Some(node_id) => node_id.to_string(),
+                    None => "all".to_string(),
+                },
+                summary.sound.to_string(),
+                summary.run_length.to_string(),
+                summary.write_ops.to_string(),
+                summary.read_ops.to_string(),
+                csv_cell(summary.operations_per_second),
+                summary.messages_sent.to_string(),
+                csv_cell(summary.messages_per_write),
+                csv_cell(summary.messages_per_read),
+                summary.retransmissions.to_string(),
+            ];
+            for latency in &summary.latencies {
+                row.push(latency.count.to_string());
+                row.push(csv_cell(latency.mean_millis));
+                row.push(csv_cell(latency.p50_millis));
+                row.push(csv_cell(latency.p90_millis));
+                row.push(csv_cell(latency.p99_millis));
+                row.push(csv_cell(latency.max_millis));
+            }
+            writeln!(csv, "{}", row.join(",")).unwrap();
+        }
+
+        csv
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self)
+            .expect("Could not serialize the aggregate.")
+    }
+}
+
+fn node_ids_string(node_ids: &[NodeId]) -> String {
+    node_ids
+        .iter()
+        .map(|node_id| node_id.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn table_cell(value: Option<f64>, decimals: usize) -> String {
+    match value {
+        Some(value) => format!("{:.*}", decimals, value),
+        None => "-".to_string(),
+    }
+}
+
+fn csv_cell(value: Option<f64>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    // A run with three nodes, where node 3 wrote and nodes 1 and 2
+    // read, and all nodes acked all messages.
+    fn run_results() -> BTreeMap<NodeId, RunResult> {
+        let mut run_results = BTreeMap::new();
+        for node_id in 1..4 {
+            let mut run_result = RunResult::new();
+            run_result.metadata.node_id = node_id;
+            run_result.metadata.run_length = 10.0;
+            for &acking_node_id in &[1, 2, 3] {
+                run_result
+                    .write_ack_message
+                    .nodes_received_from
+                    .insert(acking_node_id);
+                run_result
+                    .read1_ack_message
+                    .nodes_received_from
+                    .insert(acking_node_id);
+                run_result
+                    .read2_ack_message
+                    .nodes_received_from
+                    .insert(acking_node_id);
+            }
+            run_results.insert(node_id, run_result);
+        }
+
+        let writer = run_results.get_mut(&3).unwrap();
+        writer.metadata.is_writer = true;
+        writer.read1_ack_message.nodes_received_from.clear();
+        writer.read2_ack_message.nodes_received_from.clear();
+        writer.write_ops = 100;
+        writer.write_message.sent = 300;
+        writer.write_ack_message.received = 300;
+        writer.latencies.write.record(Duration::from_millis(2));
+
+        for node_id in 1..3 {
+            let reader = run_results.get_mut(&node_id).unwrap();
+            reader.metadata.is_reader = true;
+            reader.write_ack_message.nodes_received_from.clear();
+            reader.read_ops = 50;
+            reader.read1_message.sent = 150;
+            reader.read1_ack_message.received = 150;
+            reader.read2_message.sent = 150;
+            reader.read2_ack_message.received = 150;
+            reader
+                .latencies
+                .read
+                .record(Duration::from_millis(node_id as u64));
+        }
+
+        run_results
+    }
+
+    #[test]
+    fn test_that_the_cluster_has_the_operations_of_all_nodes() {
+        let aggregate = aggregate(&run_results(), 3);
+
+        assert!(aggregate.is_sound());
+        let cluster = &aggregate.cluster;
+        assert_eq!(cluster.write_ops, 100);
+        assert_eq!(cluster.read_ops, 100);
+        assert_eq!(cluster.operations_per_second, Some(20.0));
+        assert_eq!(cluster.messages_per_write, Some(6.0));
+        assert_eq!(cluster.messages_per_read, Some(12.0));
+
+        let read_latency = &cluster.latencies[0];
+        assert_eq!(read_latency.name, "read");
+        assert_eq!(read_latency.count, 2);
+        assert_eq!(read_latency.max_millis, Some(2.0));
+        assert_eq!(read_latency.mean_millis, Some(1.5));
+        assert_eq!(aggregate.nodes[0].latencies[0].count, 1);
+    }
+
+    #[test]
+    fn test_that_unsound_and_missing_result_files_are_found() {
+        let mut run_results = run_results();
+        run_results
+            .get_mut(&1)
+            .unwrap()
+            .read1_ack_message
+            .nodes_received_from
+            .remove(&2);
+
+        let aggregate = aggregate(&run_results, 4);
+
+        assert!(!aggregate.is_sound());
+        assert!(!aggregate.cluster.sound);
+        assert_eq!(aggregate.missing_node_ids, vec![4]);
+        // With four nodes, no node has acks from all of them.
+        assert_eq!(aggregate.unsound_node_ids, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_that_the_csv_has_a_row_per_node_and_one_for_the_cluster() {
+        let csv = aggregate(&run_results(), 3).to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(lines.len(), 1 + 3 + 1);
+        assert!(lines[0].starts_with("node,sound,run_length,"));
+        assert!(lines[0].contains(",read_p99_millis,"));
+        assert!(lines[4].starts_with("all,true,10,100,100,20,"));
+        for line in &lines {
+            assert_eq!(
+                line.split(',').count(),
+                lines[0].split(',').count()
+            );
+        }
+    }
+
+    #[test]
+    fn test_that_only_result_files_are_read_from_a_directory() {
+        assert_eq!(node_id_from_file_name("node000012.eval"), Some(12));
+        assert_eq!(node_id_from_file_name("node000012.history"), None);
+        assert_eq!(node_id_from_file_name("node12.eval"), None);
+    }
+
+    #[test]
+    fn test_that_a_corrupt_result_file_is_missing() {
+        let directory = std::env::temp_dir()
+            .join(format!("aggregation_test_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let run_result = run_results().remove(&1).unwrap();
+        fs::write(
+            directory.join("node000001.eval"),
+            serde_json::to_string(&run_result).unwrap(),
+        )
+        .unwrap();
+        fs::write(directory.join("node000002.eval"), "{\"write_ops\":")
+            .unwrap();
+
+        let run_results = run_results_from_directory(&directory);
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(run_results.keys().collect::<Vec<_>>(), vec![&1]);
+        assert_eq!(aggregate(&run_results, 2).missing_node_ids, vec![2]);
+    }
+
+    #[test]
+    fn test_that_a_writer_that_served_client_reads_is_sound() {
+        let mut run_results = run_results();
+        let writer = run_results.get_mut(&3).unwrap();
+        writer.metadata.is_reader = true;
+        writer.read_ops = 1;
+        // The read completed before node 1 had acked.
+        writer.read1_ack_message.nodes_received_from =
+            vec![2, 3].into_iter().collect();
+        writer.read2_ack_message.nodes_received_from =
+            vec![2, 3].into_iter().collect();
+        assert!(!aggregate(&run_results, 3).is_sound());
+
+        run_results
+            .get_mut(&3)
+            .unwrap()
+            .metadata
+            .writer_served_client_reads = true;
+        assert!(aggregate(&run_results, 3).is_sound());
+    }
+
+    #[test]
+    fn test_that_majority_acks_are_sound_with_fastest_quorum_first() {
+        let mut run_results = run_results();
+        for run_result in run_results.values_mut() {
+            for nodes_received_from in [
+                &mut run_result.write_ack_message.nodes_received_from,
+                &mut run_result.read1_ack_message.nodes_received_from,
+                &mut run_result.read2_ack_message.nodes_received_from,
+            ] {
+                nodes_received_from.remove(&1);
+            }
+        }
+        assert!(!aggregate(&run_results, 3).is_sound());
+
+        for run_result in run_results.values_mut() {
+            run_result.metadata.fastest_quorum_first = true;
+        }
+        assert!(aggregate(&run_results, 3).is_sound());
+
+        run_results
+            .get_mut(&3)
+            .unwrap()
+            .write_ack_message
+            .nodes_received_from
+            .remove(&2);
+        assert!(!aggregate(&run_results, 3).is_sound());
+    }
+}
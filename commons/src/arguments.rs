@@ -1,6 +1,6 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::net::ToSocketAddrs;
+use std::net::{SocketAddr, ToSocketAddrs};
 
 use clap::{Arg, ArgMatches};
 use colored::Color;
@@ -39,11 +39,7 @@ pub fn node_infos_from_string(string: String) -> HashSet<NodeInfo> {
         let components: Vec<&str> = line.split(",").collect();
         let node_id =
             components[0].parse().expect("Could not parse node id.");
-        let socket_addr = components[1]
-            .to_socket_addrs()
-            .expect("Could not transform to socket addrs.")
-            .next()
-            .expect("No socket addrs provided.");
+        let socket_addr = socket_addr_from_address(components[1]);
         let key_path = components[2].to_string();
         let username = components[3].to_string();
         let script_path = components[4].to_string();
@@ -62,6 +58,52 @@ pub fn node_infos_from_string(string: String) -> HashSet<NodeInfo> {
     node_infos
 }
 
+// Only reads the node ids and the addresses, so it works for the
+// hosts files of both the application and remote_starter.
+pub fn socket_addrs_from_matches(
+    matches: &ArgMatches<'static>,
+) -> HashMap<NodeId, SocketAddr> {
+    let hosts_file_path = hosts_file_from_matches(matches);
+    let string = fs::read_to_string(hosts_file_path)
+        .expect("Unable to read the hosts file.");
+    socket_addrs_from_string(string)
+}
+
+pub fn socket_addrs_from_string(
+    string: String,
+) -> HashMap<NodeId, SocketAddr> {
+    let mut socket_addrs = HashMap::new();
+
+    for line in string.lines() {
+        let components: Vec<&str> = line.split(",").collect();
+        let node_id =
+            components[0].parse().expect("Could not parse node id.");
+        let socket_addr = socket_addr_from_address(components[1]);
+
+        socket_addrs.insert(node_id, socket_addr);
+    }
+
+    socket_addrs
+}
+
+fn socket_addr_from_address(address: &str) -> SocketAddr {
+    address_without_scheme(address)
+        .to_socket_addrs()
+        .expect("Could not transform to socket addrs.")
+        .next()
+        .expect("No socket addrs provided.")
+}
+
+// The application accepts addresses like tcp://ip:port, where the
+// scheme decides the transport. The remote computer is the same
+// regardless of the transport.
+fn address_without_scheme(address: &str) -> &str {
+    match address.find("://") {
+        Some(index) => &address[index + 3..],
+        None => address,
+    }
+}
+
 pub fn should_write() -> Arg<'static, 'static> {
     Arg::with_name("write")
         .required(false)
@@ -150,6 +192,115 @@ pub fn print_client_operations_string_from_matches(
     }
 }
 
+pub fn record_latencies() -> Arg<'static, 'static> {
+    Arg::with_name("record-latencies")
+        .takes_value(false)
+        .long("record-latencies")
+        .help("Record the latencies of the reads and writes, and of the two phases of the reads, in the result file. Always done when the metrics endpoint is turned on. If not included, the performance might be slightly higher.")
+}
+
+pub fn record_latencies_string_from_matches(
+    matches: &ArgMatches<'static>,
+) -> String {
+    match matches.is_present("record-latencies") {
+        true => "--record-latencies".to_string(),
+        false => "".to_string(),
+    }
+}
+
+pub fn partition_file() -> Arg<'static, 'static> {
+    Arg::with_name("partition-file")
+        .takes_value(true)
+        .long("partition-file")
+        .help("A file with a schedule of network partitions. Each line is start second, end second and two groups of space separated node ids, for example \"10,20,1 2,3 4 5\".")
+}
+
+pub fn partition_file_string_from_matches(
+    matches: &ArgMatches<'static>,
+) -> String {
+    match matches.value_of("partition-file") {
+        Some(file_path) => format!("--partition-file {}", file_path),
+        None => "".to_string(),
+    }
+}
+
+pub fn client_port_offset() -> Arg<'static, 'static> {
+    Arg::with_name("client-port-offset")
+        .takes_value(true)
+        .long("client-port-offset")
+        .default_value("0")
+        .help("The client endpoint of a node is on 127.0.0.1, on the port of the node's address in the hosts file plus this offset. 0 turns off the client endpoints.")
+}
+
+pub fn client_port_offset_from_matches(matches: &ArgMatches<'static>) -> u16 {
+    matches
+        .value_of("client-port-offset")
+        .expect("client port offset arg not existing.")
+        .parse()
+        .expect("Could not parse the client port offset.")
+}
+
+pub fn client_port_offset_string_from_matches(
+    matches: &ArgMatches<'static>,
+) -> String {
+    format!(
+        "--client-port-offset {}",
+        client_port_offset_from_matches(matches)
+    )
+}
+
+pub fn admin_port_offset() -> Arg<'static, 'static> {
+    Arg::with_name("admin-port-offset")
+        .takes_value(true)
+        .long("admin-port-offset")
+        .default_value("0")
+        .help("The admin endpoint of a node is on 127.0.0.1, on the port of the node's address in the hosts file plus this offset. 0 turns off the admin endpoints.")
+}
+
+pub fn admin_port_offset_from_matches(matches: &ArgMatches<'static>) -> u16 {
+    matches
+        .value_of("admin-port-offset")
+        .expect("admin port offset arg not existing.")
+        .parse()
+        .expect("Could not parse the admin port offset.")
+}
+
+pub fn admin_port_offset_string_from_matches(
+    matches: &ArgMatches<'static>,
+) -> String {
+    format!(
+        "--admin-port-offset {}",
+        admin_port_offset_from_matches(matches)
+    )
+}
+
+pub fn metrics_port_offset() -> Arg<'static, 'static> {
+    Arg::with_name("metrics-port-offset")
+        .takes_value(true)
+        .long("metrics-port-offset")
+        .default_value("0")
+        .help("The Prometheus metrics of a node are served over http, on the port of the node's address in the hosts file plus this offset, at /metrics. 0 turns off the metrics endpoints.")
+}
+
+pub fn metrics_port_offset_from_matches(
+    matches: &ArgMatches<'static>,
+) -> u16 {
+    matches
+        .value_of("metrics-port-offset")
+        .expect("metrics port offset arg not existing.")
+        .parse()
+        .expect("Could not parse the metrics port offset.")
+}
+
+pub fn metrics_port_offset_string_from_matches(
+    matches: &ArgMatches<'static>,
+) -> String {
+    format!(
+        "--metrics-port-offset {}",
+        metrics_port_offset_from_matches(matches)
+    )
+}
+
 pub fn color_from_node_id(node_id: NodeId) -> Color {
     let colors = vec![Black, Red, Green, Yellow, Blue, Magenta, Cyan];
     colors[(node_id as usize) % colors.len()]
@@ -158,3 +309,7 @@ pub fn color_from_node_id(node_id: NodeId) -> Color {
 pub fn run_result_file_name_from_node_id(node_id: NodeId) -> String {
     format!("node{:0>6}.eval", node_id)
 }
+
+pub fn history_file_name_from_node_id(node_id: NodeId) -> String {
+    format!("node{:0>6}.history", node_id)
+}
@@ -0,0 +1,175 @@
+use crate::types::Timestamp;
+
+/*
+The line-based protocol of the client endpoint of a node. Each request
+is one line, and gets one line in response:
+
+GET                 VALUE <value>
+GET_VERSIONED       VERSIONED <timestamp> <value>
+PUT <value>         OK
+
+A request that fails gets ERROR <reason> instead. Everything after the
+first space is the value, so values may contain spaces, but not line
+breaks. The timestamp of a value is its version: a larger timestamp
+means a later write.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Get,
+    GetVersioned,
+    Put(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    Value(String),
+    Versioned(Timestamp, String),
+    Ok,
+    Error(String),
+}
+
+impl Request {
+    pub fn from_line(line: &str) -> Result<Request, String> {
+        let (command, argument) = split_command(line);
+        match (command, argument) {
+            ("GET", None) => Ok(Request::Get),
+            ("GET_VERSIONED", None) => Ok(Request::GetVersioned),
+            ("PUT", Some(value)) => Ok(Request::Put(value.to_string())),
+            ("PUT", None) => Err("PUT needs a value.".to_string()),
+            _ => Err(format!("Unknown request \"{}\".", line)),
+        }
+    }
+
+    // A value with a line break can't be put, since it would end the
+    // request line.
+    pub fn to_line(&self) -> Result<String, String> {
+        match self {
+            Request::Get => Ok("GET".to_string()),
+            Request::GetVersioned => Ok("GET_VERSIONED".to_string()),
+            Request::Put(value) => {
+                if value.contains(&['\n', '\r'][..]) {
+                    Err("The value can't contain line breaks.".to_string())
+                } else {
+                    Ok(format!("PUT {}", value))
+                }
+            }
+        }
+    }
+}
+
+impl Response {
+    pub fn from_line(line: &str) -> Result<Response, String> {
+        let (command, argument) = split_command(line);
+        match (command, argument) {
+            ("VALUE", argument) => {
+                Ok(Response::Value(argument.unwrap_or("").to_string()))
+            }
+            ("VERSIONED", Some(argument)) => {
+                let (timestamp, value) = split_command(argument);
+                let timestamp = timestamp.parse().map_err(|_| {
+                    format!(
+                        "Could not parse the timestamp in \"{}\".",
+                        line
+                    )
+                })?;
+                Ok(Response::Versioned(
+                    timestamp,
+                    value.unwrap_or("").to_string(),
+                ))
+            }
+            ("OK", None) => Ok(Response::Ok),
+            ("ERROR", reason) => {
+                Ok(Response::Error(reason.unwrap_or("").to_string()))
+            }
+            _ => Err(format!("Unknown response \"{}\".", line)),
+        }
+    }
+
+    pub fn to_line(&self) -> String {
+        match self {
+            Response::Value(value) => format!("VALUE {}", value),
+            Response::Versioned(timestamp, value) => {
+                format!("VERSIONED {} {}", timestamp, value)
+            }
+            Response::Ok => "OK".to_string(),
+            Response::Error(reason) => format!("ERROR {}", reason),
+        }
+    }
+}
+
+fn split_command(line: &str) -> (&str, Option<&str>) {
+    match line.find(' ') {
+        Some(index) => (&line[..index], Some(&line[index + 1..])),
+        None => (line, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_requests_are_parsed_from_their_lines() {
+        let requests = vec![
+            Request::Get,
+            Request::GetVersioned,
+            Request::Put("Val: 1".to_string()),
+            Request::Put("".to_string()),
+        ];
+
+        for request in requests {
+            assert_eq!(
+                Request::from_line(&request.to_line().unwrap()),
+                Ok(request)
+            );
+        }
+    }
+
+    #[test]
+    fn test_that_values_with_spaces_and_commands_are_kept() {
+        let values =
+            vec!["PUT GET", " leading", "trailing ", "a  b", "OK"];
+
+        for value in values {
+            let request = Request::Put(value.to_string());
+            assert_eq!(
+                Request::from_line(&request.to_line().unwrap()),
+                Ok(request)
+            );
+        }
+    }
+
+    #[test]
+    fn test_that_a_value_with_a_line_break_is_not_put() {
+        for value in &["first\nsecond", "first\r\nsecond", "last\r"] {
+            assert!(Request::Put(value.to_string()).to_line().is_err());
+        }
+    }
+
+    #[test]
+    fn test_that_responses_are_parsed_from_their_lines() {
+        let responses = vec![
+            Response::Value("Val: 1".to_string()),
+            Response::Value("".to_string()),
+            Response::Versioned(7, "Val: 7".to_string()),
+            Response::Ok,
+            Response::Error("Timed out.".to_string()),
+        ];
+
+        for response in responses {
+            assert_eq!(
+                Response::from_line(&response.to_line()),
+                Ok(response)
+            );
+        }
+    }
+
+    #[test]
+    fn test_that_malformed_requests_are_errors() {
+        assert!(Request::from_line("").is_err());
+        assert!(Request::from_line("get").is_err());
+        assert!(Request::from_line("GET now").is_err());
+        assert!(Request::from_line("PUT").is_err());
+        assert!(Response::from_line("VERSIONED x Val: 1").is_err());
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+// Each doubling of the latency is split into this many buckets, so a
+// bucket is about 19% wide.
+const BUCKETS_PER_DOUBLING: usize = 4;
+// The last bucket starts at 2^27 microseconds, about two minutes.
+const NUMBER_OF_BUCKETS: usize = 28 * BUCKETS_PER_DOUBLING;
+
+/*
+A histogram of latencies, with buckets that grow exponentially, so
+that both short and long latencies are recorded with about the same
+relative precision, in constant memory. Bucket i has the latencies from
+2^(i/4) up to 2^((i+1)/4) microseconds, except the first, which also
+has the latencies below one microsecond, and the last, which has all
+the latencies above it.
+
+The bucket bounds at whole powers of two are exact, which is what
+cumulative_count_at_most() needs.
+
+In the result file, a histogram also has its p50, p90 and p99, and
+only the buckets that aren't empty, so that it can be read without
+the buckets, and merged with the histograms of the other nodes.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(
+    into = "SerializedLatencyHistogram",
+    try_from = "SerializedLatencyHistogram"
+)]
+pub struct LatencyHistogram {
+    bucket_counts: Vec<u64>,
+    count: u64,
+    sum_micros: u64,
+    max_micros: u64,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> LatencyHistogram {
+        LatencyHistogram {
+            bucket_counts: vec![0; NUMBER_OF_BUCKETS],
+            count: 0,
+            sum_micros: 0,
+            max_micros: 0,
+        }
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let micros = latency.as_micros() as u64;
+        self.bucket_counts[bucket_index(micros)] += 1;
+        self.count += 1;
+        self.sum_micros += micros;
+        self.max_micros = self.max_micros.max(micros);
+    }
+
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (count, other_count) in self
+            .bucket_counts
+            .iter_mut()
+            .zip(other.bucket_counts.iter())
+        {
+            *count += other_count;
+        }
+        self.count += other.count;
+        self.sum_micros += other.sum_micros;
+        self.max_micros = self.max_micros.max(other.max_micros);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> Duration {
+        Duration::from_micros(self.sum_micros)
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.max_micros)
+    }
+
+    // The number of latencies of at most 2^exponent microseconds.
+    pub fn cumulative_count_at_most(&self, exponent: u32) -> u64 {
+        let number_of_buckets = (exponent as usize * BUCKETS_PER_DOUBLING)
+            .min(NUMBER_OF_BUCKETS);
+        self.bucket_counts[..number_of_buckets].iter().sum()
+    }
+
+    // The upper bound of the bucket of the latency at the given
+    // percentile, but at most the max latency. None if nothing has
+    // been recorded.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let rank = ((percentile / 100.0 * self.count as f64).ceil()
+            as u64)
+            .max(1)
+            .min(self.count);
+        let mut cumulative_count = 0;
+        for (index, count) in self.bucket_counts.iter().enumerate() {
+            cumulative_count += count;
+            if cumulative_count >= rank {
+                let upper_bound = bucket_upper_bound_micros(index);
+                return Some(Duration::from_micros(
+                    upper_bound.min(self.max_micros),
+                ));
+            }
+        }
+        Some(self.max())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedLatencyHistogram {
+    count: u64,
+    p50_micros: Option<u64>,
+    p90_micros: Option<u64>,
+    p99_micros: Option<u64>,
+    max_micros: u64,
+    sum_micros: u64,
+    // Bucket index to count.
+    bucket_counts: BTreeMap<usize, u64>,
+}
+
+impl From<LatencyHistogram> for SerializedLatencyHistogram {
+    fn from(histogram: LatencyHistogram) -> SerializedLatencyHistogram {
+        let percentile_micros = |percentile| {
+            histogram
+                .percentile(percentile)
+                .map(|latency: Duration| latency.as_micros() as u64)
+        };
+
+        SerializedLatencyHistogram {
+            count: histogram.count,
+            p50_micros: percentile_micros(50.0),
+            p90_micros: percentile_micros(90.0),
+            p99_micros: percentile_micros(99.0),
+            max_micros: histogram.max_micros,
+            sum_micros: histogram.sum_micros,
+            bucket_counts: histogram
+                .bucket_counts
+                .iter()
+                .enumerate()
+                .filter(|(_, &count)| count > 0)
+                .map(|(index, &count)| (index, count))
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<SerializedLatencyHistogram> for LatencyHistogram {
+    type Error = String;
+
+    fn try_from(
+        serialized: SerializedLatencyHistogram,
+    ) -> Result<LatencyHistogram, String> {
+        let mut histogram = LatencyHistogram::new();
+        for (index, count) in serialized.bucket_counts {
+            if index >= NUMBER_OF_BUCKETS {
+                return Err(format!("There is no bucket {}.", index));
+            }
+            histogram.bucket_counts[index] = count;
+        }
+        if histogram.bucket_counts.iter().sum::<u64>() != serialized.count
+        {
+            return Err(
+                "The bucket counts don't add up to the count.".to_string()
+            );
+        }
+        histogram.count = serialized.count;
+        histogram.sum_micros = serialized.sum_micros;
+        histogram.max_micros = serialized.max_micros;
+        Ok(histogram)
+    }
+}
+
+fn bucket_index(micros: u64) -> usize {
+    if micros <= 1 {
+        return 0;
+    }
+    let index = ((micros as f64).log2() * BUCKETS_PER_DOUBLING as f64)
+        .floor() as usize;
+    // A latency at a bound belongs to the bucket below it, like the
+    // le buckets of Prometheus.
+    let index =
+        if bucket_upper_bound_micros(index.saturating_sub(1)) >= micros {
+            index.saturating_sub(1)
+        } else {
+            index
+        };
+    index.min(NUMBER_OF_BUCKETS - 1)
+}
+
+fn bucket_upper_bound_micros(index: usize) -> u64 {
+    let exponent = (index + 1) as f64 / BUCKETS_PER_DOUBLING as f64;
+    2f64.powf(exponent).round() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn micros(micros: u64) -> Duration {
+        Duration::from_micros(micros)
+    }
+
+    #[test]
+    fn test_that_latencies_at_a_power_of_two_are_at_most_it() {
+        let mut histogram = LatencyHistogram::new();
+        histogram.record(micros(1024));
+        histogram.record(micros(1025));
+
+        assert_eq!(histogram.cumulative_count_at_most(9), 0);
+        assert_eq!(histogram.cumulative_count_at_most(10), 1);
+        assert_eq!(histogram.cumulative_count_at_most(11), 2);
+        assert_eq!(histogram.cumulative_count_at_most(100), 2);
+    }
+
+    #[test]
+    fn test_that_percentiles_are_within_a_bucket() {
+        let mut histogram = LatencyHistogram::new();
+        for latency in 1..=1000 {
+            histogram.record(micros(latency));
+        }
+
+        for &(percentile, exact) in
+            &[(50.0, 500), (90.0, 900), (99.0, 990)]
+        {
+            let estimate =
+                histogram.percentile(percentile).unwrap().as_micros()
+                    as f64;
+            assert!(estimate >= exact as f64);
+            assert!(estimate <= exact as f64 * 1.2);
+        }
+        assert_eq!(histogram.percentile(100.0), Some(micros(1000)));
+        assert_eq!(histogram.max(), micros(1000));
+        assert_eq!(histogram.count(), 1000);
+    }
+
+    #[test]
+    fn test_that_an_empty_histogram_has_no_percentiles() {
+        assert_eq!(LatencyHistogram::new().percentile(50.0), None);
+    }
+
+    #[test]
+    fn test_that_merged_histograms_have_all_latencies() {
+        let mut histogram = LatencyHistogram::new();
+        histogram.record(micros(10));
+        let mut other = LatencyHistogram::new();
+        other.record(micros(5000));
+        other.record(Duration::from_secs(1000));
+
+        histogram.merge(&other);
+
+        assert_eq!(histogram.count(), 3);
+        assert_eq!(histogram.max(), Duration::from_secs(1000));
+        assert_eq!(histogram.sum(), micros(1_000_005_010));
+        assert_eq!(histogram.cumulative_count_at_most(4), 1);
+    }
+
+    #[test]
+    fn test_that_a_histogram_is_the_same_after_serialization() {
+        let mut histogram = LatencyHistogram::new();
+        for latency in &[3, 400, 400, 7000, 2_000_000] {
+            histogram.record(micros(*latency));
+        }
+
+        let json = serde_json::to_string(&histogram).unwrap();
+
+        assert!(json.contains("\"p50_micros\":"));
+        assert!(json.contains("\"max_micros\":2000000"));
+        let parsed: LatencyHistogram =
+            serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, histogram);
+        assert!(serde_json::from_str::<LatencyHistogram>(
+            &json.replace("\"count\":5", "\"count\":6")
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,114 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{NodeId, Timestamp};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationKind {
+    Write,
+    Read,
+}
+
+// A single client operation. The timestamp is the wall clock time
+// when the operation started, in microseconds since the Unix epoch.
+// The start and end times are monotonic, in microseconds since the
+// node started. The end time and, for reads, the value are None if
+// the operation never returned. The version is the timestamp of the
+// value in the register, which tells apart writes of the same value.
+// It is None for a read that never returned, and in histories from
+// before versions were recorded.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Operation {
+    pub node_id: NodeId,
+    pub kind: OperationKind,
+    pub value: Option<String>,
+    pub timestamp: u64,
+    pub start: u64,
+    pub end: Option<u64>,
+    pub version: Option<Timestamp>,
+}
+
+impl Operation {
+    pub fn is_complete(&self) -> bool {
+        self.end.is_some()
+    }
+
+    // The monotonic times are only comparable within a single node.
+    // This moves them to the wall clock, using the timestamp.
+    pub fn on_wall_clock(&self) -> Operation {
+        let mut operation = self.clone();
+        operation.start = self.timestamp;
+        operation.end =
+            self.end.map(|end| self.timestamp + end - self.start);
+        operation
+    }
+}
+
+// Parses a history file, which has one json operation per line. A last
+// line without a line break that can't be parsed is skipped, since it
+// is what a node that was killed while writing leaves behind.
+pub fn operations_from_json_lines(
+    string: &str,
+) -> Result<Vec<Operation>, String> {
+    let mut operations = Vec::new();
+    let lines: Vec<&str> = string.lines().collect();
+
+    for (index, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let is_truncated_last_line =
+            index == lines.len() - 1 && !string.ends_with('\n');
+        match serde_json::from_str(line) {
+            Ok(operation) => operations.push(operation),
+            Err(_) if is_truncated_last_line => {}
+            Err(error) => {
+                return Err(format!(
+                    "Could not parse the operation on line {}: {}",
+                    index + 1,
+                    error
+                ))
+            }
+        }
+    }
+
+    Ok(operations)
+}
+
+// The histories of different nodes are compared with each other, so
+// wall clock time is used. On a single machine this is accurate, but
+// for remote machines it is only as good as their clock
+// synchronization.
+pub fn now_in_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("The system time is before the Unix epoch.")
+        .as_micros() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATION_LINE: &str = r#"{"node_id":1,"kind":"Write","value":"a","timestamp":5,"start":0,"end":2,"version":1}"#;
+
+    #[test]
+    fn test_that_a_truncated_last_line_is_skipped() {
+        let string = format!("{}\n{{\"node_id\":1,\"ki", OPERATION_LINE);
+
+        let operations = operations_from_json_lines(&string).unwrap();
+
+        assert_eq!(operations.len(), 1);
+        assert_eq!(operations[0].value, Some("a".to_string()));
+    }
+
+    #[test]
+    fn test_that_a_corrupt_line_is_an_error() {
+        let string = format!("{{\"node_id\":1\n{}\n", OPERATION_LINE);
+
+        assert!(operations_from_json_lines(&string)
+            .unwrap_err()
+            .contains("line 1"));
+    }
+}
@@ -1,7 +1,13 @@
 //#![allow(dead_code, unused_variables, unused_imports, unused_mut)]
 
+pub mod admin_protocol;
+pub mod aggregation;
 pub mod arguments;
+pub mod client_protocol;
 pub mod execution;
+pub mod histogram;
+pub mod history;
+pub mod linearizability;
 pub mod node_info;
 pub mod remote_machine;
 pub mod run_result;
@@ -0,0 +1,461 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::history::{Operation, OperationKind};
+use crate::types::Timestamp;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub reason: String,
+    pub sub_history: Vec<Operation>,
+}
+
+impl Violation {
+    fn new(reason: &str, operations: Vec<&Operation>) -> Violation {
+        let mut sub_history: Vec<Operation> =
+            operations.into_iter().cloned().collect();
+        sub_history.sort_by_key(|operation| operation.start);
+
+        Violation {
+            reason: reason.to_string(),
+            sub_history: sub_history,
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.reason)?;
+        for operation in self.sub_history.iter() {
+            writeln!(
+                f,
+                "  Node {} {:?} {:?} [{}, {}]",
+                operation.node_id,
+                operation.kind,
+                operation.value,
+                operation.start,
+                match operation.end {
+                    Some(end) => end.to_string(),
+                    None => "pending".to_string(),
+                }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/*
+Checks that a history of operations on a single-writer register is
+linearizable. The register starts with the empty string.
+
+A general Wing-Gong search tries every order of the concurrent
+operations, which is exponential in the worst case. For a register
+with a single writer, the writes are totally ordered and each read
+can be mapped to the write it returned, by the version if the history
+has them, and otherwise by the value, which then must be unique. The
+history is then linearizable if and only if:
+
+1. every read returns the initial value or a written value,
+2. no read returns the value of a write that started after the read
+   ended,
+3. no read returns a value that was overwritten before the read
+   started, and
+4. no read returns an older value than a read that ended before it
+   started.
+
+These are checked in O(n log n), so also long runs can be checked.
+The operations of different nodes are compared on the wall clock.
+The first violation found is returned together with the operations
+that make up the violation.
+*/
+pub fn check(operations: &[Operation]) -> Result<(), Violation> {
+    let operations: Vec<Operation> =
+        operations.iter().map(Operation::on_wall_clock).collect();
+    let writes = sorted_writes(&operations)?;
+    let write_indices = WriteIndices::new(&writes)?;
+
+    let mut reads: Vec<(&Operation, usize)> = Vec::new();
+    for read in operations.iter().filter(|operation| {
+        operation.kind == OperationKind::Read && operation.is_complete()
+    }) {
+        reads.push((read, write_indices.index_of(read, &writes)?));
+    }
+
+    for &(read, index) in reads.iter() {
+        check_not_from_the_future(read, index, &writes)?;
+        check_not_overwritten(read, index, &writes)?;
+    }
+
+    check_no_new_old_inversion(&reads, &writes)
+}
+
+fn sorted_writes(
+    operations: &[Operation],
+) -> Result<Vec<&Operation>, Violation> {
+    let mut writes: Vec<&Operation> = operations
+        .iter()
+        .filter(|operation| operation.kind == OperationKind::Write)
+        .collect();
+    writes.sort_by_key(|write| write.start);
+
+    for pair in writes.windows(2) {
+        let overlapping = match pair[0].end {
+            Some(end) => end > pair[1].start,
+            None => true,
+        };
+        if overlapping {
+            return Err(Violation::new(
+                "Two writes overlap, so the register is not single-writer.",
+                vec![pair[0], pair[1]],
+            ));
+        }
+    }
+
+    Ok(writes)
+}
+
+// Index 0 is the initial value and index i is the i:th write.
+struct WriteIndices<'a> {
+    by_version: HashMap<Timestamp, usize>,
+    // None if the value was written more than once.
+    by_value: HashMap<&'a str, Option<usize>>,
+}
+
+impl<'a> WriteIndices<'a> {
+    fn new(
+        writes: &[&'a Operation],
+    ) -> Result<WriteIndices<'a>, Violation> {
+        let mut by_version = HashMap::new();
+        let mut by_value = HashMap::new();
+        by_version.insert(0, 0);
+        by_value.insert("", Some(0));
+
+        for (i, write) in writes.iter().enumerate() {
+            if let Some(version) = write.version {
+                if by_version.insert(version, i + 1).is_some() {
+                    return Err(Violation::new(
+                        "A version was written twice.",
+                        vec![write],
+                    ));
+                }
+            }
+            by_value
+                .entry(value_of(write))
+                .and_modify(|index| *index = None)
+                .or_insert(Some(i + 1));
+        }
+
+        Ok(WriteIndices {
+            by_version: by_version,
+            by_value: by_value,
+        })
+    }
+
+    fn index_of(
+        &self,
+        read: &Operation,
+        writes: &[&Operation],
+    ) -> Result<usize, Violation> {
+        let index = match read.version {
+            Some(version) => self.by_version.get(&version).cloned(),
+            None => match self.by_value.get(value_of(read)) {
+                Some(Some(index)) => Some(*index),
+                Some(None) => {
+                    return Err(Violation::new(
+                        "A read without a version returned a value that was written twice, which the checker does not support.",
+                        vec![read],
+                    ));
+                }
+                None => None,
+            },
+        };
+
+        match index {
+            Some(0) if value_of(read).is_empty() => Ok(0),
+            Some(index)
+                if index > 0
+                    && value_of(writes[index - 1]) == value_of(read) =>
+            {
+                Ok(index)
+            }
+            _ => Err(Violation::new(
+                "A read returned a value that was never written.",
+                vec![read],
+            )),
+        }
+    }
+}
+
+fn value_of(operation: &Operation) -> &str {
+    operation.value.as_deref().unwrap_or("")
+}
+
+fn check_not_from_the_future(
+    read: &Operation,
+    index: usize,
+    writes: &[&Operation],
+) -> Result<(), Violation> {
+    if index == 0 {
+        return Ok(());
+    }
+
+    let write = writes[index - 1];
+    if write.start > read.end.unwrap() {
+        return Err(Violation::new(
+            "A read returned the value of a write that started after the read ended.",
+            vec![write, read],
+        ));
+    }
+
+    Ok(())
+}
+
+fn check_not_overwritten(
+    read: &Operation,
+    index: usize,
+    writes: &[&Operation],
+) -> Result<(), Violation> {
+    let number_of_completed_writes = writes
+        .iter()
+        .take_while(|write| match write.end {
+            Some(end) => end < read.start,
+            None => false,
+        })
+        .count();
+
+    if index < number_of_completed_writes {
+        let mut operations = vec![writes[number_of_completed_writes - 1], read];
+        if index > 0 {
+            operations.push(writes[index - 1]);
+        }
+        return Err(Violation::new(
+            "A read returned a value that was overwritten before the read started.",
+            operations,
+        ));
+    }
+
+    Ok(())
+}
+
+fn check_no_new_old_inversion(
+    reads: &[(&Operation, usize)],
+    writes: &[&Operation],
+) -> Result<(), Violation> {
+    let mut reads_by_start = reads.to_vec();
+    reads_by_start.sort_by_key(|&(read, _)| read.start);
+    let mut reads_by_end = reads.to_vec();
+    reads_by_end.sort_by_key(|&(read, _)| read.end);
+
+    let mut ended = 0;
+    let mut newest_ended_read: Option<(&Operation, usize)> = None;
+
+    for &(read, index) in reads_by_start.iter() {
+        while ended < reads_by_end.len()
+            && reads_by_end[ended].0.end.unwrap() < read.start
+        {
+            let candidate = reads_by_end[ended];
+            match newest_ended_read {
+                Some((_, newest_index)) if newest_index >= candidate.1 => {}
+                _ => newest_ended_read = Some(candidate),
+            }
+            ended += 1;
+        }
+
+        if let Some((earlier_read, earlier_index)) = newest_ended_read {
+            if earlier_index > index {
+                return Err(Violation::new(
+                    "A read returned an older value than a read that ended before it started.",
+                    vec![writes[earlier_index - 1], earlier_read, read],
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(value: &str, start: u64, end: Option<u64>) -> Operation {
+        Operation {
+            node_id: 3,
+            kind: OperationKind::Write,
+            value: Some(value.to_string()),
+            timestamp: start,
+            start: start,
+            end: end,
+            version: None,
+        }
+    }
+
+    fn read(node_id: i32, value: &str, start: u64, end: u64) -> Operation {
+        Operation {
+            node_id: node_id,
+            kind: OperationKind::Read,
+            value: Some(value.to_string()),
+            timestamp: start,
+            start: start,
+            end: Some(end),
+            version: None,
+        }
+    }
+
+    #[test]
+    fn test_that_an_empty_history_is_linearizable() {
+        assert_eq!(check(&[]), Ok(()));
+    }
+
+    #[test]
+    fn test_that_a_sequential_history_is_linearizable() {
+        let history = vec![
+            read(1, "", 0, 1),
+            write("a", 2, Some(3)),
+            read(1, "a", 4, 5),
+            write("b", 6, Some(7)),
+            read(2, "b", 8, 9),
+        ];
+        assert_eq!(check(&history), Ok(()));
+    }
+
+    #[test]
+    fn test_that_concurrent_reads_may_return_the_old_or_the_new_value() {
+        let history = vec![
+            write("a", 0, Some(1)),
+            write("b", 10, Some(20)),
+            read(1, "a", 11, 12),
+            read(2, "b", 11, 19),
+        ];
+        assert_eq!(check(&history), Ok(()));
+    }
+
+    #[test]
+    fn test_that_a_pending_write_may_be_read() {
+        let history = vec![write("a", 0, None), read(1, "a", 5, 6)];
+        assert_eq!(check(&history), Ok(()));
+    }
+
+    #[test]
+    fn test_that_pending_reads_are_ignored() {
+        let mut pending_read = read(1, "", 0, 0);
+        pending_read.value = None;
+        pending_read.end = None;
+        let history = vec![write("a", 0, Some(1)), pending_read];
+        assert_eq!(check(&history), Ok(()));
+    }
+
+    #[test]
+    fn test_that_an_unwritten_value_is_a_violation() {
+        let history = vec![write("a", 0, Some(1)), read(1, "x", 2, 3)];
+        let violation = check(&history).unwrap_err();
+        assert_eq!(violation.sub_history, vec![read(1, "x", 2, 3)]);
+    }
+
+    #[test]
+    fn test_that_a_value_from_the_future_is_a_violation() {
+        let history = vec![read(1, "a", 0, 1), write("a", 2, Some(3))];
+        let violation = check(&history).unwrap_err();
+        assert_eq!(violation.sub_history, history);
+    }
+
+    #[test]
+    fn test_that_an_overwritten_value_is_a_violation() {
+        let history = vec![
+            write("a", 0, Some(1)),
+            write("b", 2, Some(3)),
+            read(1, "a", 4, 5),
+        ];
+        let violation = check(&history).unwrap_err();
+        assert_eq!(violation.sub_history, history);
+    }
+
+    #[test]
+    fn test_that_reading_the_initial_value_after_a_write_is_a_violation() {
+        let history = vec![write("a", 0, Some(1)), read(1, "", 2, 3)];
+        let violation = check(&history).unwrap_err();
+        assert_eq!(violation.sub_history, history);
+    }
+
+    #[test]
+    fn test_that_a_new_old_inversion_is_a_violation() {
+        let history = vec![
+            write("a", 0, Some(1)),
+            write("b", 10, Some(30)),
+            read(1, "b", 11, 12),
+            read(2, "a", 13, 14),
+        ];
+        let violation = check(&history).unwrap_err();
+        assert_eq!(
+            violation.sub_history,
+            vec![
+                write("b", 10, Some(30)),
+                read(1, "b", 11, 12),
+                read(2, "a", 13, 14),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_that_operations_are_compared_on_the_wall_clock() {
+        let mut late_read = read(1, "", 0, 1);
+        late_read.timestamp = 100;
+        let history = vec![write("a", 10, Some(20)), late_read];
+        let violation = check(&history).unwrap_err();
+        assert_eq!(violation.sub_history[1].start, 100);
+        assert_eq!(violation.sub_history[1].end, Some(101));
+    }
+
+    #[test]
+    fn test_that_overlapping_writes_are_rejected() {
+        let history = vec![write("a", 0, Some(5)), write("b", 2, Some(3))];
+        assert!(check(&history).is_err());
+    }
+
+    #[test]
+    fn test_that_reading_a_value_written_twice_needs_a_version() {
+        let history = vec![
+            write("a", 0, Some(1)),
+            write("a", 2, Some(3)),
+            read(1, "a", 4, 5),
+        ];
+        assert!(check(&history).is_err());
+    }
+
+    fn versioned(
+        mut operation: Operation,
+        version: Timestamp,
+    ) -> Operation {
+        operation.version = Some(version);
+        operation
+    }
+
+    // The writer writes its own values, and a client writes a value it
+    // already wrote and the initial value through the client endpoint.
+    #[test]
+    fn test_that_old_and_initial_values_can_be_written_again() {
+        let history = vec![
+            versioned(write("Val: 1", 0, Some(1)), 1),
+            versioned(write("Val: 1", 2, Some(3)), 2),
+            versioned(write("Val: 2", 4, Some(5)), 3),
+            versioned(write("", 6, Some(7)), 4),
+            versioned(read(1, "Val: 1", 2, 3), 1),
+            versioned(read(2, "Val: 1", 3, 4), 2),
+            versioned(read(1, "", 7, 8), 4),
+        ];
+        assert_eq!(check(&history), Ok(()));
+
+        let mut stale_history = history.clone();
+        stale_history.push(versioned(read(2, "Val: 1", 4, 8), 1));
+        assert!(check(&stale_history).is_err());
+    }
+
+    #[test]
+    fn test_that_a_read_must_return_the_value_of_its_version() {
+        let history = vec![
+            versioned(write("a", 0, Some(1)), 1),
+            versioned(read(1, "b", 2, 3), 1),
+        ];
+        assert!(check(&history).is_err());
+    }
+}
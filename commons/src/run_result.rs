@@ -1,8 +1,9 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::iter::FromIterator;
 
 use serde::{Deserialize, Serialize};
 
+use crate::histogram::LatencyHistogram;
 use crate::types::{Int, NodeId};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -11,6 +12,8 @@ pub struct RunResult {
     pub write_quorum_accesses: Int,
     pub read_ops: Int,
     pub read_quorum_accesses: Int,
+    pub quorum_accesses: QuorumAccesses,
+    pub latencies: Latencies,
 
     pub write_message: MessageTypeResult,
     pub write_ack_message: MessageTypeResult,
@@ -18,6 +21,15 @@ pub struct RunResult {
     pub read1_ack_message: MessageTypeResult,
     pub read2_message: MessageTypeResult,
     pub read2_ack_message: MessageTypeResult,
+    pub heartbeat_message: MessageTypeResult,
+    pub heartbeat_ack_message: MessageTypeResult,
+
+    pub injected_faults: InjectedFaults,
+    pub rejected_messages: RejectedMessages,
+    pub network_errors: NetworkErrors,
+    pub peer_protocol_versions: HashMap<NodeId, u32>,
+    // The number of times each node was suspected to have failed.
+    pub suspected_nodes: HashMap<NodeId, Int>,
 
     pub metadata: Metadata,
 }
@@ -29,6 +41,8 @@ impl RunResult {
             write_quorum_accesses: 0,
             read_ops: 0,
             read_quorum_accesses: 0,
+            quorum_accesses: QuorumAccesses::new(),
+            latencies: Latencies::new(),
 
             write_message: MessageTypeResult::new(),
             write_ack_message: MessageTypeResult::new(),
@@ -36,35 +50,65 @@ impl RunResult {
             read1_ack_message: MessageTypeResult::new(),
             read2_message: MessageTypeResult::new(),
             read2_ack_message: MessageTypeResult::new(),
+            heartbeat_message: MessageTypeResult::new(),
+            heartbeat_ack_message: MessageTypeResult::new(),
+
+            injected_faults: InjectedFaults::new(),
+            rejected_messages: RejectedMessages::new(),
+            network_errors: NetworkErrors::new(),
+            peer_protocol_versions: HashMap::new(),
+            suspected_nodes: HashMap::new(),
 
             metadata: Metadata::new(),
         }
     }
 
+    // With fastest_quorum_first, the messages are first sent only to
+    // a majority, so the other nodes may never have acked. The writer
+    // only reads when it serves reads from the client endpoint, which
+    // may be too few for all nodes to have acked.
     pub fn is_sound(&self, number_of_nodes: Int) -> bool {
         let mut sound = true;
+        let metadata = &self.metadata;
+        let reads_need_all_nodes = !metadata.fastest_quorum_first
+            && !metadata.writer_served_client_reads;
 
         sound &= Self::implies(
-            self.metadata.is_writer,
-            self.write_ack_message.nodes_received_from
-                == Self::all_nodes_set(number_of_nodes),
+            metadata.is_writer,
+            Self::acked_by_enough_nodes(
+                &self.write_ack_message.nodes_received_from,
+                number_of_nodes,
+                !metadata.fastest_quorum_first,
+            ),
         );
 
         sound &= Self::implies(
-            self.metadata.is_reader,
-            self.read1_ack_message.nodes_received_from
-                == Self::all_nodes_set(number_of_nodes),
+            metadata.is_reader,
+            Self::acked_by_enough_nodes(
+                &self.read1_ack_message.nodes_received_from,
+                number_of_nodes,
+                reads_need_all_nodes,
+            ),
         );
 
         sound &= Self::implies(
-            self.metadata.is_reader,
-            self.read2_ack_message.nodes_received_from
-                == Self::all_nodes_set(number_of_nodes),
+            metadata.is_reader,
+            Self::acked_by_enough_nodes(
+                &self.read2_ack_message.nodes_received_from,
+                number_of_nodes,
+                reads_need_all_nodes,
+            ),
         );
 
-        sound &= Self::implies(self.metadata.is_writer, !self.metadata.is_reader);
+        sound &= Self::implies(
+            metadata.is_writer,
+            !metadata.is_reader || metadata.writer_served_client_reads,
+        );
 
-        sound &= Self::implies(self.metadata.is_reader, !self.metadata.is_writer);
+        sound &= Self::implies(
+            metadata.is_reader,
+            !metadata.is_writer || metadata.writer_served_client_reads,
+        );
 
         sound &= Self::implies(
             !self.metadata.is_writer,
@@ -92,6 +136,21 @@ impl RunResult {
         }
     }
 
+    // All nodes, or at least a majority of them.
+    fn acked_by_enough_nodes(
+        nodes_received_from: &HashSet<NodeId>,
+        number_of_nodes: Int,
+        all_nodes_needed: bool,
+    ) -> bool {
+        let all_nodes = Self::all_nodes_set(number_of_nodes);
+        if all_nodes_needed {
+            *nodes_received_from == all_nodes
+        } else {
+            nodes_received_from.is_subset(&all_nodes)
+                && nodes_received_from.len() as Int > number_of_nodes / 2
+        }
+    }
+
     fn all_nodes_set(number_of_nodes: Int) -> HashSet<NodeId> {
         HashSet::from_iter(1..(number_of_nodes + 1) as NodeId)
     }
@@ -114,12 +173,155 @@ impl MessageTypeResult {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InjectedFaults {
+    pub dropped: Int,
+    pub duplicated: Int,
+    pub delayed: Int,
+    pub reordered: Int,
+    pub partitioned: Int,
+}
+
+impl InjectedFaults {
+    pub fn new() -> InjectedFaults {
+        InjectedFaults {
+            dropped: 0,
+            duplicated: 0,
+            delayed: 0,
+            reordered: 0,
+            partitioned: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RejectedMessages {
+    pub malformed: Int,
+    pub incompatible_protocol_version: Int,
+    pub other_cluster: Int,
+    pub unknown_sender: Int,
+    pub unauthenticated: Int,
+    pub undecryptable: Int,
+    pub previous_incarnation: Int,
+}
+
+impl RejectedMessages {
+    pub fn new() -> RejectedMessages {
+        RejectedMessages {
+            malformed: 0,
+            incompatible_protocol_version: 0,
+            other_cluster: 0,
+            unknown_sender: 0,
+            unauthenticated: 0,
+            undecryptable: 0,
+            previous_incarnation: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NetworkErrors {
+    pub unknown_receiver: Int,
+    pub send_failed: Int,
+    pub receive_failed: Int,
+    // Partial UDP messages that were evicted, since their sender had
+    // too many incomplete messages.
+    pub evicted_partial_messages: Int,
+}
+
+impl NetworkErrors {
+    pub fn new() -> NetworkErrors {
+        NetworkErrors {
+            unknown_receiver: 0,
+            send_failed: 0,
+            receive_failed: 0,
+            evicted_partial_messages: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QuorumAccesses {
+    pub write: QuorumAccessResult,
+    pub read1: QuorumAccessResult,
+    pub read2: QuorumAccessResult,
+}
+
+impl QuorumAccesses {
+    pub fn new() -> QuorumAccesses {
+        QuorumAccesses {
+            write: QuorumAccessResult::new(),
+            read1: QuorumAccessResult::new(),
+            read2: QuorumAccessResult::new(),
+        }
+    }
+}
+
+// The counters of all the accesses of one kind of quorum. A round is
+// the first send of the message, or a retransmission timeout after
+// which it was sent again, and a retransmission is one message sent
+// again to a node that hadn't acked it. An ack that arrives while the
+// access still waits for a majority, including the one that completes
+// it, is before the majority. Only the accesses that reached a
+// majority have a time to majority.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QuorumAccessResult {
+    pub accesses: Int,
+    pub aborted: Int,
+    pub rounds: Int,
+    pub retransmissions: Int,
+    pub acks_before_majority: Int,
+    pub acks_after_majority: Int,
+    pub time_to_majority: LatencyHistogram,
+}
+
+impl QuorumAccessResult {
+    pub fn new() -> QuorumAccessResult {
+        QuorumAccessResult {
+            accesses: 0,
+            aborted: 0,
+            rounds: 0,
+            retransmissions: 0,
+            acks_before_majority: 0,
+            acks_after_majority: 0,
+            time_to_majority: LatencyHistogram::new(),
+        }
+    }
+}
+
+// The latencies of the reads and writes that weren't aborted, and of
+// the two phases of the reads. They are only recorded with
+// --record-latencies or when the metrics endpoint is turned on, and
+// are empty otherwise.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Latencies {
+    pub read: LatencyHistogram,
+    pub write: LatencyHistogram,
+    pub read_phase1: LatencyHistogram,
+    pub read_phase2: LatencyHistogram,
+}
+
+impl Latencies {
+    pub fn new() -> Latencies {
+        Latencies {
+            read: LatencyHistogram::new(),
+            write: LatencyHistogram::new(),
+            read_phase1: LatencyHistogram::new(),
+            read_phase2: LatencyHistogram::new(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Metadata {
     pub node_id: NodeId,
     pub is_reader: bool,
     pub is_writer: bool,
-    pub run_length: Int,
+    // In seconds.
+    pub run_length: f64,
+    pub fastest_quorum_first: bool,
+    // Whether the writer also served reads from the client endpoint.
+    pub writer_served_client_reads: bool,
 }
 
 impl Metadata {
@@ -128,7 +330,9 @@ impl Metadata {
             node_id: 0,
             is_reader: false,
             is_writer: false,
-            run_length: 0,
+            run_length: 0.0,
+            fastest_quorum_first: false,
+            writer_served_client_reads: false,
         }
     }
 }
@@ -13,7 +13,17 @@ pub struct Arguments {
     pub number_of_readers: Int,
     pub release_mode_string: String,
     pub print_client_operations_string: String,
+    pub record_latencies_string: String,
     pub run_length_string: String,
+    pub check_linearizability: bool,
+    pub aggregate: bool,
+    pub partition_file_string: String,
+    pub client_port_offset_string: String,
+    pub admin_port_offset_string: String,
+    pub metrics_port_offset_string: String,
+    pub transport: String,
+    pub authenticate: bool,
+    pub encrypt: bool,
 }
 
 impl Arguments {
@@ -34,9 +44,26 @@ impl Arguments {
                 arguments::print_client_operations_string_from_matches(
                     &matches,
                 ),
+            record_latencies_string:
+                arguments::record_latencies_string_from_matches(&matches),
             run_length_string: arguments::run_length_string_from_matches(
                 &matches,
             ),
+            check_linearizability: check_linearizability_from_matches(
+                &matches,
+            ),
+            aggregate: aggregate_from_matches(&matches),
+            partition_file_string:
+                arguments::partition_file_string_from_matches(&matches),
+            client_port_offset_string:
+                arguments::client_port_offset_string_from_matches(&matches),
+            admin_port_offset_string:
+                arguments::admin_port_offset_string_from_matches(&matches),
+            metrics_port_offset_string:
+                arguments::metrics_port_offset_string_from_matches(&matches),
+            transport: transport_from_matches(&matches),
+            authenticate: authenticate_from_matches(&matches),
+            encrypt: encrypt_from_matches(&matches),
         }
     }
 }
@@ -51,7 +78,17 @@ fn get_matches() -> ArgMatches<'static> {
         .arg(arguments::number_of_readers())
         .arg(arguments::optimize())
         .arg(arguments::print_client_operations())
+        .arg(arguments::record_latencies())
         .arg(arguments::run_length())
+        .arg(check_linearizability_argument())
+        .arg(aggregate_argument())
+        .arg(arguments::partition_file())
+        .arg(arguments::client_port_offset())
+        .arg(arguments::admin_port_offset())
+        .arg(arguments::metrics_port_offset())
+        .arg(transport_argument())
+        .arg(authenticate_argument())
+        .arg(encrypt_argument())
         .get_matches()
 }
 
@@ -68,3 +105,67 @@ fn number_of_nodes_from_matches(matches: &ArgMatches<'static>) -> Int {
         .parse()
         .expect("Could not parse number of nodes.")
 }
+
+fn check_linearizability_argument() -> Arg<'static, 'static> {
+    Arg::with_name("check-linearizability")
+        .takes_value(false)
+        .short("c")
+        .long("check-linearizability")
+        .help("When all nodes have exited, check that the operations they performed were linearizable. Requires a run length other than 0.")
+}
+
+fn check_linearizability_from_matches(matches: &ArgMatches<'static>) -> bool {
+    matches.is_present("check-linearizability")
+}
+
+fn aggregate_argument() -> Arg<'static, 'static> {
+    Arg::with_name("aggregate")
+        .takes_value(false)
+        .long("aggregate")
+        .help("When all nodes have exited, check that their result files are sound and print the throughput, messages per operation and latencies of the cluster. Requires a run length other than 0.")
+}
+
+fn aggregate_from_matches(matches: &ArgMatches<'static>) -> bool {
+    matches.is_present("aggregate")
+}
+
+fn transport_argument() -> Arg<'static, 'static> {
+    Arg::with_name("transport")
+        .short("t")
+        .long("transport")
+        .takes_value(true)
+        .possible_values(&["udp", "tcp", "unix"])
+        .default_value("udp")
+        .help("The transport the nodes use to communicate with each other.")
+}
+
+fn transport_from_matches(matches: &ArgMatches<'static>) -> String {
+    matches
+        .value_of("transport")
+        .expect("Transport arg not existing.")
+        .to_string()
+}
+
+fn authenticate_argument() -> Arg<'static, 'static> {
+    Arg::with_name("authenticate")
+        .short("a")
+        .long("authenticate")
+        .takes_value(false)
+        .help("Generate a key for each node in keys.txt, next to hosts.txt, and make the nodes authenticate their messages with them.")
+}
+
+fn authenticate_from_matches(matches: &ArgMatches<'static>) -> bool {
+    matches.is_present("authenticate")
+}
+
+fn encrypt_argument() -> Arg<'static, 'static> {
+    Arg::with_name("encrypt")
+        .short("e")
+        .long("encrypt")
+        .takes_value(false)
+        .help("Generate a key for the cluster in encryption_key.txt, next to hosts.txt, and make the nodes encrypt their messages with it.")
+}
+
+fn encrypt_from_matches(matches: &ArgMatches<'static>) -> bool {
+    matches.is_present("encrypt")
+}
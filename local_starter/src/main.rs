@@ -5,10 +5,13 @@ mod arguments;
 
 use std::fs;
 use std::path::Path;
-use std::process::Child;
+use std::process::{self, Child};
 use std::vec::Vec;
 
+use commons::aggregation;
 use commons::execution;
+use commons::history;
+use commons::linearizability;
 use commons::types::NodeId;
 
 use crate::arguments::ARGUMENTS;
@@ -16,14 +19,38 @@ use crate::arguments::ARGUMENTS;
 fn main() {
     check_write_read_soundness();
     create_hosts_file();
+    if ARGUMENTS.authenticate {
+        create_key_file();
+    }
+    if ARGUMENTS.encrypt {
+        create_encryption_key_file();
+    }
     build_application();
+    remove_old_result_files();
     run_application();
+
+    let linearizable =
+        !ARGUMENTS.check_linearizability || check_linearizability();
+    if ARGUMENTS.aggregate {
+        aggregate_run_results();
+    }
+    if !linearizable {
+        process::exit(1);
+    }
 }
 
 fn check_write_read_soundness() {
     if ARGUMENTS.should_write && ARGUMENTS.number_of_readers >= ARGUMENTS.number_of_nodes {
         panic!("If the writer node shall write, the number of readers must be less than the number of nodes in total. The writer cannot read and write at the same time.");
     }
+
+    if ARGUMENTS.check_linearizability && ARGUMENTS.run_length_string == "0" {
+        panic!("Linearizability can only be checked if the nodes exit by themselves, so the run length must be other than 0.");
+    }
+
+    if ARGUMENTS.aggregate && ARGUMENTS.run_length_string == "0" {
+        panic!("The result files can only be aggregated if the nodes exit by themselves, so the run length must be other than 0.");
+    }
 }
 
 fn create_hosts_file() {
@@ -49,16 +76,57 @@ fn hosts_file_string() -> String {
     let port_offset = 62000;
 
     for node_id in 1..ARGUMENTS.number_of_nodes + 1 {
-        string.push_str(&format!(
-            "{},127.0.0.1:{}\n",
-            node_id,
-            node_id + port_offset
-        ));
+        let address = match ARGUMENTS.transport.as_str() {
+            "unix" => format!("unix://node{:0>6}.sock", node_id),
+            transport => format!(
+                "{}://127.0.0.1:{}",
+                transport,
+                node_id + port_offset
+            ),
+        };
+        string.push_str(&format!("{},{}\n", node_id, address));
     }
 
     string
 }
 
+// New keys are generated for every run.
+fn create_key_file() {
+    let mut string = String::new();
+    for node_id in 1..ARGUMENTS.number_of_nodes + 1 {
+        string.push_str(&format!("{},{}\n", node_id, random_key_hex()));
+    }
+
+    fs::write("keys.txt", string)
+        .expect("Could not write the keys.txt file.");
+}
+
+fn create_encryption_key_file() {
+    fs::write("encryption_key.txt", format!("{}\n", random_key_hex()))
+        .expect("Could not write the encryption_key.txt file.");
+}
+
+fn random_key_hex() -> String {
+    let key: [u8; 32] = rand::random();
+    key.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn key_file_string() -> &'static str {
+    if ARGUMENTS.authenticate {
+        "--key-file keys.txt"
+    } else {
+        ""
+    }
+}
+
+fn encryption_key_file_string() -> &'static str {
+    if ARGUMENTS.encrypt {
+        "--encryption-key-file encryption_key.txt"
+    } else {
+        ""
+    }
+}
+
 fn build_application() {
     let command = format!(
         "cargo build {} --manifest-path ../application/Cargo.toml",
@@ -70,10 +138,40 @@ fn build_application() {
         .expect("Could not wait for the build process.");
 }
 
+// The files of an earlier run would otherwise be checked and
+// aggregated if a node doesn't write its own.
+fn remove_old_result_files() {
+    for node_id in 1..ARGUMENTS.number_of_nodes + 1 {
+        for file_name in &[
+            commons::arguments::run_result_file_name_from_node_id(node_id),
+            commons::arguments::history_file_name_from_node_id(node_id),
+        ] {
+            let file_path = Path::new(file_name);
+            if file_path.exists() {
+                fs::remove_file(file_path)
+                    .expect(&format!("Could not remove {}", file_name));
+            }
+        }
+    }
+}
+
 fn run_application() {
+    // All nodes count the partition schedule from the same time, so
+    // that each partition starts and ends at the same time at both of
+    // its sides.
+    let partition_start_string =
+        if ARGUMENTS.partition_file_string.is_empty() {
+            "".to_string()
+        } else {
+            format!("--partition-start {}", history::now_in_micros())
+        };
+
     let mut run_processes = Vec::new();
     for node_id in 1..ARGUMENTS.number_of_nodes + 1 {
-        let run_process = run_single_application_instance(node_id);
+        let run_process = run_single_application_instance(
+            node_id,
+            &partition_start_string,
+        );
         run_processes.push(run_process);
     }
 
@@ -84,7 +182,10 @@ fn run_application() {
     }
 }
 
-fn run_single_application_instance(node_id: NodeId) -> Child {
+fn run_single_application_instance(
+    node_id: NodeId,
+    partition_start_string: &str,
+) -> Child {
     let color = commons::arguments::color_from_node_id(node_id);
     let write_string = if node_id == ARGUMENTS.number_of_nodes && ARGUMENTS.should_write {
         "--write"
@@ -96,14 +197,75 @@ fn run_single_application_instance(node_id: NodeId) -> Child {
         false => "",
     };
 
-    let command = format!("cargo run {} --manifest-path ../application/Cargo.toml -- {} hosts.txt -c {:?} -l {} {} {} {}", 
+    let command = format!("cargo run {} --manifest-path ../application/Cargo.toml -- {} hosts.txt -c {:?} -l {} {} {} {} {} {} {} {} {} {} {} {}", 
         ARGUMENTS.release_mode_string,
         node_id,
         color,
         ARGUMENTS.run_length_string,
         ARGUMENTS.print_client_operations_string,
+        ARGUMENTS.record_latencies_string,
         write_string,
-        read_string);
+        read_string,
+        ARGUMENTS.partition_file_string,
+        partition_start_string,
+        ARGUMENTS.client_port_offset_string,
+        ARGUMENTS.admin_port_offset_string,
+        ARGUMENTS.metrics_port_offset_string,
+        key_file_string(),
+        encryption_key_file_string());
 
     execution::execute_local_command(&command)
 }
+
+// A missing or corrupt history file fails the check, since the
+// operations in it can't be checked.
+fn check_linearizability() -> bool {
+    let mut operations = Vec::new();
+    let mut unreadable_histories = Vec::new();
+    for node_id in 1..ARGUMENTS.number_of_nodes + 1 {
+        let file_name =
+            commons::arguments::history_file_name_from_node_id(node_id);
+        let node_operations = fs::read_to_string(&file_name)
+            .map_err(|error| error.to_string())
+            .and_then(|json| history::operations_from_json_lines(&json));
+        match node_operations {
+            Ok(node_operations) => operations.extend(node_operations),
+            Err(error) => unreadable_histories.push(format!(
+                "Node {}, {}: {}",
+                node_id, file_name, error
+            )),
+        }
+    }
+
+    if !unreadable_histories.is_empty() {
+        println!("The linearizability could not be checked. Could not read the histories of:");
+        for unreadable_history in unreadable_histories {
+            println!("{}", unreadable_history);
+        }
+        return false;
+    }
+
+    match linearizability::check(&operations) {
+        Ok(()) => {
+            println!(
+                "The run was linearizable. {} operations were checked.",
+                operations.len()
+            );
+            true
+        }
+        Err(violation) => {
+            println!("The run was not linearizable. {}", violation);
+            false
+        }
+    }
+}
+
+fn aggregate_run_results() {
+    let mut run_results =
+        aggregation::run_results_from_directory(Path::new("."));
+    // Result files of an earlier run with more nodes.
+    run_results.retain(|&node_id, _| node_id <= ARGUMENTS.number_of_nodes);
+    let aggregate =
+        aggregation::aggregate(&run_results, ARGUMENTS.number_of_nodes);
+    print!("{}", aggregate.to_table());
+}
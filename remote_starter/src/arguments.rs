@@ -18,8 +18,10 @@ pub struct Arguments {
     pub number_of_readers: Int,
     pub release_mode_string: String,
     pub print_client_operations_string: String,
+    pub record_latencies_string: String,
     pub run_length_string: String,
     pub install: bool,
+    pub metrics_port_offset_string: String,
 }
 
 impl Arguments {
@@ -41,10 +43,14 @@ impl Arguments {
                 arguments::print_client_operations_string_from_matches(
                     &matches,
                 ),
+            record_latencies_string:
+                arguments::record_latencies_string_from_matches(&matches),
             run_length_string: arguments::run_length_string_from_matches(
                 &matches,
             ),
             install: install_from_matches(&matches),
+            metrics_port_offset_string:
+                arguments::metrics_port_offset_string_from_matches(&matches),
         }
     }
 
@@ -80,6 +86,8 @@ fn get_matches() -> ArgMatches<'static> {
         .arg(arguments::optimize())
         .arg(install_argument())
         .arg(arguments::print_client_operations())
+        .arg(arguments::record_latencies())
+        .arg(arguments::metrics_port_offset())
         .get_matches()
 }
 
@@ -6,12 +6,16 @@ extern crate lazy_static;
 mod arguments;
 
 use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::{self, Child};
 use std::thread;
 use std::vec::Vec;
 
+use chrono::Local;
 use ctrlc;
 
+use commons::aggregation;
 use commons::execution;
 use commons::node_info::NodeInfo;
 use commons::remote_machine::*;
@@ -30,6 +34,14 @@ fn main() {
         build_source_code();
     } else {
         run_application_on_remote_computers();
+
+        // The nodes have exited by themselves only if they had a run
+        // length, otherwise this program was stopped with Ctrl-C.
+        if ARGUMENTS.run_length_string != "0" {
+            let results_directory = create_results_directory();
+            download_result_files(&results_directory);
+            aggregate_result_files(&results_directory);
+        }
     }
 }
 
@@ -254,18 +266,92 @@ fn run_application_on_remote_computer(node_info: &NodeInfo) -> Child {
             false => "",
         };
 
+    // The result files of an earlier run are removed, so that they
+    // aren't downloaded if the node fails this time.
     let command_string = format!(
-        "\"cd {}/application/;cargo run {} -- {} {} -l {} -c {:?} {} {} {};cd ../../\"",
+        "\"cd {}/application/;rm -f {} {};cargo run {} -- {} {} -l {} -c {:?} {} {} {} {} {};cd ../../\"",
         REMOTE_DIRECTORY_NAME,
+        commons::arguments::run_result_file_name_from_node_id(node_info.node_id),
+        commons::arguments::history_file_name_from_node_id(node_info.node_id),
         ARGUMENTS.release_mode_string,
         node_info.node_id,
         REMOTE_HOSTS_FILE_NAME,
         ARGUMENTS.run_length_string,
         commons::arguments::color_from_node_id(node_info.node_id),
         ARGUMENTS.print_client_operations_string,
+        ARGUMENTS.record_latencies_string,
         write_string,
-        read_string
+        read_string,
+        ARGUMENTS.metrics_port_offset_string
     );
 
     execution::execute_remote_command(&command_string, &node_info)
 }
+
+fn create_results_directory() -> PathBuf {
+    let results_directory = Path::new("results")
+        .join(Local::now().format("%Y-%m-%d_%H-%M-%S").to_string());
+    fs::create_dir_all(&results_directory)
+        .expect("Could not create the results directory.");
+    results_directory
+}
+
+// Several nodes can run on the same remote computer, in the same
+// directory, so the files are downloaded for each node.
+fn download_result_files(results_directory: &Path) {
+    let destination_path = results_directory
+        .to_str()
+        .expect("The results directory is not valid unicode.");
+    let mut processes = Vec::new();
+
+    for node_info in ARGUMENTS.node_infos.iter() {
+        for file_name in &[
+            commons::arguments::run_result_file_name_from_node_id(
+                node_info.node_id,
+            ),
+            commons::arguments::history_file_name_from_node_id(
+                node_info.node_id,
+            ),
+        ] {
+            let process =
+                execution::scp_copy_of_remote_source_path_to_local_destination_path(
+                    &format!("application/{}", file_name),
+                    destination_path,
+                    node_info,
+                );
+            processes.push((
+                node_info.node_id,
+                file_name.clone(),
+                process,
+            ));
+        }
+    }
+
+    // A result file that couldn't be downloaded is missing from the
+    // aggregate, so the run is reported as unsound.
+    for (node_id, file_name, process) in processes.iter_mut() {
+        let exit_status = process
+            .wait()
+            .expect("Could not wait for a result file download.");
+        if !exit_status.success() {
+            eprintln!(
+                "Could not download {} of node {}: scp exited with {}.",
+                file_name, node_id, exit_status
+            );
+        }
+    }
+}
+
+fn aggregate_result_files(results_directory: &Path) {
+    let run_results =
+        aggregation::run_results_from_directory(results_directory);
+    let aggregate =
+        aggregation::aggregate(&run_results, ARGUMENTS.number_of_nodes());
+
+    println!("The result files are in {}", results_directory.display());
+    print!("{}", aggregate.to_table());
+
+    if !aggregate.is_sound() {
+        process::exit(1);
+    }
+}